@@ -11,6 +11,13 @@ fi
 # Generate plots
 echo "Generating plots..."
 gnuplot report/images/plot_loss.gp
+gnuplot report/images/plot_ber.gp
+gnuplot report/images/plot_burst.gp
+gnuplot report/images/plot_loss_2d.gp
+gnuplot report/images/plot_nak.gp
+gnuplot report/images/plot_fast_retransmit.gp
 gnuplot report/images/plot_window.gp
+gnuplot report/images/plot_rto.gp
+gnuplot report/images/plot_cwnd.gp
 
 echo "Plots generated in report/images/"
@@ -0,0 +1,12 @@
+set terminal png size 800,600
+set output 'report/images/efficiency_vs_ber.png'
+set title 'Protocol Efficiency vs Bit Error Rate (Window Size = 5)'
+set xlabel 'Bit Error Rate'
+set ylabel 'Efficiency Coefficient'
+set format x '%.0e'
+set grid
+set key outside
+plot 'report/data/saw_vs_ber.dat' using 1:2 with linespoints title 'Stop-and-Wait', \
+     'report/data/gbn_vs_ber.dat' using 1:2 with linespoints title 'Go-Back-N', \
+     'report/data/sr_vs_ber.dat' using 1:2 with linespoints title 'Selective Repeat', \
+     'report/data/sack_vs_ber.dat' using 1:2 with linespoints title 'SACK'
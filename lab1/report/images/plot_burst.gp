@@ -0,0 +1,11 @@
+set terminal png size 800,600
+set output 'report/images/efficiency_vs_burst.png'
+set title 'Protocol Efficiency vs Mean Burst Length (Window Size = 5, Loss Rate = 0.2)'
+set xlabel 'Mean Burst Length (packets)'
+set ylabel 'Efficiency Coefficient'
+set grid
+set key outside
+plot 'report/data/saw_vs_burst.dat' using 1:2 with linespoints title 'Stop-and-Wait', \
+     'report/data/gbn_vs_burst.dat' using 1:2 with linespoints title 'Go-Back-N', \
+     'report/data/sr_vs_burst.dat' using 1:2 with linespoints title 'Selective Repeat', \
+     'report/data/sack_vs_burst.dat' using 1:2 with linespoints title 'SACK'
@@ -0,0 +1,13 @@
+set terminal png size 800,600
+set output 'report/images/cwnd_dynamics.png'
+set title 'Congestion Window over a Transfer (Window Size = 10, Loss Rate = 0.1)'
+set xlabel 'Time (ms)'
+set ylabel 'cwnd (packets)'
+set grid
+set key outside
+plot 'report/data/gbn_reno_cwnd.dat' using 1:2 with steps title 'Go-Back-N, Reno', \
+     'report/data/gbn_cubic_cwnd.dat' using 1:2 with steps title 'Go-Back-N, CUBIC', \
+     'report/data/sr_reno_cwnd.dat' using 1:2 with steps title 'Selective Repeat, Reno', \
+     'report/data/sr_cubic_cwnd.dat' using 1:2 with steps title 'Selective Repeat, CUBIC', \
+     'report/data/sack_reno_cwnd.dat' using 1:2 with steps title 'SACK, Reno', \
+     'report/data/sack_cubic_cwnd.dat' using 1:2 with steps title 'SACK, CUBIC'
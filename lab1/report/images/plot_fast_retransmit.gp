@@ -0,0 +1,9 @@
+set terminal png size 800,600
+set output 'report/images/completion_time_vs_loss_fast_retransmit.png'
+set title 'Go-Back-N Completion Time with and without Fast Retransmit (Window Size = 5, Delay = 10 ms)'
+set xlabel 'Loss Rate'
+set ylabel 'Completion Time (ms)'
+set grid
+set key outside
+plot 'report/data/gbn_fast_retransmit.dat' using 1:4 with linespoints title 'Timeout only', \
+     'report/data/gbn_fast_retransmit.dat' using 1:5 with linespoints title 'Fast retransmit (3 duplicate ACKs)'
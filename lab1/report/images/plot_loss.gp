@@ -5,5 +5,7 @@ set xlabel 'Loss Rate'
 set ylabel 'Efficiency Coefficient'
 set grid
 set key outside
-plot 'report/data/gbn_vs_loss.dat' using 1:2 with linespoints title 'Go-Back-N', \
-     'report/data/sr_vs_loss.dat' using 1:2 with linespoints title 'Selective Repeat'
+plot 'report/data/saw_vs_loss.dat' using 1:2 with linespoints title 'Stop-and-Wait', \
+     'report/data/gbn_vs_loss.dat' using 1:2 with linespoints title 'Go-Back-N', \
+     'report/data/sr_vs_loss.dat' using 1:2 with linespoints title 'Selective Repeat', \
+     'report/data/sack_vs_loss.dat' using 1:2 with linespoints title 'SACK'
@@ -0,0 +1,13 @@
+set terminal png size 800,600
+set output 'report/images/efficiency_vs_loss_2d.png'
+set title 'Protocol Efficiency vs Data and ACK Loss Rates (Window Size = 5)'
+set xlabel 'Data Loss Rate'
+set ylabel 'ACK Loss Rate'
+set zlabel 'Efficiency Coefficient' rotate parallel
+set grid
+set key outside
+set hidden3d
+set view 60, 30
+splot 'report/data/gbn_vs_loss_2d.dat' using 1:2:3 with lines title 'Go-Back-N', \
+      'report/data/sr_vs_loss_2d.dat' using 1:2:3 with lines title 'Selective Repeat', \
+      'report/data/sack_vs_loss_2d.dat' using 1:2:3 with lines title 'SACK'
@@ -0,0 +1,19 @@
+set terminal png size 800,600
+set output 'report/images/efficiency_vs_loss_nak.png'
+set title 'Protocol Efficiency with and without NAKs (Window Size = 5, Delay = 10 ms)'
+set xlabel 'Loss Rate'
+set ylabel 'Efficiency Coefficient'
+set grid
+set key outside
+plot 'report/data/gbn_nak.dat' using 1:2 with linespoints title 'Go-Back-N', \
+     'report/data/gbn_nak.dat' using 1:3 with linespoints title 'Go-Back-N + NAK', \
+     'report/data/sr_nak.dat' using 1:2 with linespoints title 'Selective Repeat', \
+     'report/data/sr_nak.dat' using 1:3 with linespoints title 'Selective Repeat + NAK'
+
+set output 'report/images/completion_time_vs_loss_nak.png'
+set title 'Completion Time with and without NAKs (Window Size = 5, Delay = 10 ms)'
+set ylabel 'Completion Time (ms)'
+plot 'report/data/gbn_nak.dat' using 1:4 with linespoints title 'Go-Back-N', \
+     'report/data/gbn_nak.dat' using 1:5 with linespoints title 'Go-Back-N + NAK', \
+     'report/data/sr_nak.dat' using 1:4 with linespoints title 'Selective Repeat', \
+     'report/data/sr_nak.dat' using 1:5 with linespoints title 'Selective Repeat + NAK'
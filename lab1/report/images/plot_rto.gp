@@ -0,0 +1,11 @@
+set terminal png size 800,600
+set output 'report/images/rto_convergence.png'
+set title 'Retransmission Timeout over a Transfer (Window Size = 5, Loss Rate = 0.1)'
+set xlabel 'Time (ms)'
+set ylabel 'RTO (ms)'
+set grid
+set key outside
+plot 'report/data/saw_rto.dat' using 1:2 with steps title 'Stop-and-Wait', \
+     'report/data/gbn_rto.dat' using 1:2 with steps title 'Go-Back-N', \
+     'report/data/sr_rto.dat' using 1:2 with steps title 'Selective Repeat', \
+     'report/data/sack_rto.dat' using 1:2 with steps title 'SACK'
@@ -6,4 +6,5 @@ set ylabel 'Efficiency Coefficient'
 set grid
 set key outside
 plot 'report/data/gbn_vs_window.dat' using 1:2 with linespoints title 'Go-Back-N', \
-     'report/data/sr_vs_window.dat' using 1:2 with linespoints title 'Selective Repeat'
+     'report/data/sr_vs_window.dat' using 1:2 with linespoints title 'Selective Repeat', \
+     'report/data/sack_vs_window.dat' using 1:2 with linespoints title 'SACK'
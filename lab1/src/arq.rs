@@ -0,0 +1,925 @@
+use std::{
+    io::{Read, Write},
+    thread,
+    time::{Duration, Instant},
+};
+
+#[cfg(feature = "async")]
+use crate::async_driver::{AsyncReaderDriver, AsyncSenderDriver, AsyncTransport};
+use crate::{
+    congestion::{CongestionAlgorithm, CongestionWindow},
+    driver::{ReaderDriver, SenderDriver},
+    duplex::{self, Duplex, DuplexMachine, Segment},
+    error::ArqError,
+    gobackn,
+    impairment::Impairments,
+    machine::{ArqReceiverMachine, ArqSenderMachine},
+    packet::{Ack, AckNumber, Packet},
+    rto::RtoEstimator,
+    sack, selective_repeat,
+    seq::SequenceSpace,
+    sim,
+    stats::TransferStats,
+    stream::{StreamReader, StreamWriter},
+    transport::{self, Meter, Metered, Transport},
+};
+
+pub(crate) const TIMEOUT: Duration = Duration::from_millis(200);
+/// How long a transfer may go without progress before it is given up.
+pub(crate) const TIMEOUT_TOTAL: Duration = Duration::from_secs(30);
+
+/// Statistics kept by the sending half of an ARQ protocol.
+pub trait SenderStats {
+    /// Ratio of distinct packets to packets put on the wire by the last `send`.
+    fn efficiency_coefficient(&self) -> f64;
+
+    /// Data packets the last `send` put on the wire more than once.
+    fn retransmissions(&self) -> usize;
+
+    /// Retransmission timeout estimator of the current connection. Its
+    /// history shows how the RTO converges over the transfer.
+    fn rto(&self) -> &RtoEstimator;
+
+    /// Congestion window of the current connection, `None` for protocols
+    /// without a sliding window.
+    fn congestion(&self) -> Option<&CongestionWindow> {
+        None
+    }
+}
+
+/// Statistics kept by the receiving half of an ARQ protocol over its
+/// whole lifetime.
+pub trait ReaderStats {
+    /// Packets that reached the reader, corrupted and control ones included.
+    fn packets_read(&self) -> usize;
+
+    /// Data packets that arrived again after the reader already had them,
+    /// because an ACK was lost or late.
+    fn duplicates(&self) -> usize;
+}
+
+/// Sending half of an ARQ protocol.
+pub trait ArqSender: SenderStats {
+    /// Opens a connection with a SYN/SYN-ACK handshake. Sequence numbers,
+    /// the RTO and the congestion window carry over between the messages
+    /// of a connection.
+    fn connect(&mut self) -> Result<(), ArqError>;
+
+    /// Reliably transfers everything `source` yields until EOF to the peer
+    /// as one message, blocking until every packet is acknowledged. Only the
+    /// current window is held in memory. Connects first if needed and fails
+    /// once no ACK advanced the window for `TIMEOUT_TOTAL`.
+    fn send_stream(&mut self, source: &mut dyn Read) -> Result<(), ArqError>;
+
+    /// Reliably transfers an arbitrary binary `message`.
+    fn send_bytes(&mut self, mut message: &[u8]) -> Result<(), ArqError> {
+        self.send_stream(&mut message)
+    }
+
+    /// Reliably transfers a text `message`.
+    fn send(&mut self, message: &str) -> Result<(), ArqError> {
+        self.send_bytes(message.as_bytes())
+    }
+
+    /// Closes the connection with a FIN/FIN-ACK exchange.
+    fn close(&mut self) -> Result<(), ArqError>;
+}
+
+/// Receiving half of an ARQ protocol.
+pub trait ArqReceiver: ReaderStats {
+    /// Blocks until a whole message is received, writing it to `sink` as
+    /// packets are delivered in order. Returns the number of bytes written
+    /// as soon as the last packet is in. Accepts a connection first if
+    /// needed and fails once the sender closed it or no packet was
+    /// delivered for `TIMEOUT_TOTAL`.
+    fn read_stream(&mut self, sink: &mut dyn Write) -> Result<u64, ArqError>;
+
+    /// Blocks until a whole binary message is received.
+    fn read_bytes(&mut self) -> Result<Vec<u8>, ArqError> {
+        let mut data = Vec::new();
+        self.read_stream(&mut data)?;
+        Ok(data)
+    }
+
+    /// Blocks until a whole message is received. Fails unless the message
+    /// is valid UTF-8.
+    fn read(&mut self) -> Result<String, ArqError> {
+        Ok(String::from_utf8(self.read_bytes()?)?)
+    }
+
+    /// Answers retransmissions of the last message until the sender's FIN,
+    /// then acknowledges it.
+    fn close(&mut self) -> Result<(), ArqError>;
+}
+
+pub type BoxedSender = Box<dyn ArqSender + Send>;
+pub type BoxedReceiver = Box<dyn ArqReceiver + Send>;
+pub type BoxedSenderMachine = Box<dyn ArqSenderMachine>;
+pub type BoxedReceiverMachine = Box<dyn ArqReceiverMachine>;
+#[cfg(feature = "async")]
+pub type BoxedAsyncSender<T> = AsyncSenderDriver<dyn ArqSenderMachine, T>;
+#[cfg(feature = "async")]
+pub type BoxedAsyncReceiver<T> = AsyncReaderDriver<dyn ArqReceiverMachine, T>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Protocol {
+    StopAndWait,
+    GoBackN,
+    SelectiveRepeat,
+    /// Cumulative ACKs with SACK blocks, only the holes are resent.
+    Sack,
+}
+
+impl Protocol {
+    pub const ALL: [Self; 4] = [
+        Self::StopAndWait,
+        Self::GoBackN,
+        Self::SelectiveRepeat,
+        Self::Sack,
+    ];
+
+    #[must_use]
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::StopAndWait => "Stop-and-Wait",
+            Self::GoBackN => "Go-Back-N",
+            Self::SelectiveRepeat => "Selective Repeat",
+            Self::Sack => "SACK",
+        }
+    }
+
+    /// Identifier used for data file names, e.g. `gbn_vs_loss.dat`.
+    #[must_use]
+    pub fn short_name(self) -> &'static str {
+        match self {
+            Self::StopAndWait => "saw",
+            Self::GoBackN => "gbn",
+            Self::SelectiveRepeat => "sr",
+            Self::Sack => "sack",
+        }
+    }
+
+    /// Protocol with the given `short_name`.
+    #[must_use]
+    pub fn from_short_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|p| p.short_name() == name)
+    }
+
+    /// Whether the protocol has a configurable window. Stop-and-Wait always
+    /// keeps a single packet in flight and ignores `window_size`.
+    #[must_use]
+    pub fn is_windowed(self) -> bool {
+        !matches!(self, Self::StopAndWait)
+    }
+
+    /// Largest window that still lets the receiver tell new packets from
+    /// retransmissions in `space`.
+    #[must_use]
+    pub fn max_window(self, space: SequenceSpace) -> u64 {
+        match self {
+            Self::StopAndWait => 1,
+            Self::GoBackN => space.max_window_gbn(),
+            Self::SelectiveRepeat | Self::Sack => space.max_window_sr(),
+        }
+    }
+
+    /// Builds a connected sender/receiver pair on top of the two ends of a
+    /// link, such as the ones `transport::channel` returns. Fails if the
+    /// window does not fit into the configured sequence space.
+    pub fn endpoints(
+        self,
+        sender_transport: impl Transport<Packet, Ack> + Send + 'static,
+        reader_transport: impl Transport<Ack, Packet> + Send + 'static,
+        config: &ArqConfig,
+    ) -> Result<(BoxedSender, BoxedReceiver), ArqError> {
+        Ok((
+            self.sender(sender_transport, config)?,
+            self.reader(reader_transport, config)?,
+        ))
+    }
+
+    /// Builds the sending half alone, for a reader in another process.
+    pub fn sender(
+        self,
+        transport: impl Transport<Packet, Ack> + Send + 'static,
+        config: &ArqConfig,
+    ) -> Result<BoxedSender, ArqError> {
+        let machine = self.sender_machine(config)?;
+        Ok(Box::new(SenderDriver::<dyn ArqSenderMachine, _>::new(
+            transport, machine,
+        )))
+    }
+
+    /// Builds the receiving half alone, for a sender in another process.
+    pub fn reader(
+        self,
+        transport: impl Transport<Ack, Packet> + Send + 'static,
+        config: &ArqConfig,
+    ) -> Result<BoxedReceiver, ArqError> {
+        let machine = self.reader_machine(config)?;
+        Ok(Box::new(ReaderDriver::<dyn ArqReceiverMachine, _>::new(
+            transport, machine,
+        )))
+    }
+
+    /// Builds one end of a duplex session, where both ends send and read
+    /// over the same `transport` and ACKs ride on the data going the other
+    /// way. Fails if the window does not fit into the configured sequence space.
+    pub fn duplex<T: Transport<Segment, Segment>>(
+        self,
+        transport: T,
+        config: &ArqConfig,
+    ) -> Result<Duplex<T>, ArqError> {
+        let machine =
+            DuplexMachine::new(self.sender_machine(config)?, self.reader_machine(config)?)
+                .with_ack_delay(config.ack_delay);
+        Ok(Duplex::new(transport, machine))
+    }
+
+    /// Same as `endpoints`, but for a tokio runtime, see `async_driver`.
+    #[cfg(feature = "async")]
+    pub fn async_endpoints<S, R>(
+        self,
+        sender_transport: S,
+        reader_transport: R,
+        config: &ArqConfig,
+    ) -> Result<(BoxedAsyncSender<S>, BoxedAsyncReceiver<R>), ArqError>
+    where
+        S: AsyncTransport<Packet, Ack>,
+        R: AsyncTransport<Ack, Packet>,
+    {
+        Ok((
+            AsyncSenderDriver::new(sender_transport, self.sender_machine(config)?),
+            AsyncReaderDriver::new(reader_transport, self.reader_machine(config)?),
+        ))
+    }
+
+    /// Builds a sender/receiver pair of state machines without I/O, for a
+    /// driver with its own transport and clock. Fails if the window does not
+    /// fit into the configured sequence space.
+    pub fn machines(
+        self,
+        config: &ArqConfig,
+    ) -> Result<(BoxedSenderMachine, BoxedReceiverMachine), ArqError> {
+        Ok((self.sender_machine(config)?, self.reader_machine(config)?))
+    }
+
+    pub fn sender_machine(self, config: &ArqConfig) -> Result<BoxedSenderMachine, ArqError> {
+        let ArqConfig {
+            window_size,
+            congestion,
+            sequence_bits,
+            fast_retransmit,
+            is_debug,
+            ..
+        } = *config;
+        Ok(match self {
+            Self::StopAndWait => Box::new(gobackn::SenderMachine::stop_and_wait(is_debug)),
+            Self::GoBackN => Box::new(
+                gobackn::SenderMachine::new(window_size, is_debug)
+                    .with_congestion_control(congestion.build())
+                    .with_fast_retransmit(fast_retransmit)
+                    .with_sequence_bits(sequence_bits)?,
+            ),
+            Self::SelectiveRepeat => Box::new(
+                selective_repeat::SenderMachine::new(window_size, is_debug)
+                    .with_congestion_control(congestion.build())
+                    .with_sequence_bits(sequence_bits)?,
+            ),
+            Self::Sack => Box::new(
+                sack::SenderMachine::new(window_size, is_debug)
+                    .with_congestion_control(congestion.build())
+                    .with_sequence_bits(sequence_bits)?,
+            ),
+        })
+    }
+
+    pub fn reader_machine(self, config: &ArqConfig) -> Result<BoxedReceiverMachine, ArqError> {
+        let ArqConfig {
+            window_size,
+            sequence_bits,
+            is_nak_enabled,
+            sack_blocks,
+            is_debug,
+            ..
+        } = *config;
+        Ok(match self {
+            Self::StopAndWait => Box::new(gobackn::ReaderMachine::stop_and_wait(is_debug)),
+            Self::GoBackN => Box::new(
+                gobackn::ReaderMachine::new(is_debug)
+                    .with_naks(is_nak_enabled)
+                    .with_sequence_bits(sequence_bits)?,
+            ),
+            Self::SelectiveRepeat => Box::new(
+                selective_repeat::ReaderMachine::new(window_size, is_debug)
+                    .with_naks(is_nak_enabled)
+                    .with_sequence_bits(sequence_bits)?,
+            ),
+            Self::Sack => Box::new(
+                sack::ReaderMachine::new(window_size, is_debug)
+                    .with_sack_blocks(sack_blocks)?
+                    .with_sequence_bits(sequence_bits)?,
+            ),
+        })
+    }
+
+    /// Same as `endpoints`, but wraps the pair into `std::io` streams.
+    pub fn stream_endpoints(
+        self,
+        sender_transport: impl Transport<Packet, Ack> + Send + 'static,
+        reader_transport: impl Transport<Ack, Packet> + Send + 'static,
+        config: &ArqConfig,
+    ) -> Result<(StreamWriter, StreamReader), ArqError> {
+        let (sender, reader) = self.endpoints(sender_transport, reader_transport, config)?;
+        Ok((StreamWriter::new(sender), StreamReader::new(reader)))
+    }
+
+    /// Transfers `message` over `mpsc` channels with debug output.
+    #[must_use]
+    pub fn setup(self, window_size: AckNumber, message: &str) -> (String, TransferStats) {
+        let config = ArqConfig::new(window_size).with_debug(true);
+        let (sender_transport, reader_transport) = transport::channel();
+        let (message_read, stats) =
+            self.metered_transfer(sender_transport, reader_transport, &config, message);
+        (message_read.unwrap(), stats)
+    }
+
+    /// Quiet transfer with independent `loss`. The same `seed` always drops
+    /// the same packets and ACKs.
+    #[must_use]
+    pub fn silent_setup_loss(
+        self,
+        window_size: AckNumber,
+        message: &str,
+        loss: f64,
+        seed: Option<u64>,
+    ) -> (String, TransferStats) {
+        let seed = seed.unwrap_or_else(rand::random);
+        let (sender_transport, reader_transport) =
+            transport::simulated_link(&Impairments::loss(loss).with_seed(seed));
+        let (message_read, stats) = self.metered_transfer(
+            sender_transport,
+            reader_transport,
+            &ArqConfig::new(window_size),
+            message,
+        );
+        let message_read = message_read.unwrap_or_else(|e| {
+            eprintln!("Reader warning: {e} (seed {seed})");
+            String::new()
+        });
+        (message_read, stats)
+    }
+
+    /// Runs a transfer over transports that count what goes through them.
+    fn metered_transfer(
+        self,
+        sender_transport: impl Transport<Packet, Ack> + Send + 'static,
+        reader_transport: impl Transport<Ack, Packet> + Send + 'static,
+        config: &ArqConfig,
+        message: &str,
+    ) -> (Result<String, ArqError>, TransferStats) {
+        let sender_transport = Metered::new(sender_transport);
+        let reader_transport = Metered::new(reader_transport);
+        let (sender_link, reader_link) = (sender_transport.meter(), reader_transport.meter());
+        let (mut sender, mut reader) = self
+            .endpoints(sender_transport, reader_transport, config)
+            .unwrap();
+        let mut data = Vec::new();
+        let completion_time = transfer_stream_timed(
+            sender.as_mut(),
+            reader.as_mut(),
+            &mut message.as_bytes(),
+            &mut data,
+        )
+        .map(|(_, completion_time)| completion_time);
+        let stats = TransferStats::collect(
+            sender.as_ref(),
+            reader.as_ref(),
+            &sender_link,
+            &reader_link,
+            data.len(),
+            completion_time.as_ref().copied().unwrap_or_default(),
+        );
+        let message_read = completion_time.and_then(|_| Ok(String::from_utf8(data)?));
+        (message_read, stats)
+    }
+
+    /// Same as `silent_setup_loss`, but takes a full `config` and lets
+    /// `inspect` look at the sender's statistics once the transfer is over.
+    pub fn silent_setup_loss_with<T>(
+        self,
+        config: &ArqConfig,
+        message: &str,
+        loss: f64,
+        seed: Option<u64>,
+        inspect: impl FnOnce(&dyn SenderStats) -> T,
+    ) -> (Result<String, ArqError>, T) {
+        let mut impairments = Impairments::loss(loss);
+        impairments.seed = seed;
+        self.silent_setup_with(config, message, &impairments, inspect)
+    }
+
+    /// Runs a quiet transfer over a simulated link with the given
+    /// `impairments`, see `transport::simulated_link`. A failed transfer is
+    /// logged with the seed that replays its drops before its error is
+    /// returned.
+    pub fn silent_setup_with<T>(
+        self,
+        config: &ArqConfig,
+        message: &str,
+        impairments: &Impairments,
+        inspect: impl FnOnce(&dyn SenderStats) -> T,
+    ) -> (Result<String, ArqError>, T) {
+        let seed = impairments.seed.unwrap_or_else(rand::random);
+        let (sender_transport, reader_transport) =
+            transport::simulated_link(&impairments.with_seed(seed));
+        let (mut sender, mut reader) = self
+            .endpoints(sender_transport, reader_transport, config)
+            .unwrap();
+        let message_read = transfer(sender.as_mut(), reader.as_mut(), message).inspect_err(|e| {
+            eprintln!("Reader warning: {e} (seed {seed})");
+        });
+        (message_read, inspect(sender.as_ref()))
+    }
+
+    /// Same as `silent_setup_loss`, but runs a discrete-event simulation on
+    /// a virtual clock, see `sim::transfer`.
+    #[must_use]
+    pub fn simulate_loss(
+        self,
+        window_size: AckNumber,
+        message: &str,
+        loss: f64,
+        seed: Option<u64>,
+    ) -> (String, TransferStats) {
+        let seed = seed.unwrap_or_else(rand::random);
+        let (mut sender, mut reader) = self.machines(&ArqConfig::new(window_size)).unwrap();
+        let (sender_link, reader_link) = (Meter::default(), Meter::default());
+        let (message_read, completion_time) = sim::metered_transfer(
+            sender.as_mut(),
+            reader.as_mut(),
+            message.as_bytes(),
+            &Impairments::loss(loss).with_seed(seed),
+            &sender_link,
+            &reader_link,
+        )
+        .and_then(|(data, completion_time)| Ok((String::from_utf8(data)?, completion_time)))
+        .unwrap_or_else(|e| {
+            eprintln!("Reader warning: {e} (seed {seed})");
+            (String::new(), Duration::ZERO)
+        });
+        let stats = TransferStats::collect(
+            sender.as_ref(),
+            reader.as_ref(),
+            &sender_link,
+            &reader_link,
+            message_read.len(),
+            completion_time,
+        );
+        (message_read, stats)
+    }
+
+    /// Same as `silent_setup_with`, but runs a discrete-event simulation on
+    /// a virtual clock. Takes milliseconds instead of seconds and the same
+    /// seed gives exactly the same transfer.
+    pub fn simulate_with<T>(
+        self,
+        config: &ArqConfig,
+        message: &str,
+        impairments: &Impairments,
+        inspect: impl FnOnce(&dyn SenderStats) -> T,
+    ) -> (String, T) {
+        let seed = impairments.seed.unwrap_or_else(rand::random);
+        let (mut sender, mut reader) = self.machines(config).unwrap();
+        let message_read = sim::transfer(
+            sender.as_mut(),
+            reader.as_mut(),
+            message.as_bytes(),
+            &impairments.with_seed(seed),
+        )
+        .and_then(|(data, _)| Ok(String::from_utf8(data)?))
+        .unwrap_or_else(|e| {
+            eprintln!("Reader warning: {e} (seed {seed})");
+            String::new()
+        });
+        (message_read, inspect(sender.as_ref()))
+    }
+}
+
+/// Endpoint parameters shared by all protocols. Protocols ignore the
+/// options that do not apply to them.
+#[derive(Debug, Clone, Copy)]
+pub struct ArqConfig {
+    pub window_size: AckNumber,
+    pub congestion: CongestionAlgorithm,
+    /// Width `k` of sequence numbers on the wire, numbers wrap modulo `2^k`.
+    pub sequence_bits: u32,
+    /// Whether readers ask for missing packets with NAKs instead of leaving
+    /// the sender to find out on timeout. Only Go-Back-N and Selective
+    /// Repeat send NAKs, SACK blocks already point at the holes.
+    pub is_nak_enabled: bool,
+    /// Duplicate ACKs after which Go-Back-N sends its window again without
+    /// waiting for the timer, `None` to always wait. The other protocols
+    /// ignore it.
+    pub fast_retransmit: Option<usize>,
+    /// Most SACK blocks per ACK of the SACK reader, up to
+    /// `packet::MAX_SACK_BLOCKS`.
+    pub sack_blocks: usize,
+    /// How long a duplex session holds an ACK back for outgoing data to
+    /// ride on, see `duplex::DuplexMachine`. Longer than `rto::RTO_MIN`,
+    /// the peer may retransmit before the ACK goes out.
+    pub ack_delay: Duration,
+    pub is_debug: bool,
+}
+
+impl ArqConfig {
+    #[must_use]
+    pub fn new(window_size: AckNumber) -> Self {
+        Self {
+            window_size,
+            congestion: CongestionAlgorithm::default(),
+            sequence_bits: AckNumber::BITS,
+            is_nak_enabled: false,
+            fast_retransmit: None,
+            sack_blocks: sack::SACK_BLOCKS,
+            ack_delay: duplex::ACK_DELAY,
+            is_debug: false,
+        }
+    }
+
+    #[must_use]
+    pub fn with_congestion(mut self, congestion: CongestionAlgorithm) -> Self {
+        self.congestion = congestion;
+        self
+    }
+
+    #[must_use]
+    pub fn with_sequence_bits(mut self, sequence_bits: u32) -> Self {
+        self.sequence_bits = sequence_bits;
+        self
+    }
+
+    #[must_use]
+    pub fn with_naks(mut self, is_nak_enabled: bool) -> Self {
+        self.is_nak_enabled = is_nak_enabled;
+        self
+    }
+
+    #[must_use]
+    pub fn with_fast_retransmit(mut self, duplicate_acks: Option<usize>) -> Self {
+        self.fast_retransmit = duplicate_acks;
+        self
+    }
+
+    #[must_use]
+    pub fn with_sack_blocks(mut self, sack_blocks: usize) -> Self {
+        self.sack_blocks = sack_blocks;
+        self
+    }
+
+    #[must_use]
+    pub fn with_ack_delay(mut self, ack_delay: Duration) -> Self {
+        self.ack_delay = ack_delay;
+        self
+    }
+
+    #[must_use]
+    pub fn with_debug(mut self, is_debug: bool) -> Self {
+        self.is_debug = is_debug;
+        self
+    }
+}
+
+/// Runs `sender` on a scoped thread and reads the message on the current one.
+pub fn transfer<S, R>(sender: &mut S, reader: &mut R, message: &str) -> Result<String, ArqError>
+where
+    S: ArqSender + Send + ?Sized,
+    R: ArqReceiver + ?Sized,
+{
+    let data = transfer_bytes(sender, reader, message.as_bytes())?;
+    Ok(String::from_utf8(data)?)
+}
+
+/// Binary counterpart of `transfer`.
+pub fn transfer_bytes<S, R>(
+    sender: &mut S,
+    reader: &mut R,
+    message: &[u8],
+) -> Result<Vec<u8>, ArqError>
+where
+    S: ArqSender + Send + ?Sized,
+    R: ArqReceiver + ?Sized,
+{
+    let mut data = Vec::new();
+    transfer_stream(sender, reader, &mut &message[..], &mut data)?;
+    Ok(data)
+}
+
+/// Streams `source` through `sender` on a scoped thread into `sink` on the
+/// current one, then closes the connection. Returns the number of bytes
+/// written to `sink`.
+pub fn transfer_stream<S, R>(
+    sender: &mut S,
+    reader: &mut R,
+    source: &mut (dyn Read + Send),
+    sink: &mut dyn Write,
+) -> Result<u64, ArqError>
+where
+    S: ArqSender + Send + ?Sized,
+    R: ArqReceiver + ?Sized,
+{
+    transfer_stream_timed(sender, reader, source, sink).map(|(bytes_read, _)| bytes_read)
+}
+
+/// Same as `transfer_stream`, but also returns how long it took until the
+/// reader had the whole message.
+fn transfer_stream_timed<S, R>(
+    sender: &mut S,
+    reader: &mut R,
+    source: &mut (dyn Read + Send),
+    sink: &mut dyn Write,
+) -> Result<(u64, Duration), ArqError>
+where
+    S: ArqSender + Send + ?Sized,
+    R: ArqReceiver + ?Sized,
+{
+    let start = Instant::now();
+    thread::scope(|s| {
+        s.spawn(|| {
+            if let Err(e) = sender.send_stream(source).and_then(|()| sender.close()) {
+                eprintln!("Sender | {e}");
+            }
+        });
+        let bytes_read = reader.read_stream(sink)?;
+        let completion_time = start.elapsed();
+        if let Err(e) = reader.close() {
+            eprintln!("Reader | {e}");
+        }
+        Ok((bytes_read, completion_time))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io, iter};
+
+    use super::*;
+    use crate::{
+        impairment::{ChannelModel, GilbertElliott, Jitter, LossModel},
+        packet::PacketState,
+        simulate_loss,
+    };
+
+    #[test]
+    fn test_protocols_small() {
+        for protocol in Protocol::ALL {
+            let message_send = String::from("test");
+            let message_received = protocol.setup(3, &message_send).0;
+            assert_eq!(message_send, message_received, "{}", protocol.name());
+        }
+    }
+
+    #[test]
+    fn test_protocols_binary() {
+        let message_send: Vec<u8> = (0..2_000).map(|i| (i * 7 % 256) as u8).collect();
+        assert!(String::from_utf8(message_send.clone()).is_err());
+        for protocol in Protocol::ALL {
+            let (sender_transport, reader_transport) = transport::channel();
+            let (mut sender, mut reader) = protocol
+                .endpoints(sender_transport, reader_transport, &ArqConfig::new(4))
+                .unwrap();
+            let message_received =
+                transfer_bytes(sender.as_mut(), reader.as_mut(), &message_send).unwrap();
+            assert_eq!(message_send, message_received, "{}", protocol.name());
+        }
+    }
+
+    #[test]
+    fn test_protocols_stream() {
+        let size = 100_000;
+        for protocol in Protocol::ALL.into_iter().filter(|p| p.is_windowed()) {
+            let (sender_transport, reader_transport) = transport::crossbeam_channel();
+            let (mut sender, mut reader) = protocol
+                .endpoints(sender_transport, reader_transport, &ArqConfig::new(16))
+                .unwrap();
+            let mut source = io::repeat(0xff).take(size);
+            let bytes_read = transfer_stream(
+                sender.as_mut(),
+                reader.as_mut(),
+                &mut source,
+                &mut io::sink(),
+            )
+            .unwrap();
+            assert_eq!(bytes_read, size, "{}", protocol.name());
+        }
+    }
+
+    #[test]
+    fn test_protocols_connection() {
+        let messages = ["first", "", "third message", &"4".repeat(1_000)];
+        for protocol in Protocol::ALL {
+            let (sender_transport, reader_transport) = transport::channel();
+            let (sender_transport, reader_transport) =
+                simulate_loss(sender_transport, reader_transport, 0.25, Some(0));
+            let config = ArqConfig::new(4).with_sequence_bits(3);
+            let (mut sender, mut reader) = protocol
+                .endpoints(sender_transport, reader_transport, &config)
+                .unwrap();
+            thread::scope(|s| {
+                s.spawn(|| {
+                    for message in messages {
+                        sender.send(message).unwrap();
+                    }
+                    sender.close().unwrap();
+                });
+                for message in messages {
+                    assert_eq!(reader.read().unwrap(), message, "{}", protocol.name());
+                }
+                reader.close().unwrap();
+                assert!(matches!(reader.read(), Err(ArqError::PeerClosed { .. })));
+            });
+        }
+    }
+
+    #[test]
+    fn test_protocols_congestion() {
+        let message_send = "C".repeat(4_000);
+        for congestion in CongestionAlgorithm::ALL {
+            let config = ArqConfig::new(8).with_congestion(congestion);
+            for protocol in Protocol::ALL.into_iter().filter(|p| p.is_windowed()) {
+                let (message_received, max_cwnd) = protocol.silent_setup_loss_with(
+                    &config,
+                    &message_send,
+                    0.1,
+                    Some(0),
+                    |sender| {
+                        let history = sender.congestion().unwrap().history();
+                        history.iter().map(|s| s.cwnd).fold(0.0, f64::max)
+                    },
+                );
+                assert_eq!(
+                    message_send,
+                    message_received.unwrap(),
+                    "{}",
+                    protocol.name()
+                );
+                assert!(max_cwnd <= 8.0);
+            }
+        }
+    }
+
+    #[test]
+    fn test_protocols_sequence_bits() {
+        let message_send = "D".repeat(4_000);
+        let space = SequenceSpace::new(2).unwrap();
+        for protocol in Protocol::ALL {
+            let window_size = protocol.max_window(space) as AckNumber;
+            let config = ArqConfig::new(window_size).with_sequence_bits(2);
+            let message_received = protocol
+                .silent_setup_loss_with(&config, &message_send, 0.25, Some(0), |_| ())
+                .0
+                .unwrap();
+            assert_eq!(message_send, message_received, "{}", protocol.name());
+        }
+    }
+
+    #[test]
+    fn test_window_too_large() {
+        for protocol in [Protocol::GoBackN, Protocol::SelectiveRepeat, Protocol::Sack] {
+            let (sender_transport, reader_transport) = transport::channel();
+            let config = ArqConfig::new(3).with_sequence_bits(2);
+            let result = protocol.endpoints(sender_transport, reader_transport, &config);
+            assert_eq!(result.is_err(), protocol != Protocol::GoBackN);
+            let (sender_transport, reader_transport) = transport::channel();
+            let config = ArqConfig::new(5).with_sequence_bits(2);
+            let result = protocol.endpoints(sender_transport, reader_transport, &config);
+            assert!(result.is_err());
+        }
+    }
+
+    #[test]
+    fn test_zero_window() {
+        for protocol in [Protocol::GoBackN, Protocol::SelectiveRepeat, Protocol::Sack] {
+            let (sender_transport, reader_transport) = transport::channel();
+            let result = protocol.endpoints(sender_transport, reader_transport, &ArqConfig::new(0));
+            assert!(
+                matches!(result, Err(ArqError::ZeroWindow)),
+                "{}",
+                protocol.name()
+            );
+        }
+    }
+
+    #[test]
+    fn test_protocols_bit_errors() {
+        let message_send = "E".repeat(2_000);
+        let impairments = Impairments::loss(0.1)
+            .with_bit_error_rate(1e-4)
+            .with_seed(0);
+        for protocol in Protocol::ALL {
+            let config = ArqConfig::new(3);
+            let (message_received, efficiency) =
+                protocol.silent_setup_with(&config, &message_send, &impairments, |sender| {
+                    sender.efficiency_coefficient()
+                });
+            assert_eq!(
+                message_send,
+                message_received.unwrap(),
+                "{}",
+                protocol.name()
+            );
+            assert!(efficiency > 0.0);
+        }
+    }
+
+    #[test]
+    fn test_protocols_channel() {
+        let message_send = "F".repeat(4_000);
+        let fifo = ChannelModel::default()
+            .with_delay(Duration::from_millis(2))
+            .with_duplication(0.2)
+            .with_bandwidth(10_000_000);
+        let reordering = fifo
+            .with_jitter(Jitter::Uniform(Duration::from_millis(2)))
+            .with_reorder(0.2);
+        for protocol in Protocol::ALL {
+            // The alternating bit cannot tell a reordered old packet from a new one
+            let channel = if protocol.is_windowed() {
+                reordering
+            } else {
+                fifo
+            };
+            let impairments = Impairments::loss(0.1).with_channel(channel).with_seed(0);
+            let message_received = protocol
+                .silent_setup_with(&ArqConfig::new(4), &message_send, &impairments, |_| ())
+                .0
+                .unwrap();
+            assert_eq!(message_send, message_received, "{}", protocol.name());
+        }
+    }
+
+    #[test]
+    fn test_protocols_burst_loss() {
+        let message_send = "G".repeat(2_000);
+        let loss = LossModel::GilbertElliott(GilbertElliott::bursts(0.1, 4.0));
+        let impairments = Impairments::default().with_loss(loss).with_seed(0);
+        for protocol in Protocol::ALL {
+            let message_received = protocol
+                .silent_setup_with(&ArqConfig::new(4), &message_send, &impairments, |_| ())
+                .0
+                .unwrap();
+            assert_eq!(message_send, message_received, "{}", protocol.name());
+        }
+    }
+
+    #[test]
+    fn test_protocols_ack_loss() {
+        let message_send = "H".repeat(2_000);
+        let impairments = Impairments::asymmetric_loss(0.0, 0.4).with_seed(0);
+        for protocol in Protocol::ALL {
+            let message_received = protocol
+                .silent_setup_with(&ArqConfig::new(4), &message_send, &impairments, |_| ())
+                .0
+                .unwrap();
+            assert_eq!(message_send, message_received, "{}", protocol.name());
+        }
+    }
+
+    #[test]
+    fn test_seeded_impairments() {
+        // Counts the packets and ACKs that get through each path
+        fn survivors(impairments: &Impairments) -> (usize, usize) {
+            let (mut sender, mut reader) = transport::simulated_link(impairments);
+            for number in 0..200 {
+                sender
+                    .send(Packet::control(PacketState::Begin, number))
+                    .unwrap();
+                reader.send(Ack::data(number)).unwrap();
+            }
+            let packets = iter::from_fn(|| reader.recv_timeout(Duration::ZERO).ok())
+                .filter(Packet::is_valid)
+                .count();
+            let acks = iter::from_fn(|| sender.recv_timeout(Duration::ZERO).ok()).count();
+            (packets, acks)
+        }
+
+        let impairments = Impairments::asymmetric_loss(0.3, 0.2)
+            .with_bit_error_rate(1e-3)
+            .with_seed(7);
+        let first = survivors(&impairments);
+        for _ in 0..3 {
+            assert_eq!(survivors(&impairments), first);
+        }
+    }
+
+    #[test]
+    fn test_protocols_loss() {
+        let message_send = "B".repeat(2_000);
+        for protocol in Protocol::ALL {
+            let message_received = protocol
+                .silent_setup_loss(3, &message_send, 0.25, Some(0))
+                .0;
+            assert_eq!(message_send, message_received, "{}", protocol.name());
+        }
+    }
+}
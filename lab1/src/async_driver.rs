@@ -0,0 +1,440 @@
+use std::{
+    future::Future,
+    io::{Read, Write},
+    sync::mpsc::SendError,
+};
+
+use tokio::{
+    sync::mpsc,
+    time::{self, Instant},
+};
+
+use crate::{
+    arq::{ReaderStats, SenderStats, TIMEOUT_TOTAL},
+    congestion::CongestionWindow,
+    connection,
+    error::{ArqError, Operation},
+    machine::{ArqReceiverMachine, ArqSenderMachine},
+    packet::{Ack, AckNumber, Packet, PacketState, Packetizer},
+    rto::RtoEstimator,
+    transport::Lossy,
+};
+
+/// Async counterpart of `Transport`, one end of a datagram link.
+pub trait AsyncTransport<O, I> {
+    /// Puts `value` on the link. Fails with the value once the peer can no
+    /// longer receive anything.
+    fn send(&mut self, value: O) -> impl Future<Output = Result<(), SendError<O>>> + Send;
+
+    /// Waits for the next value, `None` once nothing can arrive anymore.
+    /// Must be cancel safe, the drivers drop it whenever a timer fires first.
+    fn recv(&mut self) -> impl Future<Output = Option<I>> + Send;
+}
+
+/// Transport over a pair of tokio channels.
+pub type Channel<O, I> = (mpsc::UnboundedSender<O>, mpsc::UnboundedReceiver<I>);
+
+/// Two connected tokio transports, one for each end.
+#[must_use]
+pub fn channel<A, B>() -> (Channel<A, B>, Channel<B, A>) {
+    let (tx_a, rx_a) = mpsc::unbounded_channel();
+    let (tx_b, rx_b) = mpsc::unbounded_channel();
+    ((tx_a, rx_b), (tx_b, rx_a))
+}
+
+impl<O: Send, I: Send> AsyncTransport<O, I> for Channel<O, I> {
+    async fn send(&mut self, value: O) -> Result<(), SendError<O>> {
+        self.0.send(value).map_err(|e| SendError(e.0))
+    }
+
+    async fn recv(&mut self) -> Option<I> {
+        self.1.recv().await
+    }
+}
+
+impl<O: Send, I: Send, T: AsyncTransport<O, I> + Send> AsyncTransport<O, I> for Lossy<T> {
+    async fn send(&mut self, value: O) -> Result<(), SendError<O>> {
+        self.inner.send(value).await
+    }
+
+    async fn recv(&mut self) -> Option<I> {
+        loop {
+            let value = self.inner.recv().await?;
+            if !self.is_lost() {
+                return Some(value);
+            }
+        }
+    }
+}
+
+/// Current time for the machines. Follows the tokio clock, so that the
+/// machines run on virtual time when the clock is paused.
+fn now() -> std::time::Instant {
+    Instant::now().into_std()
+}
+
+/// Runs a sender machine on a tokio runtime. Sleeps on the transport until
+/// the machine's next timer, a waiting sender takes no thread.
+pub struct AsyncSenderDriver<M: ?Sized, T = Channel<Packet, Ack>> {
+    transport: T,
+    initial_sequence: Option<AckNumber>,
+    pub(crate) machine: Box<M>,
+}
+
+impl<M: ArqSenderMachine + ?Sized, T: AsyncTransport<Packet, Ack>> AsyncSenderDriver<M, T> {
+    #[must_use]
+    pub fn new(transport: T, machine: impl Into<Box<M>>) -> Self {
+        Self {
+            transport,
+            initial_sequence: None,
+            machine: machine.into(),
+        }
+    }
+
+    /// Opens connections with `isn` instead of a random initial sequence number.
+    #[must_use]
+    pub fn with_initial_sequence(mut self, isn: AckNumber) -> Self {
+        self.initial_sequence = Some(isn);
+        self
+    }
+
+    /// Puts whatever the machine has to send on the wire, then waits for an
+    /// ACK until the machine's next timer or `deadline`.
+    async fn step(&mut self, deadline: std::time::Instant) -> Result<(), ArqError> {
+        while let Some(packet) = self.machine.poll_transmit(now()) {
+            self.transport
+                .send(packet)
+                .await
+                .map_err(|e| ArqError::PeerDisconnected {
+                    unsent: Some(e.0.number),
+                })?;
+        }
+        let wake = self
+            .machine
+            .poll_timeout()
+            .map_or(deadline, |timer| timer.min(deadline));
+        match time::timeout_at(Instant::from_std(wake), self.transport.recv()).await {
+            Ok(Some(ack)) => self.machine.handle_ack(ack, now()),
+            Ok(None) => return Err(ArqError::PeerDisconnected { unsent: None }),
+            Err(_) => self.machine.handle_timeout(now()),
+        }
+        Ok(())
+    }
+
+    /// Fails `operation` at the oldest packet still waiting for its ACK.
+    fn timeout(&self, operation: Operation) -> ArqError {
+        ArqError::Timeout {
+            operation,
+            number: self.machine.oldest_unacked(),
+        }
+    }
+
+    /// Steps the machine until `is_done`, failing `operation` after `TIMEOUT_TOTAL`.
+    async fn run_until(
+        &mut self,
+        is_done: impl Fn(&M) -> bool,
+        operation: Operation,
+    ) -> Result<(), ArqError> {
+        let deadline = now() + TIMEOUT_TOTAL;
+        while !is_done(self.machine.as_ref()) {
+            if now() >= deadline {
+                return Err(self.timeout(operation));
+            }
+            self.step(deadline).await?;
+        }
+        Ok(())
+    }
+
+    /// Same as `ArqSender::connect`.
+    pub async fn connect(&mut self) -> Result<(), ArqError> {
+        let isn = self
+            .initial_sequence
+            .unwrap_or_else(|| connection::random_sequence(self.machine.space()));
+        self.machine.connect(isn, now());
+        self.run_until(M::is_connected, Operation::Connect).await
+    }
+
+    /// Same as `ArqSender::send_stream`. Reads `source` between the steps,
+    /// so it should not block.
+    pub async fn send_stream(&mut self, source: &mut (impl Read + ?Sized)) -> Result<(), ArqError> {
+        if !self.machine.is_connected() {
+            self.connect().await?;
+        }
+        self.machine.start_message();
+        let mut packetizer = Packetizer::new(source);
+        let mut deadline = now() + TIMEOUT_TOTAL;
+        let mut oldest = self.machine.oldest_unacked();
+        loop {
+            while self.machine.has_room() {
+                let Some(packet) = packetizer.next_packet()? else {
+                    break;
+                };
+                self.machine.push(packet);
+            }
+            if packetizer.is_finished() && self.machine.is_idle() {
+                return Ok(());
+            }
+            if self.machine.oldest_unacked() != oldest {
+                // Only a transfer that stops advancing times out
+                oldest = self.machine.oldest_unacked();
+                deadline = now() + TIMEOUT_TOTAL;
+            }
+            if now() >= deadline {
+                return Err(self.timeout(Operation::Send));
+            }
+            self.step(deadline).await?;
+        }
+    }
+
+    pub async fn send_bytes(&mut self, mut message: &[u8]) -> Result<(), ArqError> {
+        self.send_stream(&mut message).await
+    }
+
+    pub async fn send(&mut self, message: &str) -> Result<(), ArqError> {
+        self.send_bytes(message.as_bytes()).await
+    }
+
+    /// Same as `ArqSender::close`.
+    pub async fn close(&mut self) -> Result<(), ArqError> {
+        if !self.machine.is_connected() {
+            return Ok(());
+        }
+        self.machine.close();
+        self.run_until(M::is_closed, Operation::Close).await
+    }
+}
+
+impl<M: ArqSenderMachine + ?Sized, T> SenderStats for AsyncSenderDriver<M, T> {
+    fn efficiency_coefficient(&self) -> f64 {
+        self.machine.efficiency_coefficient()
+    }
+
+    fn retransmissions(&self) -> usize {
+        self.machine.retransmissions()
+    }
+
+    fn rto(&self) -> &RtoEstimator {
+        self.machine.rto()
+    }
+
+    fn congestion(&self) -> Option<&CongestionWindow> {
+        self.machine.congestion()
+    }
+}
+
+/// Runs a receiver machine on a tokio runtime.
+pub struct AsyncReaderDriver<M: ?Sized, T = Channel<Ack, Packet>> {
+    transport: T,
+    pub(crate) machine: Box<M>,
+}
+
+impl<M: ArqReceiverMachine + ?Sized, T: AsyncTransport<Ack, Packet>> AsyncReaderDriver<M, T> {
+    #[must_use]
+    pub fn new(transport: T, machine: impl Into<Box<M>>) -> Self {
+        Self {
+            transport,
+            machine: machine.into(),
+        }
+    }
+
+    async fn send_acks(&mut self) -> Result<(), ArqError> {
+        while let Some(ack) = self.machine.poll_transmit() {
+            self.transport
+                .send(ack)
+                .await
+                .map_err(|e| ArqError::PeerDisconnected {
+                    unsent: Some(e.0.number),
+                })?;
+        }
+        Ok(())
+    }
+
+    /// Sends the pending ACKs, then waits for a packet until the machine's
+    /// next timer or `deadline`. Returns `false` once the sender is gone.
+    async fn step(&mut self, deadline: std::time::Instant) -> Result<bool, ArqError> {
+        self.send_acks().await?;
+        let wake = self
+            .machine
+            .poll_timeout()
+            .map_or(deadline, |timer| timer.min(deadline));
+        match time::timeout_at(Instant::from_std(wake), self.transport.recv()).await {
+            Ok(Some(packet)) => self.machine.handle_packet(packet, now())?,
+            Err(_) => self.machine.handle_timeout(now()),
+            // No FIN can come anymore
+            Ok(None) if self.machine.is_closed() => return Ok(false),
+            Ok(None) => return Err(ArqError::PeerDisconnected { unsent: None }),
+        }
+        Ok(true)
+    }
+
+    /// Same as `ArqReceiver::read_stream`.
+    pub async fn read_stream(&mut self, sink: &mut (impl Write + ?Sized)) -> Result<u64, ArqError> {
+        if self.machine.is_closed() {
+            return Err(ArqError::PeerClosed {
+                fin: self.machine.expected(),
+            });
+        }
+        let mut bytes_read = 0;
+        let mut deadline = now() + TIMEOUT_TOTAL;
+        loop {
+            while let Some(packet) = self.machine.poll_deliver() {
+                sink.write_all(packet.payload())?;
+                bytes_read += u64::from(packet.size);
+                deadline = now() + TIMEOUT_TOTAL;
+                if matches!(packet.state, PacketState::End) {
+                    sink.flush()?;
+                    self.send_acks().await?;
+                    return Ok(bytes_read);
+                }
+            }
+            if now() >= deadline {
+                return Err(ArqError::Timeout {
+                    operation: Operation::Read,
+                    number: self
+                        .machine
+                        .is_established()
+                        .then(|| self.machine.expected()),
+                });
+            }
+            let is_established = self.machine.is_established();
+            self.step(deadline).await?;
+            if !is_established && self.machine.is_established() {
+                // The message deadline starts with a new connection
+                deadline = now() + TIMEOUT_TOTAL;
+            }
+        }
+    }
+
+    pub async fn read_bytes(&mut self) -> Result<Vec<u8>, ArqError> {
+        let mut data = Vec::new();
+        self.read_stream(&mut data).await?;
+        Ok(data)
+    }
+
+    pub async fn read(&mut self) -> Result<String, ArqError> {
+        Ok(String::from_utf8(self.read_bytes().await?)?)
+    }
+
+    /// Same as `ArqReceiver::close`.
+    pub async fn close(&mut self) -> Result<(), ArqError> {
+        if !self.machine.is_established() && !self.machine.is_closed() {
+            return Ok(());
+        }
+        self.machine.close();
+        let deadline = now() + TIMEOUT_TOTAL;
+        while !self.machine.is_time_wait_over(now()) {
+            if now() >= deadline {
+                return Err(ArqError::Timeout {
+                    operation: Operation::Close,
+                    number: Some(self.machine.expected()),
+                });
+            }
+            if !self.step(deadline).await? {
+                break;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<M: ArqReceiverMachine + ?Sized, T> ReaderStats for AsyncReaderDriver<M, T> {
+    fn packets_read(&self) -> usize {
+        self.machine.packets_read()
+    }
+
+    fn duplicates(&self) -> usize {
+        self.machine.duplicates()
+    }
+}
+
+/// Async counterpart of `arq::transfer_bytes`. Runs both ends concurrently
+/// on the current task.
+pub async fn transfer_bytes<MS, S, MR, R>(
+    sender: &mut AsyncSenderDriver<MS, S>,
+    reader: &mut AsyncReaderDriver<MR, R>,
+    message: &[u8],
+) -> Result<Vec<u8>, ArqError>
+where
+    MS: ArqSenderMachine + ?Sized,
+    S: AsyncTransport<Packet, Ack>,
+    MR: ArqReceiverMachine + ?Sized,
+    R: AsyncTransport<Ack, Packet>,
+{
+    let mut data = Vec::new();
+    let send = async {
+        if let Err(e) = async {
+            sender.send_bytes(message).await?;
+            sender.close().await
+        }
+        .await
+        {
+            eprintln!("Sender | {e}");
+        }
+    };
+    let read = async {
+        reader.read_stream(&mut data).await?;
+        if let Err(e) = reader.close().await {
+            eprintln!("Reader | {e}");
+        }
+        Ok::<_, ArqError>(())
+    };
+    let ((), read) = tokio::join!(send, read);
+    read.map(|()| data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        arq::{ArqConfig, Protocol},
+        simulate_loss,
+    };
+
+    async fn setup_async(
+        protocol: Protocol,
+        message: &str,
+        loss: f64,
+        seed: Option<u64>,
+    ) -> String {
+        let (sender_transport, reader_transport) = channel();
+        let (sender_transport, reader_transport) =
+            simulate_loss(sender_transport, reader_transport, loss, seed);
+        let (mut sender, mut reader) = protocol
+            .async_endpoints(sender_transport, reader_transport, &ArqConfig::new(4))
+            .unwrap();
+        let data = transfer_bytes(&mut sender, &mut reader, message.as_bytes())
+            .await
+            .unwrap();
+        String::from_utf8(data).unwrap()
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_async_protocols_loss() {
+        let message_send = "A".repeat(5_000);
+        let time = Instant::now();
+        let real_time = std::time::Instant::now();
+        for protocol in Protocol::ALL {
+            for loss in [0.0, 0.25, 0.5] {
+                let message_received = setup_async(protocol, &message_send, loss, Some(0)).await;
+                assert_eq!(message_send, message_received, "{}", protocol.name());
+            }
+        }
+        // Timeouts pass without waiting for them
+        assert!(time.elapsed() > real_time.elapsed());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_async_concurrent_transfers() {
+        let handles: Vec<_> = (0..1_000)
+            .map(|i| {
+                let protocol = Protocol::ALL[i % Protocol::ALL.len()];
+                tokio::spawn(async move {
+                    setup_async(protocol, &i.to_string(), 0.1, Some(i as u64)).await
+                })
+            })
+            .collect();
+        for (i, handle) in handles.into_iter().enumerate() {
+            assert_eq!(handle.await.unwrap(), i.to_string());
+        }
+    }
+}
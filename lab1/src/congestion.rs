@@ -0,0 +1,355 @@
+use std::time::{Duration, Instant};
+
+/// Congestion window after slow start restarts.
+const INITIAL_WINDOW: f64 = 1.0;
+const MIN_SSTHRESH: f64 = 2.0;
+/// Slow start is unbounded until the first loss.
+const INITIAL_SSTHRESH: f64 = f64::INFINITY;
+
+/// Congestion control algorithm driving the sender's window. All windows
+/// are measured in packets.
+pub trait CongestionControl: Send {
+    fn cwnd(&self) -> f64;
+
+    /// `acked` packets left the network at `now`.
+    fn on_ack(&mut self, acked: u32, now: Instant);
+
+    /// A packet was lost while ACKs keep arriving (multiplicative decrease).
+    fn on_loss(&mut self);
+
+    /// The retransmission timer fired for the whole window.
+    fn on_timeout(&mut self);
+
+    /// Keeps the window at most `max` packets, so that it stops growing
+    /// while the sender is limited by its flow-control window.
+    fn cap(&mut self, max: f64);
+
+    fn reset(&mut self);
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CongestionAlgorithm {
+    /// Window limited only by the configured window size.
+    #[default]
+    Fixed,
+    /// Slow start followed by AIMD.
+    Reno,
+    /// CUBIC window growth (RFC 8312).
+    Cubic,
+}
+
+impl CongestionAlgorithm {
+    pub const ALL: [Self; 3] = [Self::Fixed, Self::Reno, Self::Cubic];
+
+    #[must_use]
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Fixed => "fixed",
+            Self::Reno => "reno",
+            Self::Cubic => "cubic",
+        }
+    }
+
+    #[must_use]
+    pub fn build(self) -> Box<dyn CongestionControl> {
+        match self {
+            Self::Fixed => Box::new(Fixed),
+            Self::Reno => Box::new(Reno::new()),
+            Self::Cubic => Box::new(Cubic::new()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Fixed;
+
+impl CongestionControl for Fixed {
+    fn cwnd(&self) -> f64 {
+        f64::INFINITY
+    }
+
+    fn on_ack(&mut self, _acked: u32, _now: Instant) {}
+
+    fn on_loss(&mut self) {}
+
+    fn on_timeout(&mut self) {}
+
+    fn cap(&mut self, _max: f64) {}
+
+    fn reset(&mut self) {}
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Reno {
+    cwnd: f64,
+    ssthresh: f64,
+}
+
+impl Default for Reno {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Reno {
+    #[must_use]
+    pub fn new() -> Self {
+        Self {
+            cwnd: INITIAL_WINDOW,
+            ssthresh: INITIAL_SSTHRESH,
+        }
+    }
+}
+
+impl CongestionControl for Reno {
+    fn cwnd(&self) -> f64 {
+        self.cwnd
+    }
+
+    fn on_ack(&mut self, acked: u32, _now: Instant) {
+        for _ in 0..acked {
+            if self.cwnd < self.ssthresh {
+                self.cwnd += 1.0;
+            } else {
+                self.cwnd += 1.0 / self.cwnd;
+            }
+        }
+    }
+
+    fn on_loss(&mut self) {
+        self.ssthresh = (self.cwnd / 2.0).max(MIN_SSTHRESH);
+        self.cwnd = self.ssthresh;
+    }
+
+    fn on_timeout(&mut self) {
+        self.ssthresh = (self.cwnd / 2.0).max(MIN_SSTHRESH);
+        self.cwnd = INITIAL_WINDOW;
+    }
+
+    fn cap(&mut self, max: f64) {
+        self.cwnd = self.cwnd.min(max);
+    }
+
+    fn reset(&mut self) {
+        *self = Self::new();
+    }
+}
+
+/// Scaling constant `C` from RFC 8312.
+const CUBIC_C: f64 = 0.4;
+/// Multiplicative decrease factor `beta_cubic` from RFC 8312.
+const CUBIC_BETA: f64 = 0.7;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Cubic {
+    cwnd: f64,
+    ssthresh: f64,
+    w_max: f64,
+    /// Start of the current congestion avoidance epoch.
+    epoch_start: Option<Instant>,
+}
+
+impl Default for Cubic {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Cubic {
+    #[must_use]
+    pub fn new() -> Self {
+        Self {
+            cwnd: INITIAL_WINDOW,
+            ssthresh: INITIAL_SSTHRESH,
+            w_max: 0.0,
+            epoch_start: None,
+        }
+    }
+
+    /// `W_cubic(t) = C * (t - K)^3 + W_max`
+    fn target(&self, elapsed: Duration) -> f64 {
+        let k = (self.w_max * (1.0 - CUBIC_BETA) / CUBIC_C).cbrt();
+        let t = elapsed.as_secs_f64() - k;
+        CUBIC_C * t * t * t + self.w_max
+    }
+
+    fn decrease(&mut self) {
+        self.w_max = self.cwnd;
+        self.ssthresh = (self.cwnd * CUBIC_BETA).max(MIN_SSTHRESH);
+        self.epoch_start = None;
+    }
+}
+
+impl CongestionControl for Cubic {
+    fn cwnd(&self) -> f64 {
+        self.cwnd
+    }
+
+    fn on_ack(&mut self, acked: u32, now: Instant) {
+        for _ in 0..acked {
+            if self.cwnd < self.ssthresh {
+                self.cwnd += 1.0;
+                continue;
+            }
+            let epoch_start = *self.epoch_start.get_or_insert(now);
+            let target = self.target(now.saturating_duration_since(epoch_start));
+            if target > self.cwnd {
+                self.cwnd += (target - self.cwnd) / self.cwnd;
+            } else {
+                // Plateau around W_max: grow very slowly
+                self.cwnd += 0.01 / self.cwnd;
+            }
+        }
+    }
+
+    fn on_loss(&mut self) {
+        self.decrease();
+        self.cwnd = self.ssthresh;
+    }
+
+    fn on_timeout(&mut self) {
+        self.decrease();
+        self.cwnd = INITIAL_WINDOW;
+    }
+
+    fn cap(&mut self, max: f64) {
+        self.cwnd = self.cwnd.min(max);
+    }
+
+    fn reset(&mut self) {
+        *self = Self::new();
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct CwndSample {
+    /// Time since the window was reset.
+    pub at: Duration,
+    pub cwnd: f64,
+}
+
+/// Sender-side congestion window: the configured algorithm capped by the
+/// flow-control window, with a trace of every change.
+pub struct CongestionWindow {
+    control: Box<dyn CongestionControl>,
+    window_size: u32,
+    start: Instant,
+    history: Vec<CwndSample>,
+}
+
+impl CongestionWindow {
+    #[must_use]
+    pub fn new(control: Box<dyn CongestionControl>, window_size: u32) -> Self {
+        Self {
+            control,
+            window_size,
+            start: Instant::now(),
+            history: Vec::new(),
+        }
+    }
+
+    /// Starts over, `now` is the origin of the history.
+    pub fn reset(&mut self, now: Instant) {
+        self.control.reset();
+        self.start = now;
+        self.history.clear();
+    }
+
+    /// Effective window, `min(cwnd, window_size)`, never below one packet.
+    #[must_use]
+    pub fn effective(&self) -> u32 {
+        let cwnd = self.control.cwnd().floor().max(1.0);
+        if cwnd >= f64::from(self.window_size) {
+            self.window_size
+        } else {
+            cwnd as u32
+        }
+    }
+
+    /// Congestion window after every change since the last reset.
+    #[must_use]
+    pub fn history(&self) -> &[CwndSample] {
+        &self.history
+    }
+
+    pub fn on_ack(&mut self, acked: u32, now: Instant) {
+        self.control.on_ack(acked, now);
+        self.control.cap(f64::from(self.window_size));
+        self.record(now);
+    }
+
+    pub fn on_loss(&mut self, now: Instant) {
+        self.control.on_loss();
+        self.record(now);
+    }
+
+    pub fn on_timeout(&mut self, now: Instant) {
+        self.control.on_timeout();
+        self.record(now);
+    }
+
+    fn record(&mut self, now: Instant) {
+        self.history.push(CwndSample {
+            at: now.saturating_duration_since(self.start),
+            cwnd: self.control.cwnd().min(f64::from(self.window_size)),
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reno_slow_start_and_aimd() {
+        let now = Instant::now();
+        let mut reno = Reno::new();
+        reno.on_ack(1, now);
+        reno.on_ack(1, now);
+        reno.on_ack(2, now);
+        assert_eq!(reno.cwnd(), 5.0);
+        reno.on_loss();
+        assert_eq!(reno.cwnd(), 2.5);
+        reno.on_ack(1, now);
+        assert!(reno.cwnd() < 3.0);
+        reno.on_timeout();
+        assert_eq!(reno.cwnd(), INITIAL_WINDOW);
+    }
+
+    #[test]
+    fn test_cubic_decrease() {
+        let now = Instant::now();
+        let mut cubic = Cubic::new();
+        cubic.on_ack(9, now);
+        assert_eq!(cubic.cwnd(), 10.0);
+        cubic.on_loss();
+        assert_eq!(cubic.cwnd(), 10.0 * CUBIC_BETA);
+        cubic.on_ack(1, now);
+        assert!(cubic.cwnd() > 10.0 * CUBIC_BETA);
+        assert!(cubic.cwnd() < 10.0);
+    }
+
+    #[test]
+    fn test_effective_window() {
+        let now = Instant::now();
+        let mut window = CongestionWindow::new(CongestionAlgorithm::Reno.build(), 4);
+        assert_eq!(window.effective(), 1);
+        window.on_ack(10, now);
+        assert_eq!(window.effective(), 4);
+        let window = CongestionWindow::new(CongestionAlgorithm::Fixed.build(), 4);
+        assert_eq!(window.effective(), 4);
+    }
+
+    #[test]
+    fn test_window_limited_growth() {
+        let now = Instant::now();
+        for algorithm in [CongestionAlgorithm::Reno, CongestionAlgorithm::Cubic] {
+            let mut window = CongestionWindow::new(algorithm.build(), 4);
+            // A long window-limited stretch does not inflate cwnd
+            window.on_ack(1_000, now);
+            assert_eq!(window.control.cwnd(), 4.0, "{}", algorithm.name());
+            window.on_loss(now);
+            assert!(window.effective() < 4, "{}", algorithm.name());
+        }
+    }
+}
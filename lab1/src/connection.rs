@@ -0,0 +1,355 @@
+use std::time::{Duration, Instant};
+
+use crate::{
+    arq::TIMEOUT,
+    packet::{Ack, AckKind, AckNumber, Packet, PacketState},
+    rto::RtoEstimator,
+    seq::SequenceSpace,
+};
+
+/// How long a closing reader stays after the last FIN, so that the sender
+/// still gets a FIN-ACK when the first one is lost.
+const TIME_WAIT: Duration = TIMEOUT.saturating_mul(2);
+/// The FIN-ACK is repeated a few times during `TIME_WAIT` rather than only
+/// on retransmitted FINs, which would need a FIN and a FIN-ACK to get through.
+const FIN_ACK_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Random initial sequence number, so that stray packets of an earlier
+/// connection are unlikely to fit into the window of a new one.
+pub(crate) fn random_sequence(space: SequenceSpace) -> AckNumber {
+    rand::random_range(0..space.modulus()) as AckNumber
+}
+
+/// Sender side of a SYN/SYN-ACK or FIN/FIN-ACK exchange without I/O. The
+/// control packet is repeated every RTO until an ACK of `kind` for it arrives.
+#[derive(Debug, Clone)]
+pub(crate) struct Handshake {
+    packet: Packet,
+    kind: AckKind,
+    last_sent: Option<Instant>,
+    is_retransmitted: bool,
+    is_due: bool,
+}
+
+impl Handshake {
+    fn new(packet: Packet, kind: AckKind) -> Self {
+        Self {
+            packet,
+            kind,
+            last_sent: None,
+            is_retransmitted: false,
+            is_due: true,
+        }
+    }
+
+    fn deadline(&self, rto: &RtoEstimator) -> Option<Instant> {
+        self.last_sent.map(|last_sent| last_sent + rto.current())
+    }
+
+    /// Whether `ack` answers the control packet.
+    fn on_ack(&mut self, ack: Ack, rto: &mut RtoEstimator, now: Instant) -> bool {
+        if ack.kind != self.kind || ack.number != self.packet.number {
+            return false;
+        }
+        // Karn's algorithm: a retransmitted packet gives an ambiguous sample
+        if let (false, Some(last_sent)) = (self.is_retransmitted, self.last_sent) {
+            rto.on_sample(now.saturating_duration_since(last_sent), now);
+        }
+        true
+    }
+
+    fn on_timeout(&mut self, rto: &mut RtoEstimator, now: Instant) {
+        if let Some(deadline) = self.deadline(rto)
+            && !self.is_due
+            && now >= deadline
+        {
+            rto.on_timeout(now);
+            self.is_due = true;
+        }
+    }
+
+    fn poll_transmit(&mut self, now: Instant) -> Option<Packet> {
+        if !self.is_due {
+            return None;
+        }
+        self.is_due = false;
+        self.is_retransmitted |= self.last_sent.is_some();
+        self.last_sent = Some(now);
+        Some(self.packet.clone())
+    }
+}
+
+/// Sender side of the connection lifecycle.
+#[derive(Debug, Clone, Default)]
+pub(crate) enum SenderConnection {
+    #[default]
+    Idle,
+    /// The SYN is not acknowledged yet.
+    Connecting(Handshake),
+    Established,
+    /// The FIN is not acknowledged yet.
+    Closing(Handshake),
+    Closed,
+}
+
+impl SenderConnection {
+    /// Starts the handshake, `isn` is the number of the SYN.
+    pub(crate) fn connect(&mut self, isn: AckNumber) {
+        let syn = Packet::control(PacketState::Syn, isn);
+        *self = Self::Connecting(Handshake::new(syn, AckKind::SynAck));
+    }
+
+    /// Starts the close, `fin` is the number right after the last data
+    /// packet. A connection that was never established is closed at once.
+    pub(crate) fn close(&mut self, fin: AckNumber) {
+        *self = match self {
+            Self::Established => {
+                let fin = Packet::control(PacketState::Fin, fin);
+                Self::Closing(Handshake::new(fin, AckKind::FinAck))
+            }
+            _ => Self::Closed,
+        };
+    }
+
+    pub(crate) fn is_established(&self) -> bool {
+        matches!(self, Self::Established)
+    }
+
+    pub(crate) fn is_closed(&self) -> bool {
+        matches!(self, Self::Closed)
+    }
+
+    /// Number of the SYN or FIN waiting for its ACK.
+    pub(crate) fn pending(&self) -> Option<AckNumber> {
+        self.handshake().map(|handshake| handshake.packet.number)
+    }
+
+    fn handshake(&self) -> Option<&Handshake> {
+        match self {
+            Self::Connecting(handshake) | Self::Closing(handshake) => Some(handshake),
+            _ => None,
+        }
+    }
+
+    fn handshake_mut(&mut self) -> Option<&mut Handshake> {
+        match self {
+            Self::Connecting(handshake) | Self::Closing(handshake) => Some(handshake),
+            _ => None,
+        }
+    }
+
+    /// Handles a SYN-ACK or FIN-ACK. Returns `false` for ACKs of data.
+    pub(crate) fn on_ack(&mut self, ack: Ack, rto: &mut RtoEstimator, now: Instant) -> bool {
+        if !ack.kind.is_control() {
+            return false;
+        }
+        if let Some(handshake) = self.handshake_mut()
+            && handshake.on_ack(ack, rto, now)
+        {
+            *self = match self {
+                Self::Connecting(_) => Self::Established,
+                _ => Self::Closed,
+            };
+        }
+        true
+    }
+
+    pub(crate) fn on_timeout(&mut self, rto: &mut RtoEstimator, now: Instant) {
+        if let Some(handshake) = self.handshake_mut() {
+            handshake.on_timeout(rto, now);
+        }
+    }
+
+    pub(crate) fn poll_timeout(&self, rto: &RtoEstimator) -> Option<Instant> {
+        self.handshake()
+            .filter(|handshake| !handshake.is_due)
+            .and_then(|handshake| handshake.deadline(rto))
+    }
+
+    pub(crate) fn poll_transmit(&mut self, now: Instant) -> Option<Packet> {
+        self.handshake_mut()?.poll_transmit(now)
+    }
+}
+
+/// Reader side of the connection lifecycle.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) enum Connection {
+    /// Waiting for a SYN.
+    #[default]
+    Listen,
+    Established {
+        syn: AckNumber,
+    },
+    /// The sender's FIN was acknowledged.
+    Closed {
+        fin: AckNumber,
+    },
+}
+
+impl Connection {
+    pub(crate) fn is_established(self) -> bool {
+        matches!(self, Self::Established { .. })
+    }
+
+    pub(crate) fn is_closed(self) -> bool {
+        matches!(self, Self::Closed { .. })
+    }
+
+    /// Lingering state to enter at `now` once the connection is closed.
+    pub(crate) fn time_wait(self, now: Instant) -> Option<TimeWait> {
+        match self {
+            Self::Closed { fin } => Some(TimeWait::new(Ack::new(fin, AckKind::FinAck), now)),
+            _ => None,
+        }
+    }
+
+    /// Handles a SYN or FIN and returns the ACK to send back, if any.
+    /// `expected` is the absolute index of the next in-order data packet,
+    /// a new connection starts it right after the initial sequence number.
+    /// A new SYN is only accepted while listening or once closed, and a FIN
+    /// only once every data packet before it arrived.
+    pub(crate) fn on_control(
+        &mut self,
+        packet: &Packet,
+        expected: &mut usize,
+        space: SequenceSpace,
+    ) -> Option<Ack> {
+        let kind = match (packet.state, *self) {
+            (PacketState::Syn, Self::Established { syn }) if syn == packet.number => {
+                AckKind::SynAck
+            }
+            // A stray SYN of an earlier connection must not reset this one
+            (PacketState::Syn, Self::Established { .. }) => return None,
+            (PacketState::Syn, _) => {
+                *self = Self::Established { syn: packet.number };
+                *expected = packet.number as usize + 1;
+                AckKind::SynAck
+            }
+            (PacketState::Fin, Self::Established { .. })
+                if packet.number == space.wrap(*expected) =>
+            {
+                *self = Self::Closed { fin: packet.number };
+                AckKind::FinAck
+            }
+            (PacketState::Fin, Self::Closed { fin }) if fin == packet.number => AckKind::FinAck,
+            _ => return None,
+        };
+        Some(Ack::new(packet.number, kind))
+    }
+}
+
+/// Reader's `TIME_WAIT`: lasts until no FIN arrived for `TIME_WAIT` and
+/// keeps repeating the FIN-ACK meanwhile.
+pub(crate) struct TimeWait {
+    fin_ack: Ack,
+    start: Instant,
+    last_ack: Instant,
+    /// Whether `poll` found `TIME_WAIT` over.
+    is_expired: bool,
+}
+
+impl TimeWait {
+    fn new(fin_ack: Ack, now: Instant) -> Self {
+        Self {
+            fin_ack,
+            start: now,
+            last_ack: now,
+            is_expired: false,
+        }
+    }
+
+    /// A retransmitted FIN arrived at `now`: the sender still lacks the
+    /// FIN-ACK, so `TIME_WAIT` starts over.
+    pub(crate) fn restart(&mut self, now: Instant) {
+        self.start = now;
+    }
+
+    pub(crate) fn is_over(&self, now: Instant) -> bool {
+        now.saturating_duration_since(self.start) >= TIME_WAIT
+    }
+
+    /// The FIN-ACK to repeat, if it is time to.
+    pub(crate) fn poll(&mut self, now: Instant) -> Option<Ack> {
+        self.is_expired = self.is_over(now);
+        if self.is_expired || now.saturating_duration_since(self.last_ack) < FIN_ACK_INTERVAL {
+            return None;
+        }
+        self.last_ack = now;
+        Some(self.fin_ack)
+    }
+
+    /// When `poll` repeats the FIN-ACK next or finds `TIME_WAIT` over,
+    /// `None` once it did.
+    pub(crate) fn deadline(&self) -> Option<Instant> {
+        let next = self.last_ack + FIN_ACK_INTERVAL;
+        (!self.is_expired).then(|| next.min(self.start + TIME_WAIT))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reader_lifecycle() {
+        let space = SequenceSpace::new(3).unwrap();
+        let mut connection = Connection::default();
+        let mut expected = 0;
+        let fin = Packet::control(PacketState::Fin, 0);
+        assert_eq!(connection.on_control(&fin, &mut expected, space), None);
+
+        let syn = Packet::control(PacketState::Syn, 7);
+        let ack = connection.on_control(&syn, &mut expected, space).unwrap();
+        assert_eq!(ack.kind, AckKind::SynAck);
+        assert!(connection.is_established());
+        assert_eq!(space.wrap(expected), 0);
+        // A retransmitted SYN does not restart the connection
+        expected += 2;
+        assert!(connection.on_control(&syn, &mut expected, space).is_some());
+        assert_eq!(expected, 10);
+
+        // FIN ahead of missing data is ignored
+        let fin = Packet::control(PacketState::Fin, 3);
+        assert_eq!(connection.on_control(&fin, &mut expected, space), None);
+        let fin = Packet::control(PacketState::Fin, 2);
+        let ack = connection.on_control(&fin, &mut expected, space).unwrap();
+        assert_eq!(ack.kind, AckKind::FinAck);
+        assert!(connection.is_closed());
+        assert!(connection.on_control(&fin, &mut expected, space).is_some());
+        // Once closed, a new SYN starts the next connection
+        let syn = Packet::control(PacketState::Syn, 4);
+        assert!(connection.on_control(&syn, &mut expected, space).is_some());
+        assert_eq!(connection, Connection::Established { syn: 4 });
+        assert_eq!(expected, 5);
+    }
+
+    #[test]
+    fn test_time_wait() {
+        let now = Instant::now();
+        let fin = Packet::control(PacketState::Fin, 2);
+        let mut connection = Connection::Established { syn: 1 };
+        connection.on_control(&fin, &mut 2, SequenceSpace::default());
+        let mut time_wait = connection.time_wait(now).unwrap();
+        assert_eq!(time_wait.poll(now), None);
+        let next = time_wait.deadline().unwrap();
+        assert_eq!(next, now + FIN_ACK_INTERVAL);
+        assert_eq!(time_wait.poll(next).unwrap().kind, AckKind::FinAck);
+
+        let end = now + TIME_WAIT;
+        assert!(!time_wait.is_over(end - FIN_ACK_INTERVAL));
+        assert_eq!(time_wait.poll(end), None);
+        assert!(time_wait.is_over(end));
+        assert_eq!(time_wait.deadline(), None);
+    }
+
+    #[test]
+    fn test_time_wait_restart() {
+        let now = Instant::now();
+        let mut time_wait = TimeWait::new(Ack::new(2, AckKind::FinAck), now);
+        let fin_at = now + TIME_WAIT / 2;
+        time_wait.restart(fin_at);
+        assert!(!time_wait.is_over(now + TIME_WAIT));
+        assert!(time_wait.poll(now + TIME_WAIT).is_some());
+        assert!(time_wait.is_over(fin_at + TIME_WAIT));
+    }
+}
@@ -0,0 +1,280 @@
+use std::{
+    io::{Read, Write},
+    sync::mpsc::RecvTimeoutError,
+    time::Instant,
+};
+
+use crate::{
+    arq::{ArqReceiver, ArqSender, ReaderStats, SenderStats, TIMEOUT_TOTAL},
+    congestion::CongestionWindow,
+    connection,
+    error::{ArqError, Operation},
+    machine::{ArqReceiverMachine, ArqSenderMachine},
+    packet::{Ack, AckNumber, Packet, PacketState, Packetizer},
+    rto::RtoEstimator,
+    transport::{Channel, Transport},
+};
+
+/// Runs a sender machine on the wall clock over a `transport`, `mpsc`
+/// channels by default. Blocks on the transport until the machine's next
+/// timer instead of polling.
+pub struct SenderDriver<M: ?Sized, T = Channel<Packet, Ack>> {
+    transport: T,
+    initial_sequence: Option<AckNumber>,
+    pub(crate) machine: Box<M>,
+}
+
+impl<M: ArqSenderMachine + ?Sized, T: Transport<Packet, Ack>> SenderDriver<M, T> {
+    #[must_use]
+    pub fn new(transport: T, machine: impl Into<Box<M>>) -> Self {
+        Self {
+            transport,
+            initial_sequence: None,
+            machine: machine.into(),
+        }
+    }
+
+    /// Opens connections with `isn` instead of a random initial sequence number.
+    #[must_use]
+    pub fn with_initial_sequence(mut self, isn: AckNumber) -> Self {
+        self.initial_sequence = Some(isn);
+        self
+    }
+
+    /// Puts whatever the machine has to send on the wire, then waits for an
+    /// ACK until the machine's next timer or `deadline`.
+    fn step(&mut self, deadline: Instant) -> Result<(), ArqError> {
+        while let Some(packet) = self.machine.poll_transmit(Instant::now()) {
+            self.transport
+                .send(packet)
+                .map_err(|e| ArqError::PeerDisconnected {
+                    unsent: Some(e.0.number),
+                })?;
+        }
+        let wake = self
+            .machine
+            .poll_timeout()
+            .map_or(deadline, |timer| timer.min(deadline));
+        match self
+            .transport
+            .recv_timeout(wake.saturating_duration_since(Instant::now()))
+        {
+            Ok(ack) => self.machine.handle_ack(ack, Instant::now()),
+            Err(RecvTimeoutError::Timeout) => self.machine.handle_timeout(Instant::now()),
+            Err(RecvTimeoutError::Disconnected) => {
+                return Err(ArqError::PeerDisconnected { unsent: None });
+            }
+        }
+        Ok(())
+    }
+
+    /// Fails `operation` at the oldest packet still waiting for its ACK.
+    fn timeout(&self, operation: Operation) -> ArqError {
+        ArqError::Timeout {
+            operation,
+            number: self.machine.oldest_unacked(),
+        }
+    }
+
+    /// Steps the machine until `is_done`, failing `operation` after `TIMEOUT_TOTAL`.
+    fn run_until(
+        &mut self,
+        is_done: impl Fn(&M) -> bool,
+        operation: Operation,
+    ) -> Result<(), ArqError> {
+        let deadline = Instant::now() + TIMEOUT_TOTAL;
+        while !is_done(self.machine.as_ref()) {
+            if Instant::now() >= deadline {
+                return Err(self.timeout(operation));
+            }
+            self.step(deadline)?;
+        }
+        Ok(())
+    }
+}
+
+impl<M: ArqSenderMachine + ?Sized, T: Transport<Packet, Ack>> ArqSender for SenderDriver<M, T> {
+    fn connect(&mut self) -> Result<(), ArqError> {
+        let isn = self
+            .initial_sequence
+            .unwrap_or_else(|| connection::random_sequence(self.machine.space()));
+        self.machine.connect(isn, Instant::now());
+        self.run_until(M::is_connected, Operation::Connect)
+    }
+
+    fn send_stream(&mut self, source: &mut dyn Read) -> Result<(), ArqError> {
+        if !self.machine.is_connected() {
+            self.connect()?;
+        }
+        self.machine.start_message();
+        let mut packetizer = Packetizer::new(source);
+        let mut deadline = Instant::now() + TIMEOUT_TOTAL;
+        let mut oldest = self.machine.oldest_unacked();
+        loop {
+            while self.machine.has_room() {
+                let Some(packet) = packetizer.next_packet()? else {
+                    break;
+                };
+                self.machine.push(packet);
+            }
+            if packetizer.is_finished() && self.machine.is_idle() {
+                return Ok(());
+            }
+            if self.machine.oldest_unacked() != oldest {
+                // Only a transfer that stops advancing times out
+                oldest = self.machine.oldest_unacked();
+                deadline = Instant::now() + TIMEOUT_TOTAL;
+            }
+            if Instant::now() >= deadline {
+                return Err(self.timeout(Operation::Send));
+            }
+            self.step(deadline)?;
+        }
+    }
+
+    fn close(&mut self) -> Result<(), ArqError> {
+        if !self.machine.is_connected() {
+            return Ok(());
+        }
+        self.machine.close();
+        self.run_until(M::is_closed, Operation::Close)
+    }
+}
+
+impl<M: ArqSenderMachine + ?Sized, T> SenderStats for SenderDriver<M, T> {
+    fn efficiency_coefficient(&self) -> f64 {
+        self.machine.efficiency_coefficient()
+    }
+
+    fn retransmissions(&self) -> usize {
+        self.machine.retransmissions()
+    }
+
+    fn rto(&self) -> &RtoEstimator {
+        self.machine.rto()
+    }
+
+    fn congestion(&self) -> Option<&CongestionWindow> {
+        self.machine.congestion()
+    }
+}
+
+/// Runs a receiver machine on the wall clock over a `transport`, `mpsc`
+/// channels by default.
+pub struct ReaderDriver<M: ?Sized, T = Channel<Ack, Packet>> {
+    transport: T,
+    pub(crate) machine: Box<M>,
+}
+
+impl<M: ArqReceiverMachine + ?Sized, T: Transport<Ack, Packet>> ReaderDriver<M, T> {
+    #[must_use]
+    pub fn new(transport: T, machine: impl Into<Box<M>>) -> Self {
+        Self {
+            transport,
+            machine: machine.into(),
+        }
+    }
+
+    fn send_acks(&mut self) -> Result<(), ArqError> {
+        while let Some(ack) = self.machine.poll_transmit() {
+            self.transport
+                .send(ack)
+                .map_err(|e| ArqError::PeerDisconnected {
+                    unsent: Some(e.0.number),
+                })?;
+        }
+        Ok(())
+    }
+
+    /// Sends the pending ACKs, then waits for a packet until the machine's
+    /// next timer or `deadline`. Returns `false` once the sender is gone.
+    fn step(&mut self, deadline: Instant) -> Result<bool, ArqError> {
+        self.send_acks()?;
+        let wake = self
+            .machine
+            .poll_timeout()
+            .map_or(deadline, |timer| timer.min(deadline));
+        match self
+            .transport
+            .recv_timeout(wake.saturating_duration_since(Instant::now()))
+        {
+            Ok(packet) => self.machine.handle_packet(packet, Instant::now())?,
+            Err(RecvTimeoutError::Timeout) => self.machine.handle_timeout(Instant::now()),
+            // No FIN can come anymore
+            Err(RecvTimeoutError::Disconnected) if self.machine.is_closed() => return Ok(false),
+            Err(RecvTimeoutError::Disconnected) => {
+                return Err(ArqError::PeerDisconnected { unsent: None });
+            }
+        }
+        Ok(true)
+    }
+}
+
+impl<M: ArqReceiverMachine + ?Sized, T> ReaderStats for ReaderDriver<M, T> {
+    fn packets_read(&self) -> usize {
+        self.machine.packets_read()
+    }
+
+    fn duplicates(&self) -> usize {
+        self.machine.duplicates()
+    }
+}
+
+impl<M: ArqReceiverMachine + ?Sized, T: Transport<Ack, Packet>> ArqReceiver for ReaderDriver<M, T> {
+    fn read_stream(&mut self, sink: &mut dyn Write) -> Result<u64, ArqError> {
+        if self.machine.is_closed() {
+            return Err(ArqError::PeerClosed {
+                fin: self.machine.expected(),
+            });
+        }
+        let mut bytes_read = 0;
+        let mut deadline = Instant::now() + TIMEOUT_TOTAL;
+        loop {
+            while let Some(packet) = self.machine.poll_deliver() {
+                sink.write_all(packet.payload())?;
+                bytes_read += u64::from(packet.size);
+                deadline = Instant::now() + TIMEOUT_TOTAL;
+                if matches!(packet.state, PacketState::End) {
+                    sink.flush()?;
+                    self.send_acks()?;
+                    return Ok(bytes_read);
+                }
+            }
+            if Instant::now() >= deadline {
+                return Err(ArqError::Timeout {
+                    operation: Operation::Read,
+                    number: self
+                        .machine
+                        .is_established()
+                        .then(|| self.machine.expected()),
+                });
+            }
+            let is_established = self.machine.is_established();
+            self.step(deadline)?;
+            if !is_established && self.machine.is_established() {
+                // The message deadline starts with a new connection
+                deadline = Instant::now() + TIMEOUT_TOTAL;
+            }
+        }
+    }
+
+    fn close(&mut self) -> Result<(), ArqError> {
+        if !self.machine.is_established() && !self.machine.is_closed() {
+            return Ok(());
+        }
+        self.machine.close();
+        let deadline = Instant::now() + TIMEOUT_TOTAL;
+        while !self.machine.is_time_wait_over(Instant::now()) {
+            if Instant::now() >= deadline {
+                return Err(ArqError::Timeout {
+                    operation: Operation::Close,
+                    number: Some(self.machine.expected()),
+                });
+            }
+            if !self.step(deadline)? {
+                break;
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,571 @@
+use std::{
+    collections::VecDeque,
+    io::{Read, Write},
+    sync::mpsc::RecvTimeoutError,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    arq::{
+        ArqReceiver, ArqSender, BoxedReceiverMachine, BoxedSenderMachine, ReaderStats, SenderStats,
+        TIMEOUT_TOTAL,
+    },
+    congestion::CongestionWindow,
+    connection,
+    error::{ArqError, Operation},
+    machine::{ArqReceiverMachine, ArqSenderMachine},
+    packet::{Ack, AckKind, AckNumber, Packet, PacketState, Packetizer},
+    rto::RtoEstimator,
+    transport::{Channel, Transport},
+};
+
+/// How long an ACK waits for outgoing data to ride on by default. Half of
+/// `RTO_MIN`, so that a delayed ACK alone does not make the peer retransmit.
+pub const ACK_DELAY: Duration = Duration::from_millis(10);
+
+/// What one end of a duplex session puts on the link: a packet of its own
+/// messages, an ACK of the peer's packets, or an ACK riding on a packet.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Segment {
+    pub(crate) packet: Option<Packet>,
+    pub(crate) ack: Option<Ack>,
+}
+
+/// Both halves of an ARQ protocol sharing one link, without I/O.
+///
+/// ACKs of the peer's data wait up to the ACK delay for an outgoing packet
+/// to ride on and only go out alone once it expires. ACKs of the handshake
+/// and NAKs never wait.
+pub struct DuplexMachine {
+    sender: BoxedSenderMachine,
+    reader: BoxedReceiverMachine,
+    ack_delay: Duration,
+    acks: VecDeque<Ack>,
+    /// When the oldest ACK in `acks` goes out, with or without a packet.
+    ack_deadline: Option<Instant>,
+    piggybacked_acks: usize,
+    pure_acks: usize,
+}
+
+impl DuplexMachine {
+    #[must_use]
+    pub fn new(sender: BoxedSenderMachine, reader: BoxedReceiverMachine) -> Self {
+        Self {
+            sender,
+            reader,
+            ack_delay: ACK_DELAY,
+            acks: VecDeque::new(),
+            ack_deadline: None,
+            piggybacked_acks: 0,
+            pure_acks: 0,
+        }
+    }
+
+    /// A zero delay sends every ACK at once, it only rides on a packet that
+    /// happens to go out at the same time.
+    #[must_use]
+    pub fn with_ack_delay(mut self, ack_delay: Duration) -> Self {
+        self.ack_delay = ack_delay;
+        self
+    }
+
+    /// Half that sends our messages.
+    pub fn sender(&mut self) -> &mut dyn ArqSenderMachine {
+        self.sender.as_mut()
+    }
+
+    /// Half that reads the peer's messages.
+    pub fn reader(&mut self) -> &mut dyn ArqReceiverMachine {
+        self.reader.as_mut()
+    }
+
+    /// Number of ACKs that went out on a packet.
+    #[must_use]
+    pub fn piggybacked_acks(&self) -> usize {
+        self.piggybacked_acks
+    }
+
+    /// Number of segments that carried an ACK alone.
+    #[must_use]
+    pub fn pure_acks(&self) -> usize {
+        self.pure_acks
+    }
+
+    /// Takes the reader's ACKs, data ACKs are held back from `now` on. A
+    /// cumulative ACK that moves past the waiting ones replaces them, equal
+    /// ones stay for the sender to count as duplicates.
+    fn queue_acks(&mut self, now: Instant) {
+        while let Some(ack) = self.reader.poll_transmit() {
+            let is_data = |ack: &Ack| matches!(ack.kind, AckKind::Data | AckKind::Sack);
+            let due = if is_data(&ack) {
+                if self.reader.is_cumulative() {
+                    self.acks
+                        .retain(|queued| !is_data(queued) || queued.number == ack.number);
+                }
+                now + self.ack_delay
+            } else {
+                now
+            };
+            self.ack_deadline = Some(self.ack_deadline.map_or(due, |deadline| deadline.min(due)));
+            self.acks.push_back(ack);
+        }
+    }
+
+    /// Handles a segment that arrived at `now`. The peer's FIN only closes
+    /// its direction, the reader then tells `is_closed`.
+    pub fn handle_segment(&mut self, segment: Segment, now: Instant) -> Result<(), ArqError> {
+        if let Some(ack) = segment.ack {
+            self.sender.handle_ack(ack, now);
+        }
+        if let Some(packet) = segment.packet {
+            let was_closed = self.reader.is_closed();
+            let result = self.reader.handle_packet(packet, now);
+            self.queue_acks(now);
+            match result {
+                Err(_) if !was_closed && self.reader.is_closed() => {}
+                result => result?,
+            }
+        }
+        Ok(())
+    }
+
+    pub fn handle_timeout(&mut self, now: Instant) {
+        self.sender.handle_timeout(now);
+        self.reader.handle_timeout(now);
+        self.queue_acks(now);
+    }
+
+    /// When `handle_timeout` or `poll_transmit` has something to do next.
+    #[must_use]
+    pub fn poll_timeout(&self) -> Option<Instant> {
+        [
+            self.sender.poll_timeout(),
+            self.reader.poll_timeout(),
+            self.ack_deadline,
+        ]
+        .into_iter()
+        .flatten()
+        .min()
+    }
+
+    /// Next segment to put on the wire. A packet takes the oldest waiting
+    /// ACK along, ACKs go out alone once their delay is over. Cumulative
+    /// ACKs only wait until a later one replaces them, see `queue_acks`.
+    pub fn poll_transmit(&mut self, now: Instant) -> Option<Segment> {
+        let packet = self.sender.poll_transmit(now);
+        let is_ack_due = self.ack_deadline.is_some_and(|deadline| now >= deadline);
+        let ack = if packet.is_some() || is_ack_due {
+            self.acks.pop_front()
+        } else {
+            None
+        };
+        if self.acks.is_empty() {
+            self.ack_deadline = None;
+        }
+        match (&packet, ack) {
+            (None, None) => return None,
+            (Some(_), Some(_)) => self.piggybacked_acks += 1,
+            (None, Some(_)) => self.pure_acks += 1,
+            (Some(_), None) => {}
+        }
+        Some(Segment { packet, ack })
+    }
+}
+
+/// Runs a duplex machine on the wall clock over a `transport`, `mpsc`
+/// channels by default.
+///
+/// Sending and reading happen on the same thread: while `send_stream`
+/// waits for its ACKs, the peer's packets are acknowledged and kept for the
+/// next `read_stream`, and the other way around. A request/response exchange
+/// needs no second thread, the ACK of a request rides on its response.
+/// ACKs only go out while the session is driven by one of its methods.
+pub struct Duplex<T = Channel<Segment, Segment>> {
+    transport: T,
+    initial_sequence: Option<AckNumber>,
+    pub(crate) machine: DuplexMachine,
+}
+
+impl<T: Transport<Segment, Segment>> Duplex<T> {
+    #[must_use]
+    pub fn new(transport: T, machine: DuplexMachine) -> Self {
+        Self {
+            transport,
+            initial_sequence: None,
+            machine,
+        }
+    }
+
+    /// Opens connections with `isn` instead of a random initial sequence number.
+    #[must_use]
+    pub fn with_initial_sequence(mut self, isn: AckNumber) -> Self {
+        self.initial_sequence = Some(isn);
+        self
+    }
+
+    #[must_use]
+    pub fn piggybacked_acks(&self) -> usize {
+        self.machine.piggybacked_acks()
+    }
+
+    #[must_use]
+    pub fn pure_acks(&self) -> usize {
+        self.machine.pure_acks()
+    }
+
+    /// Closes both directions: our FIN is acknowledged first, then the
+    /// session lingers until the peer's FIN.
+    pub fn close(&mut self) -> Result<(), ArqError> {
+        ArqSender::close(self)?;
+        ArqReceiver::close(self)
+    }
+
+    /// Puts whatever the machine has to send on the wire, then waits for a
+    /// segment until the machine's next timer or `deadline`. Returns `false`
+    /// once the peer closed its direction and is gone.
+    fn step(&mut self, deadline: Instant) -> Result<bool, ArqError> {
+        while let Some(segment) = self.machine.poll_transmit(Instant::now()) {
+            self.transport.send(segment).map_err(|e| {
+                let segment = e.0;
+                ArqError::PeerDisconnected {
+                    unsent: segment
+                        .packet
+                        .map(|packet| packet.number)
+                        .or(segment.ack.map(|ack| ack.number)),
+                }
+            })?;
+        }
+        let wake = self
+            .machine
+            .poll_timeout()
+            .map_or(deadline, |timer| timer.min(deadline));
+        match self
+            .transport
+            .recv_timeout(wake.saturating_duration_since(Instant::now()))
+        {
+            Ok(segment) => self.machine.handle_segment(segment, Instant::now())?,
+            Err(RecvTimeoutError::Timeout) => self.machine.handle_timeout(Instant::now()),
+            Err(RecvTimeoutError::Disconnected) if self.machine.reader.is_closed() => {
+                return Ok(false);
+            }
+            Err(RecvTimeoutError::Disconnected) => {
+                return Err(ArqError::PeerDisconnected { unsent: None });
+            }
+        }
+        Ok(true)
+    }
+
+    /// Fails `operation` of our direction at the oldest packet still
+    /// waiting for its ACK.
+    fn timeout(&self, operation: Operation) -> ArqError {
+        ArqError::Timeout {
+            operation,
+            number: self.machine.sender.oldest_unacked(),
+        }
+    }
+
+    fn peer_closed(&self) -> ArqError {
+        ArqError::PeerClosed {
+            fin: self.machine.reader.expected(),
+        }
+    }
+
+    /// Steps the machine until `is_done`, failing `operation` after
+    /// `TIMEOUT_TOTAL` or once the peer is gone.
+    fn run_until(
+        &mut self,
+        is_done: impl Fn(&DuplexMachine) -> bool,
+        operation: Operation,
+    ) -> Result<(), ArqError> {
+        let deadline = Instant::now() + TIMEOUT_TOTAL;
+        while !is_done(&self.machine) {
+            if Instant::now() >= deadline {
+                return Err(self.timeout(operation));
+            }
+            if !self.step(deadline)? {
+                return Err(self.peer_closed());
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<T: Transport<Segment, Segment>> ArqSender for Duplex<T> {
+    fn connect(&mut self) -> Result<(), ArqError> {
+        let isn = self
+            .initial_sequence
+            .unwrap_or_else(|| connection::random_sequence(self.machine.sender.space()));
+        self.machine.sender.connect(isn, Instant::now());
+        self.run_until(|m| m.sender.is_connected(), Operation::Connect)
+    }
+
+    fn send_stream(&mut self, source: &mut dyn Read) -> Result<(), ArqError> {
+        if !self.machine.sender.is_connected() {
+            self.connect()?;
+        }
+        self.machine.sender.start_message();
+        let mut packetizer = Packetizer::new(source);
+        let mut deadline = Instant::now() + TIMEOUT_TOTAL;
+        let mut oldest = self.machine.sender.oldest_unacked();
+        loop {
+            while self.machine.sender.has_room() {
+                let Some(packet) = packetizer.next_packet()? else {
+                    break;
+                };
+                self.machine.sender.push(packet);
+            }
+            if packetizer.is_finished() && self.machine.sender.is_idle() {
+                return Ok(());
+            }
+            if self.machine.sender.oldest_unacked() != oldest {
+                // Only a transfer that stops advancing times out
+                oldest = self.machine.sender.oldest_unacked();
+                deadline = Instant::now() + TIMEOUT_TOTAL;
+            }
+            if Instant::now() >= deadline {
+                return Err(self.timeout(Operation::Send));
+            }
+            if !self.step(deadline)? {
+                return Err(self.peer_closed());
+            }
+        }
+    }
+
+    /// Closes our direction only, the peer may still send.
+    fn close(&mut self) -> Result<(), ArqError> {
+        if !self.machine.sender.is_connected() {
+            return Ok(());
+        }
+        self.machine.sender.close();
+        self.run_until(|m| m.sender.is_closed(), Operation::Close)
+    }
+}
+
+impl<T: Transport<Segment, Segment>> ArqReceiver for Duplex<T> {
+    fn read_stream(&mut self, sink: &mut dyn Write) -> Result<u64, ArqError> {
+        let mut bytes_read = 0;
+        let mut deadline = Instant::now() + TIMEOUT_TOTAL;
+        loop {
+            while let Some(packet) = self.machine.reader.poll_deliver() {
+                sink.write_all(packet.payload())?;
+                bytes_read += u64::from(packet.size);
+                deadline = Instant::now() + TIMEOUT_TOTAL;
+                if matches!(packet.state, PacketState::End) {
+                    sink.flush()?;
+                    return Ok(bytes_read);
+                }
+            }
+            if self.machine.reader.is_closed() {
+                return Err(self.peer_closed());
+            }
+            if Instant::now() >= deadline {
+                let reader = &self.machine.reader;
+                return Err(ArqError::Timeout {
+                    operation: Operation::Read,
+                    number: reader.is_established().then(|| reader.expected()),
+                });
+            }
+            let is_established = self.machine.reader.is_established();
+            self.step(deadline)?;
+            if !is_established && self.machine.reader.is_established() {
+                // The message deadline starts with a new connection
+                deadline = Instant::now() + TIMEOUT_TOTAL;
+            }
+        }
+    }
+
+    /// Waits for the peer's FIN and lingers after it, our direction stays open.
+    fn close(&mut self) -> Result<(), ArqError> {
+        if !self.machine.reader.is_established() && !self.machine.reader.is_closed() {
+            return Ok(());
+        }
+        self.machine.reader.close();
+        let deadline = Instant::now() + TIMEOUT_TOTAL;
+        while !self.machine.reader.is_time_wait_over(Instant::now()) {
+            if Instant::now() >= deadline {
+                return Err(ArqError::Timeout {
+                    operation: Operation::Close,
+                    number: Some(self.machine.reader.expected()),
+                });
+            }
+            if !self.step(deadline)? {
+                break;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<T> ReaderStats for Duplex<T> {
+    fn packets_read(&self) -> usize {
+        self.machine.reader.packets_read()
+    }
+
+    fn duplicates(&self) -> usize {
+        self.machine.reader.duplicates()
+    }
+}
+
+impl<T> SenderStats for Duplex<T> {
+    fn efficiency_coefficient(&self) -> f64 {
+        self.machine.sender.efficiency_coefficient()
+    }
+
+    fn retransmissions(&self) -> usize {
+        self.machine.sender.retransmissions()
+    }
+
+    fn rto(&self) -> &RtoEstimator {
+        self.machine.sender.rto()
+    }
+
+    fn congestion(&self) -> Option<&CongestionWindow> {
+        self.machine.sender.congestion()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::*;
+    use crate::{
+        arq::{ArqConfig, Protocol},
+        gobackn,
+        packet::DATA_SIZE,
+        selective_repeat,
+        transport::{self, Lossy},
+    };
+
+    fn reverse(message: &str) -> String {
+        message.chars().rev().collect()
+    }
+
+    /// Answers every request with its reversal until the client closes.
+    /// Returns the number of requests served.
+    fn serve<T: Transport<Segment, Segment>>(server: &mut Duplex<T>) -> Result<usize, ArqError> {
+        let mut requests = 0;
+        loop {
+            match server.read() {
+                Ok(request) => server.send(&reverse(&request))?,
+                Err(_) if server.machine.reader.is_closed() => break,
+                Err(e) => return Err(e),
+            }
+            requests += 1;
+        }
+        server.close()?;
+        Ok(requests)
+    }
+
+    fn check_requests<T>(make: impl Fn() -> (T, T), config: &ArqConfig)
+    where
+        T: Transport<Segment, Segment> + Send,
+    {
+        for protocol in Protocol::ALL {
+            let (client_transport, server_transport) = make();
+            let mut client = protocol.duplex(client_transport, config).unwrap();
+            let mut server = protocol.duplex(server_transport, config).unwrap();
+            thread::scope(|s| {
+                let handle = s.spawn(|| serve(&mut server));
+                for i in 0..3 {
+                    let request = format!("request {i} ").repeat(100 * i + 1);
+                    client.send(&request).unwrap();
+                    let response = client.read().unwrap();
+                    assert_eq!(response, reverse(&request), "{}", protocol.name());
+                }
+                client.close().unwrap();
+                assert_eq!(handle.join().unwrap().unwrap(), 3, "{}", protocol.name());
+            });
+        }
+    }
+
+    #[test]
+    fn test_duplex_requests() {
+        check_requests(transport::channel, &ArqConfig::new(4));
+        check_requests(
+            || {
+                let (client, server) = transport::crossbeam_channel();
+                (
+                    Lossy::new(client, 0.2).with_seed(0),
+                    Lossy::new(server, 0.2).with_seed(1),
+                )
+            },
+            &ArqConfig::new(4).with_naks(true),
+        );
+    }
+
+    #[test]
+    fn test_piggybacked_acks() {
+        let request = "P".repeat(2_000);
+        // ACKs the server sent until its response was acknowledged, the
+        // FIN-ACKs repeated while closing are left out
+        let run = |ack_delay| {
+            let config = ArqConfig::new(4).with_ack_delay(ack_delay);
+            let (client_transport, server_transport) = transport::channel();
+            let mut client = Protocol::GoBackN.duplex(client_transport, &config).unwrap();
+            let mut server = Protocol::GoBackN.duplex(server_transport, &config).unwrap();
+            thread::scope(|s| {
+                let handle = s.spawn(|| {
+                    let request = server.read().unwrap();
+                    server.send(&reverse(&request)).unwrap();
+                    let acks = (server.piggybacked_acks(), server.pure_acks());
+                    assert!(matches!(server.read(), Err(ArqError::PeerClosed { .. })));
+                    server.close().unwrap();
+                    acks
+                });
+                client.send(&request).unwrap();
+                assert_eq!(client.read().unwrap(), reverse(&request));
+                client.close().unwrap();
+                handle.join().unwrap()
+            })
+        };
+        let (_, immediate_pure) = run(Duration::ZERO);
+        // The ACKs of the request's last packets wait for the response
+        let (delayed_piggybacked, delayed_pure) = run(ACK_DELAY);
+        assert!(delayed_piggybacked > 0);
+        assert!(
+            delayed_pure < immediate_pure,
+            "{delayed_pure} >= {immediate_pure}"
+        );
+    }
+
+    #[test]
+    fn test_cumulative_acks_collapse() {
+        let now = Instant::now();
+        let segment = |packet| Segment {
+            packet: Some(packet),
+            ack: None,
+        };
+        let readers: [(BoxedReceiverMachine, &[AckNumber]); 2] = [
+            (Box::new(gobackn::ReaderMachine::new(false)), &[12]),
+            (
+                Box::new(selective_repeat::ReaderMachine::new(4, false)),
+                &[10, 11, 12],
+            ),
+        ];
+        for (reader, expected) in readers {
+            let sender = Box::new(gobackn::SenderMachine::new(4, false));
+            let mut machine = DuplexMachine::new(sender, reader);
+            let syn = Packet::control(PacketState::Syn, 9);
+            machine.handle_segment(segment(syn), now).unwrap();
+            assert!(machine.poll_transmit(now).is_some());
+            for (number, state) in [
+                (10, PacketState::Begin),
+                (11, PacketState::Ongoing),
+                (12, PacketState::End),
+            ] {
+                let packet = Packet::new(number, [0; DATA_SIZE], 1, state);
+                machine.handle_segment(segment(packet), now).unwrap();
+            }
+            // Our SYN goes out at once and takes the ACKs along
+            machine.sender().connect(0, now);
+            let mut acks: Vec<AckNumber> = Vec::new();
+            for at in [now, now + ACK_DELAY] {
+                while let Some(segment) = machine.poll_transmit(at) {
+                    acks.extend(segment.ack.map(|ack| ack.number));
+                }
+            }
+            assert_eq!(acks, expected);
+        }
+    }
+}
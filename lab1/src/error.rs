@@ -0,0 +1,250 @@
+use std::{fmt, io, string::FromUtf8Error};
+
+use crate::{packet::AckNumber, wire::DecodeError};
+
+/// Step of a transfer that can run out of time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operation {
+    /// The SYN/SYN-ACK handshake.
+    Connect,
+    Send,
+    Read,
+    /// The FIN/FIN-ACK exchange, or lingering after it.
+    Close,
+}
+
+/// Way a packet breaks the protocol.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Violation {
+    /// The first packet of a message is not a `Begin` packet.
+    MissingBegin,
+    /// A `Begin` packet in the middle of a message.
+    UnexpectedBegin,
+    /// New data while the reader is closing: the sender did not get the
+    /// ACKs of a message the reader already finished.
+    DataWhileClosing,
+}
+
+/// Error of an ARQ endpoint, its machine or its configuration.
+#[derive(Debug)]
+pub enum ArqError {
+    /// `operation` made no progress for `TIMEOUT_TOTAL`. `number` is the
+    /// oldest packet still waiting for its ACK on the sending side and the
+    /// next packet in order on the reading side, if there is one.
+    Timeout {
+        operation: Operation,
+        number: Option<AckNumber>,
+    },
+    /// The peer's end of the link is gone. `unsent` is the packet or ACK
+    /// that could not go out, `None` if nothing came in anymore.
+    PeerDisconnected { unsent: Option<AckNumber> },
+    /// The peer closed the connection with a FIN numbered `fin`.
+    PeerClosed { fin: AckNumber },
+    /// Packet `number` breaks the protocol.
+    ProtocolViolation {
+        violation: Violation,
+        number: AckNumber,
+    },
+    /// A datagram is not a packet or an ACK.
+    Decode(DecodeError),
+    /// A message that should be text is not valid UTF-8.
+    Utf8(FromUtf8Error),
+    /// Reading the message to send or writing the received one failed.
+    Io(io::Error),
+    /// Sequence numbers must have from 1 to `AckNumber::BITS` bits.
+    SequenceBits { bits: u32 },
+    /// A window must hold at least one packet.
+    ZeroWindow,
+    /// A window of `window_size` packets does not fit into a `bits`-bit
+    /// sequence space, the protocol allows at most `max_window`.
+    WindowTooLarge {
+        window_size: AckNumber,
+        bits: u32,
+        max_window: u64,
+    },
+    /// An ACK carries at most `packet::MAX_SACK_BLOCKS` SACK blocks.
+    TooManySackBlocks { sack_blocks: usize },
+}
+
+impl fmt::Display for Operation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Connect => "Connection",
+            Self::Send => "Message send",
+            Self::Read => "Message read",
+            Self::Close => "Connection close",
+        })
+    }
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::MissingBegin => "does not correspond to the start of the message",
+            Self::UnexpectedBegin => "starts a message in the middle of another one",
+            Self::DataWhileClosing => "is unexpected data while closing the connection",
+        })
+    }
+}
+
+impl fmt::Display for ArqError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Timeout {
+                operation,
+                number: Some(number),
+            } => write!(f, "{operation} timeout at packet {number}"),
+            Self::Timeout {
+                operation,
+                number: None,
+            } => write!(f, "{operation} timeout"),
+            Self::PeerDisconnected {
+                unsent: Some(number),
+            } => write!(f, "Peer disconnected, failed to send {number}"),
+            Self::PeerDisconnected { unsent: None } => write!(f, "Peer disconnected"),
+            Self::PeerClosed { fin } => write!(f, "Connection closed by the peer with FIN {fin}"),
+            Self::ProtocolViolation { violation, number } => {
+                write!(f, "Packet {number} {violation}")
+            }
+            Self::Decode(e) => write!(f, "Failed to decode a datagram: {e}"),
+            Self::Utf8(e) => write!(f, "Failed to encode the message: {e}"),
+            Self::Io(e) => write!(f, "Failed to transfer the message: {e}"),
+            Self::SequenceBits { bits } => write!(
+                f,
+                "Sequence number must have from 1 to {} bits, got {bits}",
+                AckNumber::BITS
+            ),
+            Self::ZeroWindow => write!(f, "Window size must be at least 1"),
+            Self::WindowTooLarge {
+                window_size,
+                bits,
+                max_window,
+            } => write!(
+                f,
+                "Window size {window_size} does not fit into a {bits}-bit sequence space, \
+                 maximum is {max_window}"
+            ),
+            Self::TooManySackBlocks { sack_blocks } => write!(
+                f,
+                "At most {} SACK blocks fit into an ACK, got {sack_blocks}",
+                crate::packet::MAX_SACK_BLOCKS
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ArqError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Decode(e) => Some(e),
+            Self::Utf8(e) => Some(e),
+            Self::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<DecodeError> for ArqError {
+    fn from(e: DecodeError) -> Self {
+        Self::Decode(e)
+    }
+}
+
+impl From<FromUtf8Error> for ArqError {
+    fn from(e: FromUtf8Error) -> Self {
+        Self::Utf8(e)
+    }
+}
+
+impl From<io::Error> for ArqError {
+    fn from(e: io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use super::*;
+    use crate::{
+        gobackn::ReaderMachine,
+        machine::ArqReceiverMachine,
+        packet::{DATA_SIZE, Packet, PacketState},
+    };
+
+    fn packet(number: AckNumber, state: PacketState) -> Packet {
+        Packet::new(number, [0; DATA_SIZE], 1, state)
+    }
+
+    #[test]
+    fn test_protocol_violations() {
+        let now = Instant::now();
+        let mut reader = ReaderMachine::new(false);
+        reader
+            .handle_packet(Packet::control(PacketState::Syn, 9), now)
+            .unwrap();
+        let error = reader
+            .handle_packet(packet(10, PacketState::Ongoing), now)
+            .unwrap_err();
+        assert!(matches!(
+            error,
+            ArqError::ProtocolViolation {
+                violation: Violation::MissingBegin,
+                number: 10
+            }
+        ));
+        assert_eq!(
+            error.to_string(),
+            "Packet 10 does not correspond to the start of the message"
+        );
+
+        reader
+            .handle_packet(packet(10, PacketState::Begin), now)
+            .unwrap();
+        let error = reader
+            .handle_packet(packet(11, PacketState::Begin), now)
+            .unwrap_err();
+        assert!(matches!(
+            error,
+            ArqError::ProtocolViolation {
+                violation: Violation::UnexpectedBegin,
+                number: 11
+            }
+        ));
+    }
+
+    #[test]
+    fn test_peer_closed() {
+        let now = Instant::now();
+        let mut reader = ReaderMachine::new(false);
+        reader
+            .handle_packet(Packet::control(PacketState::Syn, 9), now)
+            .unwrap();
+        let error = reader
+            .handle_packet(Packet::control(PacketState::Fin, 10), now)
+            .unwrap_err();
+        assert!(matches!(error, ArqError::PeerClosed { fin: 10 }));
+        assert_eq!(reader.expected(), 10);
+    }
+
+    #[test]
+    fn test_display() {
+        let timeout = ArqError::Timeout {
+            operation: Operation::Send,
+            number: Some(7),
+        };
+        assert_eq!(timeout.to_string(), "Message send timeout at packet 7");
+        let timeout = ArqError::Timeout {
+            operation: Operation::Connect,
+            number: None,
+        };
+        assert_eq!(timeout.to_string(), "Connection timeout");
+        let decode = ArqError::from(DecodeError::UnknownAckKind(9));
+        assert_eq!(
+            decode.to_string(),
+            "Failed to decode a datagram: unknown ACK kind 9"
+        );
+        assert!(std::error::Error::source(&decode).is_some());
+    }
+}
@@ -1,337 +1,498 @@
-use std::{
-    collections::VecDeque,
-    sync::mpsc::{self, TryRecvError},
-    thread,
-    time::{Duration, Instant},
-};
-
-use crate::simulate_loss;
-
-const DATA_SIZE: usize = u8::MAX as usize;
-const TIMEOUT: Duration = Duration::from_millis(200);
-const TIMEOUT_TOTAL: Duration = Duration::from_secs(30);
-
-#[derive(Debug, Clone, Copy)]
-enum PacketState {
-    Begin,
-    Ongoing,
-    End,
-}
+use std::{collections::VecDeque, time::Instant};
 
-type AckNumber = u32;
+use crate::{
+    arq::{Protocol, ReaderStats, SenderStats},
+    congestion::{CongestionAlgorithm, CongestionControl, CongestionWindow},
+    connection::SenderConnection,
+    driver::{ReaderDriver, SenderDriver},
+    error::ArqError,
+    machine::{ArqReceiverMachine, ArqSenderMachine, ReaderCore},
+    packet::{Ack, AckKind, AckNumber, Packet},
+    rto::RtoEstimator,
+    seq::SequenceSpace,
+    stats::TransferStats,
+};
 
 #[derive(Debug, Clone)]
-pub struct Packet {
-    number: AckNumber,
-    data: [u8; DATA_SIZE],
-    size: u8,
-    state: PacketState,
+struct SenderPacket {
+    packet: Packet,
+    last_sent: Option<Instant>,
+    is_retransmitted: bool,
 }
 
-pub struct Sender {
-    tx: mpsc::Sender<Packet>,
-    rx: mpsc::Receiver<u32>,
+/// Go-Back-N sender over `mpsc` channels.
+pub type Sender = SenderDriver<SenderMachine>;
+/// Go-Back-N reader over `mpsc` channels.
+pub type Reader = ReaderDriver<ReaderMachine>;
+
+/// Go-Back-N sender without I/O. A single timer runs for the oldest packet
+/// in flight, when it expires the whole window is sent again.
+pub struct SenderMachine {
     window_size: AckNumber,
-    base: AckNumber,
+    /// Absolute index of the oldest unacknowledged packet, keeps growing
+    /// across the messages of a connection.
+    base: usize,
     packets_total: usize,
     packets_send: usize,
     packets_ack: usize,
-    packets_to_send: VecDeque<Packet>,
+    window: VecDeque<SenderPacket>,
+    /// Packets of `window` from this index on are yet to be (re)sent.
+    next: usize,
+    /// Retransmission deadline of the oldest packet in flight.
+    timer: Option<Instant>,
+    /// Duplicate ACKs that resend the window without waiting for `timer`.
+    fast_retransmit: Option<usize>,
+    duplicate_acks: usize,
+    /// Whether the window was sent again since `base` last moved, further
+    /// duplicate ACKs come from packets that were already in flight.
+    is_recovering: bool,
+    connection: SenderConnection,
+    rto: RtoEstimator,
+    congestion: CongestionWindow,
+    space: SequenceSpace,
+    is_windowed: bool,
     is_debug: bool,
 }
 
-impl Sender {
-    #[must_use] 
-    pub fn new(
-        tx: mpsc::Sender<Packet>,
-        rx: mpsc::Receiver<AckNumber>,
-        window_size: AckNumber,
-        is_debug: bool,
-    ) -> Self {
+impl SenderMachine {
+    #[must_use]
+    pub fn new(window_size: AckNumber, is_debug: bool) -> Self {
         Self {
-            tx,
-            rx,
             window_size,
             base: 0,
             packets_total: 0,
             packets_send: 0,
             packets_ack: 0,
-            packets_to_send: VecDeque::with_capacity(window_size as usize),
+            window: VecDeque::with_capacity(window_size as usize),
+            next: 0,
+            timer: None,
+            fast_retransmit: None,
+            duplicate_acks: 0,
+            is_recovering: false,
+            connection: SenderConnection::default(),
+            rto: RtoEstimator::new(),
+            congestion: CongestionWindow::new(CongestionAlgorithm::Fixed.build(), window_size),
+            space: SequenceSpace::default(),
+            is_windowed: true,
             is_debug,
         }
     }
 
-    fn reset(&mut self, message: &str) {
-        self.base = 0;
-        self.packets_total = message.len().div_ceil(DATA_SIZE).max(2);
+    /// Stop-and-Wait is Go-Back-N with a single packet in flight, numbered
+    /// with one bit.
+    #[must_use]
+    pub fn stop_and_wait(is_debug: bool) -> Self {
+        let mut machine = Self::new(1, is_debug);
+        machine.space = SequenceSpace::ALTERNATING_BIT;
+        machine.is_windowed = false;
+        machine
+    }
+
+    /// Numbers packets modulo `2^bits`. Fails unless the window is at most `2^bits - 1`.
+    pub fn with_sequence_bits(mut self, bits: u32) -> Result<Self, ArqError> {
+        let space = SequenceSpace::new(bits)?;
+        space.check_window(self.window_size, space.max_window_gbn())?;
+        self.space = space;
+        Ok(self)
+    }
+
+    /// Limits the window to `min(cwnd, window_size)` using `control`.
+    #[must_use]
+    pub fn with_congestion_control(mut self, control: Box<dyn CongestionControl>) -> Self {
+        self.congestion = CongestionWindow::new(control, self.window_size);
+        self
+    }
+
+    /// Resends the window after `duplicate_acks` ACKs of the packet before
+    /// the base, TCP-style fast retransmit. `None` waits for the timer.
+    #[must_use]
+    pub fn with_fast_retransmit(mut self, duplicate_acks: Option<usize>) -> Self {
+        self.fast_retransmit = duplicate_acks;
+        self
+    }
+
+    /// Slides the window past `count` acknowledged packets.
+    fn acknowledge(&mut self, count: usize) {
+        if count > 0 {
+            self.duplicate_acks = 0;
+            self.is_recovering = false;
+        }
+        for _ in 0..count {
+            self.window.pop_front();
+            self.packets_ack += 1;
+            self.base += 1;
+            self.next -= 1;
+        }
+    }
+
+    /// Goes back to the packet the reader is missing without waiting for
+    /// the timer. The packets before it have arrived.
+    fn handle_nak(&mut self, number: AckNumber, now: Instant) {
+        let offset = self.space.distance(self.space.wrap(self.base), number) as usize;
+        if offset >= self.next {
+            return;
+        }
+        self.acknowledge(offset);
+        if offset > 0 {
+            self.congestion.on_ack(offset as u32, now);
+        }
+        self.congestion.on_loss(now);
+        self.next = 0;
+        self.timer = None;
+        self.is_recovering = true;
+        if self.is_debug {
+            eprintln!(
+                "Sender | Nak packet: {}, resend from base {}",
+                number, self.base
+            );
+        }
+    }
+
+    /// Counts an ACK of the packet before the base, the reader got a packet
+    /// past a gap. Enough of them send the window again, once until the base
+    /// moves.
+    fn handle_duplicate_ack(&mut self, now: Instant) {
+        let Some(threshold) = self.fast_retransmit else {
+            return;
+        };
+        if self.is_recovering {
+            return;
+        }
+        self.duplicate_acks += 1;
+        if self.duplicate_acks < threshold {
+            return;
+        }
+        self.congestion.on_loss(now);
+        self.next = 0;
+        self.timer = None;
+        self.duplicate_acks = 0;
+        self.is_recovering = true;
+        if self.is_debug {
+            eprintln!("Sender | Fast retransmit from base {}", self.base);
+        }
+    }
+}
+
+impl ArqSenderMachine for SenderMachine {
+    fn space(&self) -> SequenceSpace {
+        self.space
+    }
+
+    fn connect(&mut self, isn: AckNumber, now: Instant) {
+        self.rto.reset(now);
+        self.congestion.reset(now);
+        self.window.clear();
+        self.next = 0;
+        self.timer = None;
+        self.duplicate_acks = 0;
+        self.is_recovering = false;
+        let isn = self.space.wrap(isn as usize);
+        self.base = isn as usize + 1;
+        self.connection.connect(isn);
+    }
+
+    fn is_connected(&self) -> bool {
+        self.connection.is_established()
+    }
+
+    fn start_message(&mut self) {
+        self.packets_total = 0;
         self.packets_send = 0;
         self.packets_ack = 0;
-        self.packets_to_send.clear();
-    }
-
-    fn window_end(&self) -> AckNumber {
-        (self.base + self.window_size).min(self.packets_total as u32)
-    }
-
-    fn prepare_packets(&mut self, message: &str) {
-        let bytes = message.as_bytes();
-        let start = self.base as usize;
-        let end = self.window_end() as usize;
-        let current_in_window = self.packets_to_send.len();
-        let packets = (start + current_in_window..end).map(|number| {
-            let data_start = DATA_SIZE * number;
-            let mut data = [0; DATA_SIZE];
-            let data_size = match bytes.len().checked_sub(data_start) {
-                Some(data_size) => {
-                    let data_size = DATA_SIZE.min(data_size);
-                    data[..data_size].copy_from_slice(&bytes[data_start..(data_start + data_size)]);
-                    data_size
-                }
-                None => 0,
-            };
-            let state = if number == 0 {
-                PacketState::Begin
-            } else if number + 1 == self.packets_total {
-                PacketState::End
-            } else {
-                PacketState::Ongoing
-            };
-            Packet {
-                number: number as AckNumber,
-                data,
-                size: data_size as u8,
-                state,
-            }
+    }
+
+    fn has_room(&self) -> bool {
+        self.is_connected() && self.window.len() < self.congestion.effective() as usize
+    }
+
+    fn push(&mut self, mut packet: Packet) {
+        packet.set_number(self.space.wrap(self.base + self.window.len()));
+        self.window.push_back(SenderPacket {
+            packet,
+            last_sent: None,
+            is_retransmitted: false,
         });
-        self.packets_to_send.extend(packets);
+        self.packets_total += 1;
     }
 
-    fn send_packet(&mut self, packet: Packet) -> Result<(), String> {
-        let number = packet.number;
-        let size = packet.size;
-        let state = packet.state;
-        if let Err(e) = self.tx.send(packet) {
-            return Err(format!(
-                "Failed to send packet {}, base {}: {e}",
-                number, self.base
-            ));
+    fn is_idle(&self) -> bool {
+        self.window.is_empty()
+    }
+
+    fn close(&mut self) {
+        self.connection.close(self.space.wrap(self.base));
+    }
+
+    fn is_closed(&self) -> bool {
+        self.connection.is_closed()
+    }
+
+    fn oldest_unacked(&self) -> Option<AckNumber> {
+        self.connection
+            .pending()
+            .or_else(|| self.window.front().map(|p| p.packet.number))
+    }
+
+    fn handle_ack(&mut self, ack: Ack, now: Instant) {
+        if self.connection.on_ack(ack, &mut self.rto, now) {
+            return;
         }
-        self.packets_send += 1;
+        if ack.kind == AckKind::Nak {
+            self.handle_nak(ack.number, now);
+            return;
+        }
+        let offset = self.space.distance(self.space.wrap(self.base), ack.number);
+        if offset as usize >= self.next {
+            if self.next > 0 && ack.number == self.space.wrap(self.base - 1) {
+                self.handle_duplicate_ack(now);
+            }
+            return;
+        }
+        // Karn's algorithm: a retransmitted packet gives an ambiguous sample
+        let acked = &self.window[offset as usize];
+        if let (false, Some(last_sent)) = (acked.is_retransmitted, acked.last_sent) {
+            self.rto
+                .on_sample(now.saturating_duration_since(last_sent), now);
+        }
+        self.rto.on_progress(now);
+        self.acknowledge(offset as usize + 1);
+        self.congestion.on_ack(offset + 1, now);
+        // The timer restarts for the next packet in flight
+        self.timer = (self.next > 0).then(|| now + self.rto.current());
         if self.is_debug {
             eprintln!(
-                "Sender | Send packet: {}, size: {}, state: {:?}, window_size: {}",
-                number, size, state, self.window_size
+                "Sender | Ack up to packet: {}, {} out of {}",
+                ack.number, self.packets_ack, self.packets_total
             );
         }
-        Ok(())
     }
 
-    pub fn send(&mut self, message: &str) -> Result<(), String> {
-        self.reset(message);
-        let time = Instant::now();
-        while self.packets_ack < self.packets_total {
-            if time.elapsed() > TIMEOUT_TOTAL {
-                return Err("Message send timeout".to_string());
-            }
-            self.prepare_packets(message);
-            let packets = self.packets_to_send.clone();
-            for packet in packets {
-                self.send_packet(packet)?;
+    fn handle_timeout(&mut self, now: Instant) {
+        self.connection.on_timeout(&mut self.rto, now);
+        if let Some(timer) = self.timer
+            && now >= timer
+        {
+            self.rto.on_timeout(now);
+            self.congestion.on_timeout(now);
+            self.next = 0;
+            self.timer = None;
+            self.duplicate_acks = 0;
+            if self.is_debug {
+                eprintln!(
+                    "Sender | Timeout at base {}, rto: {}ms",
+                    self.base,
+                    self.rto.current().as_millis()
+                );
             }
-            self.ack()?;
         }
-        Ok(())
     }
 
-    fn ack(&mut self) -> Result<(), String> {
-        let end = self.window_end();
-        let time = Instant::now();
-        while !self.packets_to_send.is_empty() && time.elapsed() < TIMEOUT {
-            match self.rx.try_recv() {
-                Ok(number) => {
-                    if !(number >= self.base && number < end) {
-                        continue;
-                    }
-                    for _ in self.base..=number {
-                        self.packets_to_send.pop_front();
-                        self.packets_ack += 1;
-                        self.base += 1;
-                    }
-                    if self.is_debug {
-                        eprintln!(
-                            "Sender | Ack up to packet: {}, {} out of {}",
-                            number, self.packets_ack, self.packets_total
-                        );
-                    }
-                }
-                Err(TryRecvError::Empty) => {
-                    thread::sleep(Duration::from_millis(1));
-                }
-                Err(e @ TryRecvError::Disconnected) => {
-                    return Err(format!("Failed to receive ACK: {e}"));
-                }
+    fn poll_timeout(&self) -> Option<Instant> {
+        let control = self.connection.poll_timeout(&self.rto);
+        [control, self.timer].into_iter().flatten().min()
+    }
+
+    fn poll_transmit(&mut self, now: Instant) -> Option<Packet> {
+        if let Some(packet) = self.connection.poll_transmit(now) {
+            if self.is_debug {
+                eprintln!("Sender | Send {:?} {}", packet.state, packet.number);
             }
+            return Some(packet);
         }
-        Ok(())
+        let sender_packet = self.window.get_mut(self.next)?;
+        sender_packet.is_retransmitted |= sender_packet.last_sent.is_some();
+        sender_packet.last_sent = Some(now);
+        self.next += 1;
+        self.packets_send += 1;
+        self.timer.get_or_insert(now + self.rto.current());
+        let packet = sender_packet.packet.clone();
+        if self.is_debug {
+            eprintln!(
+                "Sender | Send packet: {}, size: {}, state: {:?}, window_size: {}",
+                packet.number,
+                packet.size,
+                packet.state,
+                self.congestion.effective()
+            );
+        }
+        Some(packet)
     }
+}
 
-    #[must_use] 
-    pub fn efficiency_coefficient(&self) -> f64 {
+impl SenderStats for SenderMachine {
+    fn efficiency_coefficient(&self) -> f64 {
         self.packets_total as f64 / self.packets_send as f64
     }
+
+    fn retransmissions(&self) -> usize {
+        self.packets_send.saturating_sub(self.packets_total)
+    }
+
+    fn rto(&self) -> &RtoEstimator {
+        &self.rto
+    }
+
+    fn congestion(&self) -> Option<&CongestionWindow> {
+        self.is_windowed.then_some(&self.congestion)
+    }
 }
 
-pub struct Reader {
-    tx: mpsc::Sender<AckNumber>,
-    rx: mpsc::Receiver<Packet>,
-    number: AckNumber,
-    packets_read: usize,
-    is_debug: bool,
+/// Go-Back-N reader without I/O. Only the next in-order packet is accepted.
+pub struct ReaderMachine {
+    core: ReaderCore,
+    is_nak_enabled: bool,
+    /// Absolute index of the packet last asked for with a NAK.
+    nak_sent: Option<usize>,
 }
 
-impl Reader {
-    #[must_use] 
-    pub fn new(tx: mpsc::Sender<AckNumber>, rx: mpsc::Receiver<Packet>, is_debug: bool) -> Self {
+impl ReaderMachine {
+    #[must_use]
+    pub fn new(is_debug: bool) -> Self {
         Self {
-            tx,
-            rx,
-            number: 0,
-            packets_read: 0,
-            is_debug,
+            core: ReaderCore::new(SequenceSpace::default(), is_debug),
+            is_nak_enabled: false,
+            nak_sent: None,
+        }
+    }
+
+    /// Reader of `stop_and_wait`'s one-bit sequence numbers.
+    #[must_use]
+    pub fn stop_and_wait(is_debug: bool) -> Self {
+        Self {
+            core: ReaderCore::new(SequenceSpace::ALTERNATING_BIT, is_debug),
+            is_nak_enabled: false,
+            nak_sent: None,
         }
     }
 
-    fn reset(&mut self) {
-        self.number = 0;
-        self.packets_read = 0;
+    /// NAKs the expected packet once a later one shows up, once per gap.
+    #[must_use]
+    pub fn with_naks(mut self, is_nak_enabled: bool) -> Self {
+        self.is_nak_enabled = is_nak_enabled;
+        self
+    }
+
+    /// Must match the sender's sequence space.
+    pub fn with_sequence_bits(mut self, bits: u32) -> Result<Self, ArqError> {
+        self.core.space = SequenceSpace::new(bits)?;
+        Ok(self)
+    }
+}
+
+impl ReaderStats for ReaderMachine {
+    fn packets_read(&self) -> usize {
+        self.core.packets_read
     }
 
-    pub fn read(&mut self) -> Result<String, String> {
-        self.reset();
-        let mut data = Vec::<u8>::new();
-        let mut is_finished_timeout: Option<Instant> = None;
-        let time = Instant::now();
-        loop {
-            if time.elapsed() > TIMEOUT_TOTAL {
-                if is_finished_timeout.is_none() {
-                    return Err("Message read timeout".to_string());
+    fn duplicates(&self) -> usize {
+        self.core.duplicates
+    }
+}
+
+impl ArqReceiverMachine for ReaderMachine {
+    fn handle_packet(&mut self, packet: Packet, now: Instant) -> Result<(), ArqError> {
+        let Some(packet) = self.core.receive(packet, now)? else {
+            return Ok(());
+        };
+        let space = self.core.space;
+        if packet.number != space.wrap(self.core.expected) {
+            // Numbers in the half of the space ahead of `expected` are taken
+            // for packets past a gap, the other half for retransmissions
+            let offset = space.distance(space.wrap(self.core.expected), packet.number);
+            let is_ahead = u64::from(offset) < space.modulus() / 2;
+            if self.is_nak_enabled && is_ahead && self.nak_sent != Some(self.core.expected) {
+                self.nak_sent = Some(self.core.expected);
+                let missing = space.wrap(self.core.expected);
+                self.core.send_ack(Ack::nak(missing));
+                if self.core.is_debug {
+                    eprintln!("Reader | Nak packet {}, got {}", missing, packet.number);
                 }
-                break;
+                return Ok(());
             }
-            match self.rx.try_recv() {
-                Ok(packet) => {
-                    self.packets_read += 1;
-                    if packet.number < self.number {
-                        self.send_ack(packet.number)?;
-                        if (packet.number + 1 == self.number) && is_finished_timeout.is_some() {
-                            is_finished_timeout = Some(Instant::now());
-                        }
-                        if self.is_debug {
-                            eprintln!(
-                                "Reader | ReAck packet {}, state: {:?}, at: {}ms",
-                                packet.number,
-                                packet.state,
-                                time.elapsed().as_millis(),
-                            );
-                        }
-                        continue;
-                    }
-                    if packet.number > self.number {
-                        continue;
-                    }
-                    if self.number == 0 && !matches!(packet.state, PacketState::Begin) {
-                        return Err("First packet does not correspond to the start of the message".to_string());
-                    } else if self.number != 0 && matches!(packet.state, PacketState::Begin) {
-                        return Err("Non first packet corresponds to the start of the message".to_string());
-                    }
-                    data.extend(&packet.data[..packet.size as usize]);
-                    self.send_ack(self.number)?;
-                    self.number += 1;
-                    if self.is_debug {
-                        eprintln!(
-                            "Reader | Ack packet {}, state: {:?}, at {}ms",
-                            packet.number,
-                            packet.state,
-                            time.elapsed().as_millis()
-                        );
-                    }
-                    if matches!(packet.state, PacketState::End) {
-                        is_finished_timeout = Some(Instant::now());
-                    }
-                }
-                Err(TryRecvError::Empty) => {
-                    if is_finished_timeout.is_some_and(|t| t.elapsed() > 2 * TIMEOUT) {
-                        break;
-                    }
-                }
-                Err(e @ TryRecvError::Disconnected) => {
-                    return Err(format!("Failed to receive packet: {e}"));
-                }
+            // Once numbers wrap, a retransmitted old packet and a packet past a
+            // gap cannot be told apart. Both get the last cumulative ACK.
+            if !is_ahead {
+                self.core.duplicates += 1;
+            }
+            let last = space.wrap(self.core.expected - 1);
+            self.core.send_ack(Ack::data(last));
+            if self.core.is_debug {
+                eprintln!("Reader | ReAck packet {}, got {}", last, packet.number);
             }
+            return Ok(());
         }
-        if self.is_debug {
-            eprintln!(
-                "Reader | Finished receiving the message at {}ms",
-                time.elapsed().as_millis()
-            );
+        self.core.ensure_reading(&packet)?;
+        let number = packet.number;
+        self.core.deliver(packet)?;
+        self.core.send_ack(Ack::data(number));
+        if self.core.is_debug {
+            eprintln!("Reader | Ack packet {number}");
         }
-        String::from_utf8(data).map_err(|e| format!("Failed to encode the message: {e}"))
+        Ok(())
+    }
+
+    fn handle_timeout(&mut self, now: Instant) {
+        self.core.handle_timeout(now);
+    }
+
+    fn poll_timeout(&self) -> Option<Instant> {
+        self.core.poll_timeout()
+    }
+
+    fn poll_transmit(&mut self) -> Option<Ack> {
+        self.core.poll_transmit()
     }
 
-    fn send_ack(&mut self, ack: AckNumber) -> Result<(), String> {
-        self.tx
-            .send(ack)
-            .map_err(|e| format!("Failed to send ack {ack}: {e}"))
+    fn poll_deliver(&mut self) -> Option<Packet> {
+        self.core.poll_deliver()
+    }
+
+    fn close(&mut self) {
+        self.core.close();
+    }
+
+    fn is_established(&self) -> bool {
+        self.core.connection.is_established()
+    }
+
+    fn is_closed(&self) -> bool {
+        self.core.connection.is_closed()
+    }
+
+    fn expected(&self) -> AckNumber {
+        self.core.expected()
+    }
+
+    fn is_time_wait_over(&self, now: Instant) -> bool {
+        self.core.is_time_wait_over(now)
+    }
+
+    fn is_cumulative(&self) -> bool {
+        true
     }
 }
 
-#[must_use] 
-pub fn setup(window_size: AckNumber, message: &str) -> (String, f64) {
-    let (tx_packet, rx_packet) = mpsc::channel();
-    let (tx_ack, rx_ack) = mpsc::channel();
-    let mut sender = Sender::new(tx_packet, rx_ack, window_size, true);
-    let mut reader = Reader::new(tx_ack, rx_packet, true);
-    let message_read = thread::scope(|s| {
-        s.spawn(|| {
-            if let Err(e) = sender.send(message) {
-                eprintln!("Sender | {e}");
-            }
-        });
-        reader.read()
-    });
-    (message_read.unwrap(), sender.efficiency_coefficient())
+#[must_use]
+pub fn setup(window_size: AckNumber, message: &str) -> (String, TransferStats) {
+    Protocol::GoBackN.setup(window_size, message)
 }
 
-#[must_use] 
-pub fn silent_setup_loss(window_size: AckNumber, message: &str, loss: f64) -> (String, f64) {
-    let (tx_packet, rx_packet) = mpsc::channel();
-    let (tx_ack, rx_ack) = mpsc::channel();
-    let (rx_packet, rx_ack, loss_handle) = simulate_loss(rx_packet, rx_ack, loss);
-    let result = {
-        let mut sender = Sender::new(tx_packet, rx_ack, window_size, false);
-        let mut reader = Reader::new(tx_ack, rx_packet, false);
-        let message_read = thread::scope(|s| {
-            s.spawn(|| {
-                if let Err(e) = sender.send(message) {
-                    eprintln!("Sender | {e}");
-                }
-            });
-            reader.read().unwrap_or_else(|e| {
-                eprintln!("Reader warning: {e}");
-                String::new()
-            })
-        });
-        (message_read, sender.efficiency_coefficient())
-    };
-    loss_handle.join().unwrap();
-    result
+#[must_use]
+pub fn silent_setup_loss(
+    window_size: AckNumber,
+    message: &str,
+    loss: f64,
+    seed: Option<u64>,
+) -> (String, TransferStats) {
+    Protocol::GoBackN.silent_setup_loss(window_size, message, loss, seed)
 }
 
 #[cfg(test)]
 mod tests {
-    use std::{fs::File, io::Read};
+    use std::{fs::File, io::Read, iter};
 
     use super::*;
+    use crate::packet::{DATA_SIZE, PacketState};
 
     fn get_file_string() -> String {
         let mut s = String::new();
@@ -356,16 +517,111 @@ mod tests {
     #[test]
     fn test_gobackn_file_loss() {
         let message_send = get_file_string();
-        let message_received = setup_loss(3, &message_send, 0.0).0;
+        let message_received = silent_setup_loss(3, &message_send, 0.0, Some(0)).0;
         assert_eq!(message_send, message_received);
-        let message_received = setup_loss(3, &message_send, 0.25).0;
+        let message_received = silent_setup_loss(3, &message_send, 0.25, Some(0)).0;
         assert_eq!(message_send, message_received);
-        let message_received = setup_loss(3, &message_send, 0.5).0;
+        let message_received = silent_setup_loss(3, &message_send, 0.5, Some(0)).0;
         assert_eq!(message_send, message_received);
-        let message_received = setup_loss(3, &message_send, 0.75).0;
+        let message_received = silent_setup_loss(3, &message_send, 0.75, Some(0)).0;
         assert_eq!(message_send, message_received);
     }
 
+    #[test]
+    fn test_gobackn_naks() {
+        let now = Instant::now();
+        let packet = |number, state| Packet::new(number, [0; DATA_SIZE], 1, state);
+        let mut reader = ReaderMachine::new(false).with_naks(true);
+        reader
+            .handle_packet(Packet::control(PacketState::Syn, 9), now)
+            .unwrap();
+        reader
+            .handle_packet(packet(10, PacketState::Begin), now)
+            .unwrap();
+        for number in [12, 13] {
+            reader
+                .handle_packet(packet(number, PacketState::Ongoing), now)
+                .unwrap();
+        }
+        let acks: Vec<Ack> = iter::from_fn(|| reader.poll_transmit()).skip(1).collect();
+        assert_eq!(acks, [Ack::data(10), Ack::nak(11), Ack::data(10)]);
+
+        let mut sender = SenderMachine::new(4, false);
+        sender.connect(9, now);
+        sender.handle_ack(Ack::new(9, AckKind::SynAck), now);
+        for number in 10..14 {
+            sender.push(packet(number, PacketState::Ongoing));
+        }
+        let sent = iter::from_fn(|| sender.poll_transmit(now)).count();
+        assert_eq!(sent, 4);
+        sender.handle_ack(Ack::nak(11), now);
+        // Packet 10 is acknowledged by the NAK, the rest goes again at once
+        let resent: Vec<AckNumber> = iter::from_fn(|| sender.poll_transmit(now))
+            .map(|p| p.number)
+            .collect();
+        assert_eq!(resent, [11, 12, 13]);
+    }
+
+    #[test]
+    fn test_gobackn_stray_syn() {
+        let now = Instant::now();
+        let packet = |number, state| Packet::new(number, [0; DATA_SIZE], 1, state);
+        let mut reader = ReaderMachine::new(false);
+        reader
+            .handle_packet(Packet::control(PacketState::Syn, 9), now)
+            .unwrap();
+        reader
+            .handle_packet(packet(10, PacketState::Begin), now)
+            .unwrap();
+        // A SYN of an earlier connection replayed in the middle of the message
+        reader
+            .handle_packet(Packet::control(PacketState::Syn, 3), now)
+            .unwrap();
+        reader
+            .handle_packet(packet(11, PacketState::End), now)
+            .unwrap();
+        let acks: Vec<Ack> = iter::from_fn(|| reader.poll_transmit()).collect();
+        assert_eq!(
+            acks,
+            [Ack::new(9, AckKind::SynAck), Ack::data(10), Ack::data(11)]
+        );
+        let delivered: Vec<AckNumber> = iter::from_fn(|| reader.poll_deliver())
+            .map(|p| p.number)
+            .collect();
+        assert_eq!(delivered, [10, 11]);
+    }
+
+    #[test]
+    fn test_gobackn_fast_retransmit() {
+        let now = Instant::now();
+        let mut sender = SenderMachine::new(4, false).with_fast_retransmit(Some(3));
+        sender.connect(9, now);
+        sender.handle_ack(Ack::new(9, AckKind::SynAck), now);
+        for number in 10..14 {
+            sender.push(Packet::new(number, [0; DATA_SIZE], 1, PacketState::Ongoing));
+        }
+        assert_eq!(iter::from_fn(|| sender.poll_transmit(now)).count(), 4);
+        // Packet 10 is lost, the reader re-acks the SYN for each later one
+        for _ in 0..2 {
+            sender.handle_ack(Ack::data(9), now);
+        }
+        assert!(sender.poll_transmit(now).is_none());
+        sender.handle_ack(Ack::data(9), now);
+        let resent: Vec<AckNumber> = iter::from_fn(|| sender.poll_transmit(now))
+            .map(|p| p.number)
+            .collect();
+        assert_eq!(resent, [10, 11, 12, 13]);
+        // Duplicates of packets that were in flight do not go back again
+        for _ in 0..3 {
+            sender.handle_ack(Ack::data(9), now);
+        }
+        assert!(sender.poll_transmit(now).is_none());
+        sender.handle_ack(Ack::data(10), now);
+        assert_eq!(sender.base, 11);
+        assert_eq!(sender.duplicate_acks, 0);
+        assert!(!sender.is_recovering);
+    }
+
     #[test]
     fn test_gobackn_small() {
         let message_send = String::from("test");
@@ -0,0 +1,605 @@
+use rand::{Rng, SeedableRng, rngs::StdRng};
+use std::{
+    cmp::Ordering,
+    f64::consts::TAU,
+    time::{Duration, Instant},
+};
+
+use crate::packet::{Ack, Corruptible, Frame, Packet};
+
+/// Extra delay of a reordered value, long enough for the values sent right
+/// after it to overtake it.
+const REORDER_DELAY: Duration = Duration::from_millis(20);
+
+/// Random generator of a simulation. The same `seed` always gives the
+/// same sequence, `None` picks a fresh one.
+#[must_use]
+pub fn simulation_rng(seed: Option<u64>) -> StdRng {
+    match seed {
+        Some(seed) => StdRng::seed_from_u64(seed),
+        None => StdRng::from_rng(&mut rand::rng()),
+    }
+}
+
+/// Two-state Markov loss model. The link alternates between a good and a
+/// bad state, each with its own loss probability, so losses come in bursts.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GilbertElliott {
+    /// Probability of moving from the good to the bad state after a value.
+    pub to_bad: f64,
+    /// Probability of moving from the bad to the good state after a value.
+    pub to_good: f64,
+    pub loss_good: f64,
+    pub loss_bad: f64,
+}
+
+impl GilbertElliott {
+    /// Gilbert model that loses everything in the bad state and nothing in
+    /// the good one, with the given average `loss` and mean burst length
+    /// in values.
+    #[must_use]
+    pub fn bursts(loss: f64, mean_burst: f64) -> Self {
+        assert!((0.0..1.0).contains(&loss));
+        assert!(mean_burst >= 1.0);
+        let to_good = 1.0 / mean_burst;
+        let to_bad = loss * to_good / (1.0 - loss);
+        assert!(to_bad <= 1.0, "bursts are too short for such a loss");
+        Self {
+            to_bad,
+            to_good,
+            loss_good: 0.0,
+            loss_bad: 1.0,
+        }
+    }
+
+    /// Long-run share of values sent in the bad state.
+    fn bad_share(self) -> f64 {
+        if self.to_bad == 0.0 {
+            return 0.0;
+        }
+        self.to_bad / (self.to_bad + self.to_good)
+    }
+
+    #[must_use]
+    pub fn mean_loss(self) -> f64 {
+        let bad = self.bad_share();
+        (1.0 - bad) * self.loss_good + bad * self.loss_bad
+    }
+
+    /// Mean number of values sent in a row in the bad state.
+    #[must_use]
+    pub fn mean_burst(self) -> f64 {
+        1.0 / self.to_good
+    }
+}
+
+/// How a link decides which values to drop.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LossModel {
+    /// Every value is dropped independently with the given probability.
+    Bernoulli(f64),
+    GilbertElliott(GilbertElliott),
+}
+
+impl Default for LossModel {
+    fn default() -> Self {
+        Self::Bernoulli(0.0)
+    }
+}
+
+impl LossModel {
+    #[must_use]
+    pub fn mean_loss(self) -> f64 {
+        match self {
+            Self::Bernoulli(loss) => loss,
+            Self::GilbertElliott(model) => model.mean_loss(),
+        }
+    }
+
+    fn start(self, rnd: &mut impl Rng) -> LossProcess {
+        let is_bad = match self {
+            Self::Bernoulli(loss) => {
+                assert!((0.0..=1.0).contains(&loss));
+                false
+            }
+            // Start in the steady state, short transfers are not biased
+            Self::GilbertElliott(model) => rnd.random::<f64>() < model.bad_share(),
+        };
+        LossProcess {
+            model: self,
+            is_bad,
+        }
+    }
+}
+
+/// State of a loss model on one link.
+struct LossProcess {
+    model: LossModel,
+    is_bad: bool,
+}
+
+impl LossProcess {
+    fn is_lost(&mut self, rnd: &mut impl Rng) -> bool {
+        match self.model {
+            LossModel::Bernoulli(loss) => rnd.random::<f64>() < loss,
+            LossModel::GilbertElliott(model) => {
+                let loss = if self.is_bad {
+                    model.loss_bad
+                } else {
+                    model.loss_good
+                };
+                let is_lost = rnd.random::<f64>() < loss;
+                let switch = if self.is_bad {
+                    model.to_good
+                } else {
+                    model.to_bad
+                };
+                if rnd.random::<f64>() < switch {
+                    self.is_bad = !self.is_bad;
+                }
+                is_lost
+            }
+        }
+    }
+}
+
+/// Flips every bit of `value` with probability `bit_error_rate`. Returns
+/// `false` if the value can no longer be parsed.
+fn corrupt<A: Corruptible>(value: &mut A, bit_error_rate: f64, rnd: &mut impl Rng) -> bool {
+    if bit_error_rate > 0.0 {
+        for index in 0..value.bit_len() {
+            if rnd.random::<f64>() < bit_error_rate && !value.flip_bit(index) {
+                return false;
+            }
+        }
+    }
+    true
+}
+
+/// Distribution of the random variation of the propagation delay.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum Jitter {
+    #[default]
+    None,
+    /// Uniform within `±range`.
+    Uniform(Duration),
+    /// Normal with the given standard deviation.
+    Normal(Duration),
+    /// Exponential with the given mean, only ever adds delay.
+    Exponential(Duration),
+}
+
+impl Jitter {
+    /// Offset to the propagation delay in seconds.
+    fn sample(self, rnd: &mut impl Rng) -> f64 {
+        match self {
+            Self::None => 0.0,
+            Self::Uniform(range) => range.as_secs_f64() * rnd.random_range(-1.0..=1.0),
+            Self::Normal(std_dev) => {
+                // Box-Muller transform
+                let u = 1.0 - rnd.random::<f64>();
+                let v = rnd.random::<f64>();
+                std_dev.as_secs_f64() * (-2.0 * u.ln()).sqrt() * (TAU * v).cos()
+            }
+            Self::Exponential(mean) => -mean.as_secs_f64() * (1.0 - rnd.random::<f64>()).ln(),
+        }
+    }
+}
+
+/// Timing of one direction of a link. The default is an ideal link that
+/// delivers everything instantly and in order.
+///
+/// Jitter alone may reorder values too, as each of them is delayed
+/// independently.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ChannelModel {
+    /// Propagation delay.
+    pub delay: Duration,
+    pub jitter: Jitter,
+    /// Probability of holding a value back so that later ones overtake it.
+    pub reorder: f64,
+    /// Probability of delivering a value twice.
+    pub duplication: f64,
+    /// Link rate in bits per second. Values queue up behind each other for
+    /// their serialization delay, `None` for an unlimited link.
+    pub bandwidth: Option<u64>,
+}
+
+impl ChannelModel {
+    #[must_use]
+    pub fn with_delay(mut self, delay: Duration) -> Self {
+        self.delay = delay;
+        self
+    }
+
+    #[must_use]
+    pub fn with_jitter(mut self, jitter: Jitter) -> Self {
+        self.jitter = jitter;
+        self
+    }
+
+    #[must_use]
+    pub fn with_reorder(mut self, reorder: f64) -> Self {
+        self.reorder = reorder;
+        self
+    }
+
+    #[must_use]
+    pub fn with_duplication(mut self, duplication: f64) -> Self {
+        self.duplication = duplication;
+        self
+    }
+
+    #[must_use]
+    pub fn with_bandwidth(mut self, bits_per_second: u64) -> Self {
+        self.bandwidth = Some(bits_per_second);
+        self
+    }
+}
+
+/// Timing state of a channel model on one link.
+struct ChannelTiming {
+    model: ChannelModel,
+    /// When the link is done serializing the values before.
+    link_free: Instant,
+}
+
+impl ChannelTiming {
+    fn new(model: ChannelModel, now: Instant) -> Self {
+        assert!((0.0..=1.0).contains(&model.reorder));
+        assert!((0.0..=1.0).contains(&model.duplication));
+        assert_ne!(model.bandwidth, Some(0));
+        Self {
+            model,
+            link_free: now,
+        }
+    }
+
+    /// Arrival times of a value of `bit_len` bits sent at `now`, two of
+    /// them if the value is duplicated.
+    fn arrivals(&mut self, bit_len: usize, now: Instant, rnd: &mut impl Rng) -> Vec<Instant> {
+        let model = self.model;
+        let departure = match model.bandwidth {
+            Some(bits_per_second) => {
+                let serialization = bit_len as f64 / bits_per_second as f64;
+                self.link_free = self.link_free.max(now) + Duration::from_secs_f64(serialization);
+                self.link_free
+            }
+            None => now,
+        };
+        let copies = if rnd.random::<f64>() < model.duplication {
+            2
+        } else {
+            1
+        };
+        (0..copies)
+            .map(|_| {
+                let mut delay = model.delay.as_secs_f64() + model.jitter.sample(rnd);
+                if rnd.random::<f64>() < model.reorder {
+                    delay += REORDER_DELAY.as_secs_f64();
+                }
+                departure + Duration::from_secs_f64(delay.max(0.0))
+            })
+            .collect()
+    }
+}
+
+/// Value in flight, ordered by its arrival time.
+pub(crate) struct Scheduled<A> {
+    pub(crate) at: Instant,
+    /// Sending order, keeps values due at the same instant in order.
+    pub(crate) order: u64,
+    pub(crate) value: A,
+}
+
+impl<A> PartialEq for Scheduled<A> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl<A> Eq for Scheduled<A> {}
+
+impl<A> PartialOrd for Scheduled<A> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<A> Ord for Scheduled<A> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (self.at, self.order).cmp(&(other.at, other.order))
+    }
+}
+
+/// Impairments of one direction of a link.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Path {
+    pub loss: LossModel,
+    pub channel: ChannelModel,
+}
+
+impl Path {
+    /// Starts the impairments of the path at `now`.
+    fn start(self, rnd: &mut StdRng, now: Instant) -> PathState {
+        let mut loss_rnd = StdRng::from_rng(&mut *rnd);
+        let loss = self.loss.start(&mut loss_rnd);
+        PathState {
+            loss,
+            loss_rnd,
+            timing: ChannelTiming::new(self.channel, now),
+            channel_rnd: StdRng::from_rng(rnd),
+        }
+    }
+}
+
+/// State of the impairments of one path.
+struct PathState {
+    loss: LossProcess,
+    loss_rnd: StdRng,
+    timing: ChannelTiming,
+    channel_rnd: StdRng,
+}
+
+impl PathState {
+    /// Arrival times of a value sent at `now`, none if it is lost.
+    fn transmit(&mut self, bit_len: usize, now: Instant) -> Vec<Instant> {
+        if self.loss.is_lost(&mut self.loss_rnd) {
+            return Vec::new();
+        }
+        self.timing.arrivals(bit_len, now, &mut self.channel_rnd)
+    }
+}
+
+/// Impairments between a sender and a reader for a driver that passes
+/// values one by one, such as a discrete-event simulation or a proxy.
+pub(crate) struct Link {
+    bit_error_rate: f64,
+    bit_error_rnd: StdRng,
+    data: PathState,
+    ack: PathState,
+}
+
+impl Link {
+    /// Arrival times and contents of a packet sent at `now`.
+    pub(crate) fn transmit_packet(
+        &mut self,
+        mut packet: Packet,
+        now: Instant,
+    ) -> Vec<(Instant, Packet)> {
+        if !corrupt(&mut packet, self.bit_error_rate, &mut self.bit_error_rnd) {
+            return Vec::new();
+        }
+        self.data
+            .transmit(packet.bit_len(), now)
+            .into_iter()
+            .map(|at| (at, packet.clone()))
+            .collect()
+    }
+
+    /// Arrival times of an ACK sent at `now`.
+    pub(crate) fn transmit_ack(&mut self, ack: Ack, now: Instant) -> Vec<Instant> {
+        self.ack.transmit(ack.bit_len(), now)
+    }
+}
+
+/// Channel impairments between a sender and a reader. The data and the
+/// ACK path are impaired independently and may differ.
+///
+/// Bit errors only hit data packets, ACKs are assumed to arrive intact
+/// whenever they are not lost.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Impairments {
+    /// Path of the data packets from the sender to the reader.
+    pub data: Path,
+    /// Path of the ACKs from the reader back to the sender.
+    pub ack: Path,
+    /// Probability of flipping each bit of a packet.
+    pub bit_error_rate: f64,
+    /// Seed of all random draws, the same seed always gives the same
+    /// drops, bit errors and delays to the n-th value of each path.
+    /// `None` picks a fresh one.
+    pub seed: Option<u64>,
+}
+
+impl Impairments {
+    /// Same independent `loss` on both paths.
+    #[must_use]
+    pub fn loss(loss: f64) -> Self {
+        Self::default().with_loss(LossModel::Bernoulli(loss))
+    }
+
+    /// Independent loss with different rates on the data and the ACK path.
+    #[must_use]
+    pub fn asymmetric_loss(data_loss: f64, ack_loss: f64) -> Self {
+        Self::default()
+            .with_data_loss(LossModel::Bernoulli(data_loss))
+            .with_ack_loss(LossModel::Bernoulli(ack_loss))
+    }
+
+    /// Same loss model on both paths, each path keeps its own state.
+    #[must_use]
+    pub fn with_loss(self, loss: LossModel) -> Self {
+        self.with_data_loss(loss).with_ack_loss(loss)
+    }
+
+    #[must_use]
+    pub fn with_data_loss(mut self, loss: LossModel) -> Self {
+        self.data.loss = loss;
+        self
+    }
+
+    #[must_use]
+    pub fn with_ack_loss(mut self, loss: LossModel) -> Self {
+        self.ack.loss = loss;
+        self
+    }
+
+    #[must_use]
+    pub fn with_bit_error_rate(mut self, bit_error_rate: f64) -> Self {
+        self.bit_error_rate = bit_error_rate;
+        self
+    }
+
+    /// Same channel model on both paths.
+    #[must_use]
+    pub fn with_channel(self, channel: ChannelModel) -> Self {
+        self.with_data_channel(channel).with_ack_channel(channel)
+    }
+
+    #[must_use]
+    pub fn with_data_channel(mut self, channel: ChannelModel) -> Self {
+        self.data.channel = channel;
+        self
+    }
+
+    #[must_use]
+    pub fn with_ack_channel(mut self, channel: ChannelModel) -> Self {
+        self.ack.channel = channel;
+        self
+    }
+
+    #[must_use]
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+
+    /// Starts the impairments at `now` for a driver that passes values one
+    /// by one. Every stage gets its own generator split off `rnd`, so a
+    /// seeded `rnd` gives the n-th value of each path the same fate.
+    pub(crate) fn start(&self, rnd: &mut StdRng, now: Instant) -> Link {
+        assert!((0.0..=1.0).contains(&self.bit_error_rate));
+        Link {
+            bit_error_rate: self.bit_error_rate,
+            bit_error_rnd: StdRng::from_rng(&mut *rnd),
+            data: self.data.start(rnd, now),
+            ack: self.ack.start(rnd, now),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet::{AckNumber, PacketState};
+
+    /// Sends packets and ACKs numbered up to `count` through a link with
+    /// `impairments`, all at once. Returns the packets that arrive and when
+    /// the ACKs do, in the order of arrival.
+    fn transmit(
+        impairments: &Impairments,
+        count: AckNumber,
+    ) -> (Vec<Packet>, Vec<(Instant, AckNumber)>) {
+        let now = Instant::now();
+        let mut link = impairments.start(&mut simulation_rng(impairments.seed), now);
+        let mut packets = Vec::new();
+        let mut acks = Vec::new();
+        for number in 0..count {
+            let packet = Packet::control(PacketState::Begin, number);
+            packets.extend(link.transmit_packet(packet, now));
+            let arrivals = link.transmit_ack(Ack::data(number), now);
+            acks.extend(arrivals.into_iter().map(|at| (at, number)));
+        }
+        // Stable, values due at the same instant stay in sending order
+        packets.sort_by_key(|&(at, _)| at);
+        acks.sort_by_key(|&(at, _)| at);
+        (
+            packets.into_iter().map(|(_, packet)| packet).collect(),
+            acks,
+        )
+    }
+
+    #[test]
+    fn test_asymmetric_loss() {
+        let (packets, acks) = transmit(&Impairments::asymmetric_loss(0.0, 1.0), 100);
+        assert_eq!(packets.len(), 100);
+        assert!(acks.is_empty());
+    }
+
+    #[test]
+    fn test_seeded_loss() {
+        let survivors = |seed| {
+            let (packets, acks) =
+                transmit(&Impairments::asymmetric_loss(0.5, 0.3).with_seed(seed), 100);
+            let packets: Vec<AckNumber> = packets.iter().map(|p| p.number).collect();
+            let acks: Vec<AckNumber> = acks.into_iter().map(|(_, number)| number).collect();
+            (packets, acks)
+        };
+        let first = survivors(42);
+        assert_eq!(first, survivors(42));
+        assert_ne!(first, survivors(43));
+    }
+
+    #[test]
+    fn test_bit_errors() {
+        let (packets, _) = transmit(
+            &Impairments::default()
+                .with_bit_error_rate(0.01)
+                .with_seed(0),
+            100,
+        );
+        // An 80-bit header survives a 1% BER about 45% of the time
+        let valid = packets.iter().filter(|p| p.is_valid()).count();
+        assert!(valid > 10 && valid < 90, "{valid}");
+        assert!(packets.len() > valid);
+    }
+
+    #[test]
+    fn test_gilbert_elliott() {
+        let model = GilbertElliott::bursts(0.2, 5.0);
+        assert!((model.mean_loss() - 0.2).abs() < 1e-9);
+        assert!((model.mean_burst() - 5.0).abs() < 1e-9);
+        let mut rnd = simulation_rng(Some(0));
+        let mut process = LossModel::GilbertElliott(model).start(&mut rnd);
+        let losses: Vec<bool> = (0..100_000).map(|_| process.is_lost(&mut rnd)).collect();
+        let lost = losses.iter().filter(|&&is_lost| is_lost).count();
+        let bursts = losses.windows(2).filter(|pair| pair[1] && !pair[0]).count();
+        let loss = lost as f64 / losses.len() as f64;
+        let mean_burst = lost as f64 / bursts as f64;
+        assert!((loss - 0.2).abs() < 0.02, "{loss}");
+        assert!((mean_burst - 5.0).abs() < 0.5, "{mean_burst}");
+    }
+
+    fn pass(model: ChannelModel, count: AckNumber) -> Vec<(Duration, AckNumber)> {
+        let start = Instant::now();
+        let (_, acks) = transmit(&Impairments::default().with_ack_channel(model), count);
+        acks.into_iter()
+            .map(|(at, number)| (at.saturating_duration_since(start), number))
+            .collect()
+    }
+
+    fn numbers(arrivals: &[(Duration, AckNumber)]) -> Vec<AckNumber> {
+        arrivals.iter().map(|&(_, number)| number).collect()
+    }
+
+    #[test]
+    fn test_channel_timing() {
+        let arrivals = pass(ChannelModel::default(), 10);
+        assert_eq!(numbers(&arrivals), (0..10).collect::<Vec<_>>());
+        let model = ChannelModel::default().with_delay(Duration::from_millis(50));
+        let arrivals = pass(model, 10);
+        assert_eq!(arrivals.len(), 10);
+        assert!(
+            arrivals
+                .iter()
+                .all(|&(at, _)| at >= Duration::from_millis(50))
+        );
+        // 40-bit ACKs at 4 kbit/s take 10 ms each
+        let arrivals = pass(ChannelModel::default().with_bandwidth(4_000), 10);
+        assert_eq!(numbers(&arrivals), (0..10).collect::<Vec<_>>());
+        assert!(arrivals[9].0 >= Duration::from_millis(100));
+    }
+
+    #[test]
+    fn test_channel_reorder_and_duplication() {
+        let arrivals = pass(ChannelModel::default().with_duplication(1.0), 10);
+        assert_eq!(arrivals.len(), 20);
+        let model = ChannelModel::default()
+            .with_jitter(Jitter::Exponential(Duration::from_millis(5)))
+            .with_reorder(0.5);
+        let numbers = numbers(&pass(model, 100));
+        assert_eq!(numbers.len(), 100);
+        assert!(numbers.windows(2).any(|pair| pair[0] > pair[1]));
+    }
+}
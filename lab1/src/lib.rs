@@ -1,101 +1,93 @@
-use rand::{self, Rng};
-use std::{
-    sync::mpsc::{self, Receiver, TryRecvError},
-    thread::{self, JoinHandle},
-    time::Duration,
-};
-
+pub mod arq;
+#[cfg(feature = "async")]
+pub mod async_driver;
+pub mod checksum;
+pub mod congestion;
+pub mod connection;
+pub mod driver;
+pub mod duplex;
+pub mod error;
 pub mod gobackn;
+pub mod impairment;
+pub mod machine;
+pub mod packet;
+pub mod rto;
+pub mod sack;
 pub mod selective_repeat;
+pub mod seq;
+pub mod sim;
+pub mod stats;
+pub mod stop_and_wait;
+pub mod stream;
+pub mod transport;
+pub mod udp;
+pub mod wire;
+
+use rand::Rng;
 
-#[must_use] 
-pub fn simulate_loss<A: Send + 'static, B: Send + 'static>(
-    ra: Receiver<A>,
-    rb: Receiver<B>,
+pub use arq::{ArqConfig, ArqReceiver, ArqSender, Protocol};
+pub use error::ArqError;
+pub use impairment::{ChannelModel, GilbertElliott, Impairments, Jitter, LossModel, Path};
+pub use stats::TransferStats;
+pub use stream::{StreamReader, StreamWriter};
+pub use transport::Transport;
+pub use wire::DecodeError;
+
+/// Drops the values both ends of a link receive with probability `loss`.
+/// Works over any transport. The same `seed` always drops the same values,
+/// without one the drops differ on every run.
+#[must_use]
+pub fn simulate_loss<A, B>(
+    a: A,
+    b: B,
     loss: f64,
-) -> (Receiver<A>, Receiver<B>, JoinHandle<()>) {
-    assert!(loss >= 0.0);
-    assert!(loss <= 1.0);
-    let (txa, rxa) = mpsc::channel();
-    let (txb, rxb) = mpsc::channel();
-    let handle = thread::spawn(move || {
-        let mut is_ra_alive = true;
-        let mut is_rb_alive = true;
-        let mut is_did_work;
-        let mut rnd = rand::rng();
-        loop {
-            is_did_work = false;
-            if is_ra_alive {
-                match ra.try_recv() {
-                    Ok(a) => {
-                        if rnd.random::<f64>() >= loss && txa.send(a).is_err() {
-                            is_ra_alive = false;
-                        }
-                        is_did_work = true;
-                    }
-                    Err(TryRecvError::Empty) => {}
-                    Err(TryRecvError::Disconnected) => is_ra_alive = false,
-                }
-            }
-            if is_rb_alive {
-                match rb.try_recv() {
-                    Ok(b) => {
-                        if rnd.random::<f64>() >= loss && txb.send(b).is_err() {
-                            is_rb_alive = false;
-                        }
-                        is_did_work = true;
-                    }
-                    Err(TryRecvError::Empty) => {}
-                    Err(TryRecvError::Disconnected) => is_rb_alive = false,
-                }
-            }
-            if !is_ra_alive && !is_rb_alive {
-                break;
-            }
-            if !is_did_work {
-                thread::sleep(Duration::from_millis(10));
-            }
-        }
-    });
-    (rxa, rxb, handle)
+    seed: Option<u64>,
+) -> (transport::Lossy<A>, transport::Lossy<B>) {
+    let mut rnd = impairment::simulation_rng(seed);
+    (
+        transport::Lossy::new(a, loss).with_seed(rnd.random()),
+        transport::Lossy::new(b, loss).with_seed(rnd.random()),
+    )
 }
 
 #[cfg(test)]
 mod tests {
-    use std::sync::mpsc;
+    use std::time::Duration;
 
     use super::*;
 
-    fn setup_loss(loss: f64) -> (usize, usize) {
-        let (txa, rxa) = mpsc::channel();
-        let (txb, rxb) = mpsc::channel();
-        let (rxa, rxb, handle) = simulate_loss(rxa, rxb, loss);
+    fn setup_loss(loss: f64, seed: Option<u64>) -> (usize, usize) {
+        let (a, b) = transport::channel::<i32, i32>();
+        let (mut a, mut b) = simulate_loss(a, b, loss, seed);
         let timeout = Duration::from_millis(100);
         for i in 0..100 {
-            txa.send(i).unwrap();
-            txb.send(i).unwrap();
+            a.send(i).unwrap();
+            b.send(i).unwrap();
         }
         let mut count_a = 0;
-        while rxa.recv_timeout(timeout).is_ok() {
+        while a.recv_timeout(timeout).is_ok() {
             count_a += 1;
         }
         let mut count_b = 0;
-        while rxb.recv_timeout(timeout).is_ok() {
+        while b.recv_timeout(timeout).is_ok() {
             count_b += 1;
         }
-        drop(txa);
-        drop(txb);
-        handle.join().unwrap();
         (count_a, count_b)
     }
 
     #[test]
     fn test_loss() {
-        let (count_a, count_b) = setup_loss(0.0);
+        let (count_a, count_b) = setup_loss(0.0, None);
         assert_eq!(count_a, 100);
         assert_eq!(count_b, 100);
-        let (count_a, count_b) = setup_loss(1.0);
+        let (count_a, count_b) = setup_loss(1.0, None);
         assert_eq!(count_a, 0);
         assert_eq!(count_b, 0);
     }
+
+    #[test]
+    fn test_seeded_loss() {
+        let counts = setup_loss(0.5, Some(42));
+        assert_eq!(counts, setup_loss(0.5, Some(42)));
+    }
 }
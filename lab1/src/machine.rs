@@ -0,0 +1,241 @@
+use std::{collections::VecDeque, time::Instant};
+
+use crate::{
+    arq::{ReaderStats, SenderStats},
+    connection::{Connection, TimeWait},
+    error::{ArqError, Violation},
+    packet::{Ack, AckKind, AckNumber, Packet, PacketState},
+    seq::SequenceSpace,
+};
+
+/// Sending half of an ARQ protocol as a state machine without I/O.
+///
+/// The machine never blocks and never reads a clock: its driver feeds it
+/// ACKs and expired timers, puts whatever `poll_transmit` yields on the
+/// wire and passes `now` from the clock it runs on, be it a wall clock or
+/// a simulated one.
+pub trait ArqSenderMachine: SenderStats + Send {
+    /// Sequence space the initial sequence number is picked from.
+    fn space(&self) -> SequenceSpace;
+
+    /// Starts a SYN/SYN-ACK handshake with the initial sequence number
+    /// `isn`. The RTO and the congestion window start over at `now`.
+    fn connect(&mut self, isn: AckNumber, now: Instant);
+
+    fn is_connected(&self) -> bool;
+
+    /// Starts a new message, the efficiency counts start over.
+    fn start_message(&mut self);
+
+    /// Whether the window takes another packet.
+    fn has_room(&self) -> bool;
+
+    /// Appends the next packet of the message to the window and numbers it.
+    fn push(&mut self, packet: Packet);
+
+    /// Whether every pushed packet was acknowledged.
+    fn is_idle(&self) -> bool;
+
+    /// Starts the FIN/FIN-ACK exchange, the window must be idle by now.
+    fn close(&mut self);
+
+    /// Whether the FIN was acknowledged.
+    fn is_closed(&self) -> bool;
+
+    /// Number of the oldest packet, SYN or FIN still waiting for its ACK.
+    fn oldest_unacked(&self) -> Option<AckNumber>;
+
+    fn handle_ack(&mut self, ack: Ack, now: Instant);
+
+    /// Schedules retransmissions of everything that timed out by `now`.
+    fn handle_timeout(&mut self, now: Instant);
+
+    /// When `handle_timeout` has something to do next.
+    fn poll_timeout(&self) -> Option<Instant>;
+
+    /// Next packet to put on the wire.
+    fn poll_transmit(&mut self, now: Instant) -> Option<Packet>;
+}
+
+/// Receiving half of an ARQ protocol as a state machine without I/O.
+pub trait ArqReceiverMachine: ReaderStats + Send {
+    /// Handles a packet that arrived at `now`. Fails on packets that break
+    /// the protocol, such as data of a new message while closing.
+    fn handle_packet(&mut self, packet: Packet, now: Instant) -> Result<(), ArqError>;
+
+    /// Repeats the FIN-ACK while lingering after the close.
+    fn handle_timeout(&mut self, now: Instant);
+
+    /// When `handle_timeout` has something to do next.
+    fn poll_timeout(&self) -> Option<Instant>;
+
+    /// Next ACK to put on the wire.
+    fn poll_transmit(&mut self) -> Option<Ack>;
+
+    /// Next data packet in message order. The `End` packet completes a message.
+    fn poll_deliver(&mut self) -> Option<Packet>;
+
+    /// Stops taking new data: only retransmissions are answered until the
+    /// sender's FIN.
+    fn close(&mut self);
+
+    fn is_established(&self) -> bool;
+
+    /// Whether the sender's FIN was acknowledged.
+    fn is_closed(&self) -> bool;
+
+    /// Number of the next packet in order, the FIN's number once closed.
+    fn expected(&self) -> AckNumber;
+
+    /// Whether the reader no longer needs to linger after the close.
+    fn is_time_wait_over(&self, now: Instant) -> bool;
+
+    /// Whether a data ACK covers every packet before its number, so that a
+    /// later one makes the earlier ones redundant.
+    fn is_cumulative(&self) -> bool;
+}
+
+/// State every reader machine keeps apart from its window.
+#[derive(Default)]
+pub(crate) struct ReaderCore {
+    /// Absolute index of the next in-order packet.
+    pub(crate) expected: usize,
+    pub(crate) space: SequenceSpace,
+    pub(crate) connection: Connection,
+    time_wait: Option<TimeWait>,
+    acks: VecDeque<Ack>,
+    delivered: VecDeque<Packet>,
+    /// Whether the next delivered packet continues a message.
+    is_in_message: bool,
+    is_closing: bool,
+    pub(crate) packets_read: usize,
+    /// Data packets the reader already had when they arrived.
+    pub(crate) duplicates: usize,
+    pub(crate) is_debug: bool,
+}
+
+impl ReaderCore {
+    pub(crate) fn new(space: SequenceSpace, is_debug: bool) -> Self {
+        Self {
+            space,
+            is_debug,
+            ..Self::default()
+        }
+    }
+
+    /// Drops corrupted packets and handles SYNs and FINs. Returns the data
+    /// packets of an established connection for the protocol to handle.
+    pub(crate) fn receive(
+        &mut self,
+        packet: Packet,
+        now: Instant,
+    ) -> Result<Option<Packet>, ArqError> {
+        self.packets_read += 1;
+        if !packet.is_valid() {
+            // Not acknowledged, the sender retransmits it on timeout
+            if self.is_debug {
+                eprintln!("Reader | Corrupted packet {} dropped", packet.number);
+            }
+            return Ok(None);
+        }
+        if packet.is_control() {
+            let connection = self.connection;
+            if let Some(ack) = self
+                .connection
+                .on_control(&packet, &mut self.expected, self.space)
+            {
+                if ack.kind == AckKind::FinAck
+                    && let Some(time_wait) = &mut self.time_wait
+                {
+                    time_wait.restart(now);
+                }
+                self.send_ack(ack);
+            }
+            if self.connection != connection && self.connection.is_established() {
+                self.is_in_message = false;
+                self.time_wait = None;
+            }
+            if self.connection.is_closed() && self.time_wait.is_none() {
+                // Linger for a while in case our FIN-ACK is lost
+                self.time_wait = self.connection.time_wait(now);
+                if !self.is_closing {
+                    return Err(ArqError::PeerClosed { fin: packet.number });
+                }
+            }
+            return Ok(None);
+        }
+        Ok(self.connection.is_established().then_some(packet))
+    }
+
+    pub(crate) fn send_ack(&mut self, ack: Ack) {
+        self.acks.push_back(ack);
+    }
+
+    /// Fails once the reader is closing: new data means the sender did
+    /// not get the ACKs of a message the reader already finished.
+    pub(crate) fn ensure_reading(&self, packet: &Packet) -> Result<(), ArqError> {
+        if self.is_closing {
+            return Err(ArqError::ProtocolViolation {
+                violation: Violation::DataWhileClosing,
+                number: packet.number,
+            });
+        }
+        Ok(())
+    }
+
+    /// Hands the next in-order packet to the application.
+    pub(crate) fn deliver(&mut self, packet: Packet) -> Result<(), ArqError> {
+        let is_begin = matches!(packet.state, PacketState::Begin);
+        let violation = match (self.is_in_message, is_begin) {
+            (false, false) => Some(Violation::MissingBegin),
+            (true, true) => Some(Violation::UnexpectedBegin),
+            _ => None,
+        };
+        if let Some(violation) = violation {
+            return Err(ArqError::ProtocolViolation {
+                violation,
+                number: packet.number,
+            });
+        }
+        self.is_in_message = !matches!(packet.state, PacketState::End);
+        self.expected += 1;
+        self.delivered.push_back(packet);
+        Ok(())
+    }
+
+    pub(crate) fn handle_timeout(&mut self, now: Instant) {
+        if let Some(ack) = self
+            .time_wait
+            .as_mut()
+            .and_then(|time_wait| time_wait.poll(now))
+        {
+            self.send_ack(ack);
+        }
+    }
+
+    pub(crate) fn expected(&self) -> AckNumber {
+        self.space.wrap(self.expected)
+    }
+
+    pub(crate) fn poll_timeout(&self) -> Option<Instant> {
+        self.time_wait.as_ref().and_then(TimeWait::deadline)
+    }
+
+    pub(crate) fn poll_transmit(&mut self) -> Option<Ack> {
+        self.acks.pop_front()
+    }
+
+    pub(crate) fn poll_deliver(&mut self) -> Option<Packet> {
+        self.delivered.pop_front()
+    }
+
+    pub(crate) fn close(&mut self) {
+        self.is_closing = true;
+    }
+
+    pub(crate) fn is_time_wait_over(&self, now: Instant) -> bool {
+        self.time_wait
+            .as_ref()
+            .is_some_and(|time_wait| time_wait.is_over(now))
+    }
+}
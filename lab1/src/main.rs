@@ -1,8 +1,249 @@
-use lab1::{gobackn, selective_repeat};
+use lab1::{
+    ArqConfig, ChannelModel, GilbertElliott, Impairments, LossModel, Protocol, arq::SenderStats,
+    congestion::CongestionAlgorithm, sim, udp,
+};
+use std::collections::HashMap;
+use std::fmt::Display;
 use std::fs::File;
-use std::io::Write;
+use std::io::{self, Write};
+use std::net::{SocketAddr, UdpSocket};
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+const USAGE: &str = "Usage:
+  lab1 [--seed <n>] [--wall-clock]
+  lab1 send <file> [--to <addr>] [--protocol <saw|gbn|sr|sack>] [--window <n>]
+  lab1 recv <file> [--listen <addr>] [--protocol <saw|gbn|sr|sack>] [--window <n>]
+  lab1 proxy [--listen <addr>] [--to <addr>] [--loss <p>] [--seed <n>]";
+
+/// Where `recv` listens and `send` sends by default.
+const RECV_ADDR: &str = "127.0.0.1:4000";
+/// Where `proxy` listens by default, point `send --to` here to go through it.
+const PROXY_ADDR: &str = "127.0.0.1:4001";
+
+struct Args {
+    /// Seed of all simulated impairments. `--seed <n>` replays an earlier run,
+    /// a random one is picked otherwise.
+    seed: u64,
+    /// `--wall-clock` runs the threaded endpoints in real time instead of
+    /// the discrete-event simulation.
+    is_wall_clock: bool,
+}
+
+fn parse_args(args: &[String]) -> Result<Args, String> {
+    let mut seed = None;
+    let mut is_wall_clock = false;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--seed" if seed.is_none() => {
+                let value = args.next().ok_or(USAGE)?;
+                seed = Some(
+                    value
+                        .parse()
+                        .map_err(|e| format!("Invalid seed {value}: {e}"))?,
+                );
+            }
+            "--wall-clock" => is_wall_clock = true,
+            _ => return Err(USAGE.to_string()),
+        }
+    }
+    Ok(Args {
+        seed: seed.unwrap_or_else(rand::random),
+        is_wall_clock,
+    })
+}
+
+/// Splits `args` into positional arguments and `--name value` options.
+/// Fails on options other than `names`.
+fn parse_options<'a>(
+    args: &'a [String],
+    names: &[&str],
+) -> Result<(Vec<&'a str>, HashMap<&'a str, &'a str>), String> {
+    let mut positional = Vec::new();
+    let mut options = HashMap::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if !arg.starts_with("--") {
+            positional.push(arg.as_str());
+            continue;
+        }
+        let value = args
+            .next()
+            .filter(|_| names.contains(&arg.as_str()))
+            .ok_or(USAGE)?;
+        options.insert(arg.as_str(), value.as_str());
+    }
+    Ok((positional, options))
+}
+
+fn parse_option<T: FromStr>(
+    options: &HashMap<&str, &str>,
+    name: &str,
+    default: &str,
+) -> Result<T, String>
+where
+    T::Err: Display,
+{
+    let value = options.get(name).copied().unwrap_or(default);
+    value
+        .parse()
+        .map_err(|e| format!("Invalid {name} {value}: {e}"))
+}
+
+/// Protocol and window shared by `send` and `recv`, both sides must agree on them.
+fn parse_protocol(options: &HashMap<&str, &str>) -> Result<(Protocol, ArqConfig), String> {
+    let name = options.get("--protocol").copied().unwrap_or("sr");
+    let protocol =
+        Protocol::from_short_name(name).ok_or_else(|| format!("Unknown protocol {name}"))?;
+    let window = parse_option(options, "--window", "8")?;
+    Ok((protocol, ArqConfig::new(window)))
+}
+
+/// Sends a file over UDP to a `recv` process, or a `proxy` in front of it.
+fn send(args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    let (paths, options) = parse_options(args, &["--to", "--protocol", "--window"])?;
+    let [path] = paths[..] else {
+        return Err(USAGE.into());
+    };
+    let peer: SocketAddr = parse_option(&options, "--to", RECV_ADDR)?;
+    let (protocol, config) = parse_protocol(&options)?;
+    let mut file = File::open(path).map_err(|e| format!("Failed to open {path}: {e}"))?;
+    let socket = UdpSocket::bind("0.0.0.0:0")?;
+    let mut sender = protocol.sender(udp::sender_transport(socket, peer), &config)?;
+    sender
+        .send_stream(&mut file)
+        .and_then(|()| sender.close())?;
+    println!(
+        "Sent {} to {} with {}, efficiency {:.3}",
+        path,
+        peer,
+        protocol.name(),
+        sender.efficiency_coefficient()
+    );
+    Ok(())
+}
+
+/// Receives one file over UDP from a `send` process.
+fn recv(args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    let (paths, options) = parse_options(args, &["--listen", "--protocol", "--window"])?;
+    let [path] = paths[..] else {
+        return Err(USAGE.into());
+    };
+    let addr: SocketAddr = parse_option(&options, "--listen", RECV_ADDR)?;
+    let (protocol, config) = parse_protocol(&options)?;
+    let mut file = File::create(path).map_err(|e| format!("Failed to create {path}: {e}"))?;
+    let socket = UdpSocket::bind(addr)?;
+    println!("Listening on {} with {}...", addr, protocol.name());
+    let mut reader = protocol.reader(udp::reader_transport(socket), &config)?;
+    let bytes_read = reader.read_stream(&mut file)?;
+    reader.close()?;
+    println!("Received {} bytes into {}", bytes_read, path);
+    Ok(())
+}
+
+/// Runs the loss simulator as a UDP proxy between `send` and `recv`.
+fn proxy(args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    let (positional, options) = parse_options(args, &["--listen", "--to", "--loss", "--seed"])?;
+    if !positional.is_empty() {
+        return Err(USAGE.into());
+    }
+    let addr: SocketAddr = parse_option(&options, "--listen", PROXY_ADDR)?;
+    let peer: SocketAddr = parse_option(&options, "--to", RECV_ADDR)?;
+    let loss: f64 = parse_option(&options, "--loss", "0")?;
+    let seed = match options.get("--seed") {
+        Some(_) => parse_option(&options, "--seed", "")?,
+        None => rand::random(),
+    };
+    let proxy = udp::Proxy::start(
+        UdpSocket::bind(addr)?,
+        peer,
+        &Impairments::loss(loss).with_seed(seed),
+    )?;
+    println!(
+        "Forwarding {} to {} with loss rate {} (seed {})",
+        addr, peer, loss, seed
+    );
+    proxy.wait();
+    Ok(())
+}
+
+/// Runs a quiet transfer on the clock picked on the command line.
+fn run<T>(
+    protocol: Protocol,
+    is_wall_clock: bool,
+    config: &ArqConfig,
+    message: &str,
+    impairments: &Impairments,
+    inspect: impl FnOnce(&dyn SenderStats) -> T,
+) -> (String, T) {
+    if is_wall_clock {
+        let (message_read, value) =
+            protocol.silent_setup_with(config, message, impairments, inspect);
+        (message_read.unwrap_or_default(), value)
+    } else {
+        protocol.simulate_with(config, message, impairments, inspect)
+    }
+}
+
+/// Like `run`, but returns the efficiency and the completion time: the whole
+/// transfer on the wall clock, or until the last data packet was acknowledged
+/// on the virtual one. Fails unless the reader got the whole `message`.
+fn run_timed(
+    protocol: Protocol,
+    is_wall_clock: bool,
+    config: &ArqConfig,
+    message: &str,
+    impairments: &Impairments,
+) -> Result<(f64, Duration), Box<dyn std::error::Error>> {
+    let (message_read, eff, completion_time) = if is_wall_clock {
+        let start = Instant::now();
+        let (message_read, eff) =
+            protocol.silent_setup_with(config, message, impairments, |sender| {
+                sender.efficiency_coefficient()
+            });
+        (message_read?.into_bytes(), eff, start.elapsed())
+    } else {
+        let (mut sender, mut reader) = protocol.machines(config)?;
+        let (data, completion_time) = sim::transfer(
+            sender.as_mut(),
+            reader.as_mut(),
+            message.as_bytes(),
+            impairments,
+        )?;
+        (data, sender.efficiency_coefficient(), completion_time)
+    };
+    if message_read != message.as_bytes() {
+        return Err("Received message differs from the sent one".into());
+    }
+    Ok((eff, completion_time))
+}
+
+/// Creates a data file that records the seed it was produced with.
+fn create_data(path: String, seed: u64) -> io::Result<File> {
+    let mut file = File::create(path)?;
+    writeln!(file, "# seed: {}", seed)?;
+    Ok(file)
+}
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match args.first().map(String::as_str) {
+        Some("send") => send(&args[1..]),
+        Some("recv") => recv(&args[1..]),
+        Some("proxy") => proxy(&args[1..]),
+        _ => sweep(parse_args(&args)?),
+    }
+}
+
+/// Collects the efficiency, RTO and congestion window data of the report.
+fn sweep(
+    Args {
+        seed,
+        is_wall_clock,
+    }: Args,
+) -> Result<(), Box<dyn std::error::Error>> {
+    println!("Seed: {}", seed);
     let message = "A".repeat(5_000); // 5_000 bytes message (~20 packets)
     let loss_rates = [0.0, 0.1, 0.2, 0.3, 0.4, 0.5, 0.6, 0.7, 0.8, 0.9];
     // Window sizes 1 to 10
@@ -10,30 +251,337 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     // Plot 1: Efficiency vs Loss Rate (fixed Window Size = 5)
     let fixed_window = 5;
-    let mut gbn_loss_data = File::create("report/data/gbn_vs_loss.dat")?;
-    let mut sr_loss_data = File::create("report/data/sr_vs_loss.dat")?;
+    let mut loss_data = Protocol::ALL
+        .iter()
+        .map(|p| create_data(format!("report/data/{}_vs_loss.dat", p.short_name()), seed))
+        .collect::<Result<Vec<_>, _>>()?;
 
-    println!("Collecting data for Efficiency vs Loss Rate (Window Size = {})...", fixed_window);
+    println!(
+        "Collecting data for Efficiency vs Loss Rate (Window Size = {})...",
+        fixed_window
+    );
+    let config = ArqConfig::new(fixed_window);
     for &loss in &loss_rates {
         println!("Loss rate: {}", loss);
-        let (_, gbn_eff) = gobackn::silent_setup_loss(fixed_window, &message, loss);
-        let (_, sr_eff) = selective_repeat::silent_setup_loss(fixed_window, &message, loss);
-        writeln!(gbn_loss_data, "{} {}", loss, gbn_eff)?;
-        writeln!(sr_loss_data, "{} {}", loss, sr_eff)?;
+        let impairments = Impairments::loss(loss).with_seed(seed);
+        for (protocol, data) in Protocol::ALL.iter().zip(&mut loss_data) {
+            let (_, eff) = run(
+                *protocol,
+                is_wall_clock,
+                &config,
+                &message,
+                &impairments,
+                |sender| sender.efficiency_coefficient(),
+            );
+            writeln!(data, "{} {}", loss, eff)?;
+        }
     }
 
     // Plot 2: Efficiency vs Window Size (fixed Loss Rate = 0.3)
     let fixed_loss = 0.3;
-    let mut gbn_window_data = File::create("report/data/gbn_vs_window.dat")?;
-    let mut sr_window_data = File::create("report/data/sr_vs_window.dat")?;
+    let windowed: Vec<Protocol> = Protocol::ALL
+        .into_iter()
+        .filter(|p| p.is_windowed())
+        .collect();
+    let mut window_data = windowed
+        .iter()
+        .map(|p| {
+            create_data(
+                format!("report/data/{}_vs_window.dat", p.short_name()),
+                seed,
+            )
+        })
+        .collect::<Result<Vec<_>, _>>()?;
 
     println!("Collecting data for Efficiency vs Window Size (Loss Rate = 0.3)...");
     for &window in &window_sizes {
         println!("Window size: {}", window);
-        let (_, gbn_eff) = gobackn::silent_setup_loss(window, &message, fixed_loss);
-        let (_, sr_eff) = selective_repeat::silent_setup_loss(window, &message, fixed_loss);
-        writeln!(gbn_window_data, "{} {}", window, gbn_eff)?;
-        writeln!(sr_window_data, "{} {}", window, sr_eff)?;
+        let config = ArqConfig::new(window);
+        let impairments = Impairments::loss(fixed_loss).with_seed(seed);
+        for (protocol, data) in windowed.iter().zip(&mut window_data) {
+            let (_, eff) = run(
+                *protocol,
+                is_wall_clock,
+                &config,
+                &message,
+                &impairments,
+                |sender| sender.efficiency_coefficient(),
+            );
+            writeln!(data, "{} {}", window, eff)?;
+        }
+    }
+
+    // Plot 3: RTO convergence over a single transfer (Window Size = 5, Loss Rate = 0.1)
+    let rto_loss = 0.1;
+    println!(
+        "Collecting RTO traces (Window Size = {}, Loss Rate = {})...",
+        fixed_window, rto_loss
+    );
+    for protocol in Protocol::ALL {
+        let mut rto_data = create_data(
+            format!("report/data/{}_rto.dat", protocol.short_name()),
+            seed,
+        )?;
+        let config = ArqConfig::new(fixed_window);
+        let impairments = Impairments::loss(rto_loss).with_seed(seed);
+        let (_, history) = run(
+            protocol,
+            is_wall_clock,
+            &config,
+            &message,
+            &impairments,
+            |sender| sender.rto().history().to_vec(),
+        );
+        for sample in history {
+            writeln!(
+                rto_data,
+                "{} {}",
+                sample.at.as_secs_f64() * 1e3,
+                sample.rto.as_secs_f64() * 1e3
+            )?;
+        }
+    }
+
+    // Plot 4: Congestion window dynamics (Window Size = 10, Loss Rate = 0.1)
+    let cwnd_window = 10;
+    let cwnd_loss = 0.1;
+    println!(
+        "Collecting congestion window traces (Window Size = {}, Loss Rate = {})...",
+        cwnd_window, cwnd_loss
+    );
+    for protocol in windowed.iter() {
+        for congestion in [CongestionAlgorithm::Reno, CongestionAlgorithm::Cubic] {
+            let mut cwnd_data = create_data(
+                format!(
+                    "report/data/{}_{}_cwnd.dat",
+                    protocol.short_name(),
+                    congestion.name()
+                ),
+                seed,
+            )?;
+            let config = ArqConfig::new(cwnd_window).with_congestion(congestion);
+            let impairments = Impairments::loss(cwnd_loss).with_seed(seed);
+            let (_, history) = run(
+                *protocol,
+                is_wall_clock,
+                &config,
+                &message,
+                &impairments,
+                |sender| {
+                    sender
+                        .congestion()
+                        .map(|c| c.history().to_vec())
+                        .unwrap_or_default()
+                },
+            );
+            for sample in history {
+                writeln!(
+                    cwnd_data,
+                    "{} {}",
+                    sample.at.as_secs_f64() * 1e3,
+                    sample.cwnd
+                )?;
+            }
+        }
+    }
+
+    // Plot 5: Efficiency vs Bit Error Rate (fixed Window Size = 5, no loss)
+    let bit_error_rates = [0.0, 1e-5, 2e-5, 5e-5, 1e-4, 2e-4, 5e-4];
+    let mut ber_data = Protocol::ALL
+        .iter()
+        .map(|p| create_data(format!("report/data/{}_vs_ber.dat", p.short_name()), seed))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    println!(
+        "Collecting data for Efficiency vs Bit Error Rate (Window Size = {})...",
+        fixed_window
+    );
+    let config = ArqConfig::new(fixed_window);
+    for &ber in &bit_error_rates {
+        println!("Bit error rate: {}", ber);
+        let impairments = Impairments::default()
+            .with_bit_error_rate(ber)
+            .with_seed(seed);
+        for (protocol, data) in Protocol::ALL.iter().zip(&mut ber_data) {
+            let (_, eff) = run(
+                *protocol,
+                is_wall_clock,
+                &config,
+                &message,
+                &impairments,
+                |sender| sender.efficiency_coefficient(),
+            );
+            writeln!(data, "{} {}", ber, eff)?;
+        }
+    }
+
+    // Plot 6: Efficiency vs Mean Burst Length (fixed Window Size = 5,
+    // Gilbert-Elliott Loss Rate = 0.2)
+    let burst_loss = 0.2;
+    let mean_bursts = [1.0, 2.0, 3.0, 4.0, 6.0, 8.0, 12.0, 16.0];
+    let mut burst_data = Protocol::ALL
+        .iter()
+        .map(|p| create_data(format!("report/data/{}_vs_burst.dat", p.short_name()), seed))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    println!(
+        "Collecting data for Efficiency vs Mean Burst Length (Window Size = {}, Loss Rate = {})...",
+        fixed_window, burst_loss
+    );
+    for &mean_burst in &mean_bursts {
+        println!("Mean burst length: {}", mean_burst);
+        let loss = LossModel::GilbertElliott(GilbertElliott::bursts(burst_loss, mean_burst));
+        let impairments = Impairments::default().with_loss(loss).with_seed(seed);
+        for (protocol, data) in Protocol::ALL.iter().zip(&mut burst_data) {
+            let (_, eff) = run(
+                *protocol,
+                is_wall_clock,
+                &config,
+                &message,
+                &impairments,
+                |sender| sender.efficiency_coefficient(),
+            );
+            writeln!(data, "{} {}", mean_burst, eff)?;
+        }
+    }
+
+    // Plot 7: Efficiency vs (Data Loss Rate, ACK Loss Rate) (fixed Window Size = 5)
+    let path_loss_rates = [0.0, 0.1, 0.2, 0.3, 0.4, 0.5];
+    let mut loss_2d_data = windowed
+        .iter()
+        .map(|p| {
+            create_data(
+                format!("report/data/{}_vs_loss_2d.dat", p.short_name()),
+                seed,
+            )
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    println!(
+        "Collecting data for Efficiency vs Data and ACK Loss Rates (Window Size = {})...",
+        fixed_window
+    );
+    for &data_loss in &path_loss_rates {
+        for &ack_loss in &path_loss_rates {
+            println!("Data loss rate: {}, ACK loss rate: {}", data_loss, ack_loss);
+            let impairments = Impairments::asymmetric_loss(data_loss, ack_loss).with_seed(seed);
+            for (protocol, data) in windowed.iter().zip(&mut loss_2d_data) {
+                let (_, eff) = run(
+                    *protocol,
+                    is_wall_clock,
+                    &config,
+                    &message,
+                    &impairments,
+                    |sender| sender.efficiency_coefficient(),
+                );
+                writeln!(data, "{} {} {}", data_loss, ack_loss, eff)?;
+            }
+        }
+        // gnuplot expects a blank line between the rows of a grid
+        for data in &mut loss_2d_data {
+            writeln!(data)?;
+        }
+    }
+
+    // Plot 8: Efficiency and completion time with and without NAKs (fixed
+    // Window Size = 5, 10 ms delay)
+    let nak_delay = Duration::from_millis(10);
+    // SACK blocks already point at the holes, only the other windowed protocols send NAKs
+    let nak_protocols = [Protocol::GoBackN, Protocol::SelectiveRepeat];
+    let mut nak_data = nak_protocols
+        .iter()
+        .map(|p| create_data(format!("report/data/{}_nak.dat", p.short_name()), seed))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    println!(
+        "Collecting data for NAKs vs Loss Rate (Window Size = {}, Delay = {:?})...",
+        fixed_window, nak_delay
+    );
+    for &loss in &path_loss_rates {
+        println!("Loss rate: {}", loss);
+        let impairments = Impairments::loss(loss)
+            .with_channel(ChannelModel::default().with_delay(nak_delay))
+            .with_seed(seed);
+        for (protocol, data) in nak_protocols.iter().zip(&mut nak_data) {
+            let without = run_timed(*protocol, is_wall_clock, &config, &message, &impairments);
+            let with = run_timed(
+                *protocol,
+                is_wall_clock,
+                &config.with_naks(true),
+                &message,
+                &impairments,
+            );
+            match (without, with) {
+                (Ok((eff_off, time_off)), Ok((eff_on, time_on))) => {
+                    writeln!(
+                        data,
+                        "{} {} {} {} {}",
+                        loss,
+                        eff_off,
+                        eff_on,
+                        time_off.as_secs_f64() * 1e3,
+                        time_on.as_secs_f64() * 1e3
+                    )?;
+                }
+                (Err(e), _) | (_, Err(e)) => {
+                    eprintln!("Skipping {} at loss rate {}: {}", protocol.name(), loss, e)
+                }
+            }
+        }
+    }
+
+    // Plot 9: Go-Back-N completion time with and without fast retransmit
+    // (fixed Window Size = 5, 10 ms delay)
+    let duplicate_acks = 3;
+    let mut fast_data = create_data("report/data/gbn_fast_retransmit.dat".to_string(), seed)?;
+
+    println!(
+        "Collecting data for Go-Back-N Fast Retransmit vs Loss Rate \
+         (Window Size = {}, Duplicate ACKs = {})...",
+        fixed_window, duplicate_acks
+    );
+    for &loss in &path_loss_rates {
+        let impairments = Impairments::loss(loss)
+            .with_channel(ChannelModel::default().with_delay(nak_delay))
+            .with_seed(seed);
+        let without = run_timed(
+            Protocol::GoBackN,
+            is_wall_clock,
+            &config,
+            &message,
+            &impairments,
+        );
+        let with = run_timed(
+            Protocol::GoBackN,
+            is_wall_clock,
+            &config.with_fast_retransmit(Some(duplicate_acks)),
+            &message,
+            &impairments,
+        );
+        match (without, with) {
+            (Ok((eff_off, time_off)), Ok((eff_on, time_on))) => {
+                let improvement = 100.0 * (1.0 - time_on.as_secs_f64() / time_off.as_secs_f64());
+                println!(
+                    "Loss rate: {}, completion time {:?} -> {:?} ({:.1}% shorter)",
+                    loss, time_off, time_on, improvement
+                );
+                writeln!(
+                    fast_data,
+                    "{} {} {} {} {}",
+                    loss,
+                    eff_off,
+                    eff_on,
+                    time_off.as_secs_f64() * 1e3,
+                    time_on.as_secs_f64() * 1e3
+                )?;
+            }
+            (Err(e), _) | (_, Err(e)) => eprintln!(
+                "Skipping {} at loss rate {}: {}",
+                Protocol::GoBackN.name(),
+                loss,
+                e
+            ),
+        }
     }
 
     println!("Data collection complete.");
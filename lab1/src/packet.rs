@@ -0,0 +1,394 @@
+use std::io::{self, Read};
+
+use crate::{
+    checksum::Crc32,
+    wire::{ACK_SIZE, PACKET_HEADER_SIZE, SACK_BLOCK_SIZE},
+};
+
+pub const DATA_SIZE: usize = u8::MAX as usize;
+
+pub type AckNumber = u32;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum PacketState {
+    /// Opens a connection, its number is the initial sequence number.
+    Syn,
+    Begin,
+    Ongoing,
+    End,
+    /// Closes a connection, numbered right after the last data packet.
+    Fin,
+}
+
+impl PacketState {
+    const ALL: [Self; 5] = [Self::Syn, Self::Begin, Self::Ongoing, Self::End, Self::Fin];
+
+    /// Header byte of the state on the wire.
+    pub(crate) fn code(self) -> u8 {
+        self as u8
+    }
+
+    pub(crate) fn from_code(code: u8) -> Option<Self> {
+        Self::ALL.get(code as usize).copied()
+    }
+}
+
+/// Width of the header on the wire: number, size, state and checksum.
+const HEADER_BITS: usize = 8 * PACKET_HEADER_SIZE;
+
+#[derive(Debug, Clone)]
+pub struct Packet {
+    pub(crate) number: AckNumber,
+    pub(crate) data: [u8; DATA_SIZE],
+    pub(crate) size: u8,
+    pub(crate) state: PacketState,
+    /// CRC-32 of the header and the payload, kept up to date by the sender.
+    pub(crate) checksum: u32,
+}
+
+impl Packet {
+    pub(crate) fn new(
+        number: AckNumber,
+        data: [u8; DATA_SIZE],
+        size: u8,
+        state: PacketState,
+    ) -> Self {
+        let mut packet = Self {
+            number,
+            data,
+            size,
+            state,
+            checksum: 0,
+        };
+        packet.checksum = packet.compute_checksum();
+        packet
+    }
+
+    /// Connection control packet without payload.
+    pub(crate) fn control(state: PacketState, number: AckNumber) -> Self {
+        Self::new(number, [0; DATA_SIZE], 0, state)
+    }
+
+    /// Renumbers the packet and updates its checksum.
+    pub(crate) fn set_number(&mut self, number: AckNumber) {
+        self.number = number;
+        self.checksum = self.compute_checksum();
+    }
+
+    fn compute_checksum(&self) -> u32 {
+        let mut crc = Crc32::new();
+        crc.update(&self.number.to_be_bytes());
+        crc.update(&[self.size, self.state.code()]);
+        crc.update(self.payload());
+        crc.finish()
+    }
+
+    /// Whether the packet arrived intact. Readers drop corrupted packets
+    /// without acknowledging them, so the sender retransmits them on timeout.
+    pub(crate) fn is_valid(&self) -> bool {
+        self.checksum == self.compute_checksum()
+    }
+
+    pub(crate) fn is_control(&self) -> bool {
+        matches!(self.state, PacketState::Syn | PacketState::Fin)
+    }
+
+    pub(crate) fn payload(&self) -> &[u8] {
+        &self.data[..self.size as usize]
+    }
+}
+
+/// Padding past `size` is not part of the packet.
+impl PartialEq for Packet {
+    fn eq(&self, other: &Self) -> bool {
+        self.number == other.number
+            && self.state == other.state
+            && self.checksum == other.checksum
+            && self.payload() == other.payload()
+    }
+}
+
+impl Eq for Packet {}
+
+/// Something that is sent over a link.
+pub trait Frame {
+    /// Number of bits the value takes on the wire.
+    fn bit_len(&self) -> usize;
+}
+
+/// Something that can suffer bit errors on the wire.
+pub trait Corruptible: Frame {
+    /// Flips the bit at `index`. Returns `false` if the value can no longer
+    /// be parsed, such a frame is dropped by the link.
+    fn flip_bit(&mut self, index: usize) -> bool;
+}
+
+/// Padding past `size` is not sent.
+impl Frame for Packet {
+    fn bit_len(&self) -> usize {
+        HEADER_BITS + 8 * self.size as usize
+    }
+}
+
+/// Bits are numbered in wire order: number, size, state, checksum, then
+/// the payload.
+impl Corruptible for Packet {
+    fn flip_bit(&mut self, index: usize) -> bool {
+        assert!(index < self.bit_len());
+        match index {
+            0..32 => self.number ^= 1 << (31 - index),
+            // Frame length no longer matches the bytes on the wire
+            32..40 => return false,
+            40..48 => match PacketState::from_code(self.state.code() ^ 1 << (47 - index)) {
+                Some(state) => self.state = state,
+                None => return false,
+            },
+            48..80 => self.checksum ^= 1 << (79 - index),
+            _ => {
+                let bit = index - HEADER_BITS;
+                self.data[bit / 8] ^= 1 << (7 - bit % 8);
+            }
+        }
+        true
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum AckKind {
+    Data,
+    SynAck,
+    FinAck,
+    /// Asks for an immediate retransmission of a packet the reader is
+    /// missing, the packets before it have all arrived.
+    Nak,
+    /// Cumulative ACK that also lists blocks of packets the reader holds
+    /// beyond the first gap.
+    Sack,
+}
+
+impl AckKind {
+    const ALL: [Self; 5] = [
+        Self::Data,
+        Self::SynAck,
+        Self::FinAck,
+        Self::Nak,
+        Self::Sack,
+    ];
+
+    /// Kind byte of the ACK on the wire.
+    pub(crate) fn code(self) -> u8 {
+        self as u8
+    }
+
+    pub(crate) fn from_code(code: u8) -> Option<Self> {
+        Self::ALL.get(code as usize).copied()
+    }
+
+    /// Whether the ACK belongs to the handshake rather than to the data.
+    pub(crate) fn is_control(self) -> bool {
+        matches!(self, Self::SynAck | Self::FinAck)
+    }
+}
+
+/// Most SACK blocks a single ACK carries.
+pub const MAX_SACK_BLOCKS: usize = 4;
+
+/// Ranges of sequence numbers received past the cumulative ACK, each from
+/// `start` up to but not including `end`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) struct SackBlocks {
+    blocks: [(AckNumber, AckNumber); MAX_SACK_BLOCKS],
+    len: usize,
+}
+
+impl SackBlocks {
+    /// Appends a block, returns `false` once all of them are taken.
+    pub(crate) fn push(&mut self, start: AckNumber, end: AckNumber) -> bool {
+        if self.len == MAX_SACK_BLOCKS {
+            return false;
+        }
+        self.blocks[self.len] = (start, end);
+        self.len += 1;
+        true
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.len
+    }
+
+    pub(crate) fn iter(&self) -> impl Iterator<Item = (AckNumber, AckNumber)> + '_ {
+        self.blocks[..self.len].iter().copied()
+    }
+}
+
+/// Acknowledgement sent back by the reader.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Ack {
+    pub(crate) number: AckNumber,
+    pub(crate) kind: AckKind,
+    /// Only used by `AckKind::Sack`.
+    pub(crate) sack: SackBlocks,
+}
+
+impl Ack {
+    pub(crate) fn new(number: AckNumber, kind: AckKind) -> Self {
+        Self {
+            number,
+            kind,
+            sack: SackBlocks::default(),
+        }
+    }
+
+    pub(crate) fn data(number: AckNumber) -> Self {
+        Self::new(number, AckKind::Data)
+    }
+
+    pub(crate) fn nak(number: AckNumber) -> Self {
+        Self::new(number, AckKind::Nak)
+    }
+
+    /// Cumulative ACK of `number` and everything before it, plus `sack`.
+    pub(crate) fn sack(number: AckNumber, sack: SackBlocks) -> Self {
+        Self {
+            number,
+            kind: AckKind::Sack,
+            sack,
+        }
+    }
+}
+
+/// Number and a kind byte, a SACK adds a count byte and its blocks.
+impl Frame for Ack {
+    fn bit_len(&self) -> usize {
+        let blocks = if self.kind == AckKind::Sack {
+            1 + SACK_BLOCK_SIZE * self.sack.len()
+        } else {
+            0
+        };
+        8 * (ACK_SIZE + blocks)
+    }
+}
+
+/// Cuts a byte stream into packets on demand, so that only the packets in
+/// the sender's window are held in memory.
+///
+/// A message always spans at least two packets, so `Begin` and `End` are
+/// never the same packet. The source is read one chunk ahead to know which
+/// packet is the last one.
+pub(crate) struct Packetizer<R> {
+    source: R,
+    lookahead: Option<([u8; DATA_SIZE], usize)>,
+    packets_total: usize,
+    is_finished: bool,
+}
+
+impl<R: Read> Packetizer<R> {
+    pub(crate) fn new(source: R) -> Self {
+        Self {
+            source,
+            lookahead: None,
+            packets_total: 0,
+            is_finished: false,
+        }
+    }
+
+    /// Number of packets produced so far.
+    #[cfg(test)]
+    pub(crate) fn packets_total(&self) -> usize {
+        self.packets_total
+    }
+
+    pub(crate) fn is_finished(&self) -> bool {
+        self.is_finished
+    }
+
+    fn read_chunk(&mut self) -> io::Result<([u8; DATA_SIZE], usize)> {
+        let mut data = [0; DATA_SIZE];
+        let mut size = 0;
+        while size < DATA_SIZE {
+            match self.source.read(&mut data[size..]) {
+                Ok(0) => break,
+                Ok(n) => size += n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok((data, size))
+    }
+
+    /// Next packet of the message numbered by its absolute index, or `None`
+    /// once the `End` packet was produced.
+    pub(crate) fn next_packet(&mut self) -> io::Result<Option<Packet>> {
+        if self.is_finished {
+            return Ok(None);
+        }
+        let (data, size) = match self.lookahead.take() {
+            Some(chunk) => chunk,
+            None => self.read_chunk()?,
+        };
+        let state = if self.packets_total == 0 {
+            PacketState::Begin
+        } else {
+            let next = self.read_chunk()?;
+            if next.1 == 0 {
+                self.is_finished = true;
+                PacketState::End
+            } else {
+                self.lookahead = Some(next);
+                PacketState::Ongoing
+            }
+        };
+        let packet = Packet::new(self.packets_total as AckNumber, data, size as u8, state);
+        self.packets_total += 1;
+        Ok(Some(packet))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn packetize(message: &[u8]) -> Vec<Packet> {
+        let mut packetizer = Packetizer::new(message);
+        let mut packets = Vec::new();
+        while let Some(packet) = packetizer.next_packet().unwrap() {
+            packets.push(packet);
+        }
+        assert!(packetizer.is_finished());
+        assert_eq!(packetizer.packets_total(), packets.len());
+        packets
+    }
+
+    #[test]
+    fn test_packets_total() {
+        assert_eq!(packetize(&[]).len(), 2);
+        assert_eq!(packetize(&[0; DATA_SIZE]).len(), 2);
+        assert_eq!(packetize(&[0; 2 * DATA_SIZE]).len(), 2);
+        assert_eq!(packetize(&[0; 2 * DATA_SIZE + 1]).len(), 3);
+    }
+
+    #[test]
+    fn test_packetizer() {
+        let bytes = vec![7; DATA_SIZE + 10];
+        let packets = packetize(&bytes);
+        assert!(matches!(packets[0].state, PacketState::Begin));
+        assert_eq!(packets[0].payload().len(), DATA_SIZE);
+        assert!(matches!(packets[1].state, PacketState::End));
+        assert_eq!(packets[1].number, 1);
+        assert_eq!(packets[1].payload(), &[7; 10]);
+        assert!(packets.iter().all(Packet::is_valid));
+    }
+
+    #[test]
+    fn test_single_bit_errors_are_detected() {
+        let mut packet = packetize(b"checksum").remove(0);
+        packet.set_number(12_345);
+        assert!(packet.is_valid());
+        for index in 0..packet.bit_len() {
+            let mut corrupted = packet.clone();
+            if corrupted.flip_bit(index) {
+                assert!(!corrupted.is_valid(), "bit {index}");
+            }
+        }
+    }
+}
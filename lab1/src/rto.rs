@@ -0,0 +1,188 @@
+use std::time::{Duration, Instant};
+
+use crate::arq::TIMEOUT;
+
+/// RTO used before the first RTT sample arrives.
+pub const RTO_INITIAL: Duration = TIMEOUT;
+pub const RTO_MIN: Duration = Duration::from_millis(20);
+/// Ceiling of the estimate. Lower than the 60 s of RFC 6298: at a tenth of
+/// `TIMEOUT_TOTAL`, even the longest path gets several retransmissions before
+/// the transfer is given up.
+pub const RTO_MAX: Duration = Duration::from_secs(3);
+/// Backoff stops at the old fixed timeout: a transfer losing most of its
+/// packets needs every retransmission it can fit into `TIMEOUT_TOTAL`. An
+/// estimate already above it, on a long path, is not backed off at all.
+pub const BACKOFF_MAX: Duration = TIMEOUT;
+
+/// Endpoints poll their channels every few milliseconds, so RTT samples are
+/// only that precise. Plays the role of `G` from RFC 6298.
+const CLOCK_GRANULARITY: Duration = Duration::from_millis(10);
+
+#[derive(Debug, Clone, Copy)]
+pub struct RtoSample {
+    /// Time since the estimator was reset.
+    pub at: Duration,
+    /// Measured round trip, `None` when the entry records a backoff or its end.
+    pub rtt: Option<Duration>,
+    pub srtt: Option<Duration>,
+    pub rto: Duration,
+}
+
+/// Jacobson/Karels retransmission timeout estimator (RFC 6298) with
+/// exponential backoff.
+///
+/// Callers are expected to apply Karn's algorithm themselves: only
+/// packets that were transmitted exactly once may be fed to `on_sample`.
+#[derive(Debug, Clone)]
+pub struct RtoEstimator {
+    srtt: Option<Duration>,
+    rttvar: Duration,
+    rto: Duration,
+    backoff: u32,
+    start: Instant,
+    history: Vec<RtoSample>,
+}
+
+impl Default for RtoEstimator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RtoEstimator {
+    #[must_use]
+    pub fn new() -> Self {
+        Self {
+            srtt: None,
+            rttvar: Duration::ZERO,
+            rto: RTO_INITIAL,
+            backoff: 0,
+            start: Instant::now(),
+            history: Vec::new(),
+        }
+    }
+
+    /// Starts over, `now` is the origin of the history.
+    pub fn reset(&mut self, now: Instant) {
+        *self = Self::new();
+        self.start = now;
+    }
+
+    /// Current timeout including backoff.
+    #[must_use]
+    pub fn current(&self) -> Duration {
+        self.rto
+            .saturating_mul(1 << self.backoff.min(16))
+            .min(self.backoff_limit())
+    }
+
+    fn backoff_limit(&self) -> Duration {
+        self.rto.max(BACKOFF_MAX)
+    }
+
+    #[must_use]
+    pub fn srtt(&self) -> Option<Duration> {
+        self.srtt
+    }
+
+    /// RTO after every sample and backoff since the last reset.
+    #[must_use]
+    pub fn history(&self) -> &[RtoSample] {
+        &self.history
+    }
+
+    pub fn on_sample(&mut self, rtt: Duration, now: Instant) {
+        match self.srtt {
+            None => {
+                self.srtt = Some(rtt);
+                self.rttvar = rtt / 2;
+            }
+            Some(srtt) => {
+                // RTTVAR = 3/4 RTTVAR + 1/4 |SRTT - R|, SRTT = 7/8 SRTT + 1/8 R
+                self.rttvar = (self.rttvar * 3 + srtt.abs_diff(rtt)) / 4;
+                self.srtt = Some((srtt * 7 + rtt) / 8);
+            }
+        }
+        let srtt = self.srtt.unwrap_or(rtt);
+        self.rto = (srtt + CLOCK_GRANULARITY.max(self.rttvar * 4)).clamp(RTO_MIN, RTO_MAX);
+        self.backoff = 0;
+        self.record(Some(rtt), now);
+    }
+
+    /// An ACK of new data ends the backoff: the path works again, even when
+    /// Karn's algorithm keeps the ACK from giving a sample.
+    pub fn on_progress(&mut self, now: Instant) {
+        if self.backoff > 0 {
+            self.backoff = 0;
+            self.record(None, now);
+        }
+    }
+
+    pub fn on_timeout(&mut self, now: Instant) {
+        if self.current() < self.backoff_limit() {
+            self.backoff += 1;
+        }
+        self.record(None, now);
+    }
+
+    fn record(&mut self, rtt: Option<Duration>, now: Instant) {
+        self.history.push(RtoSample {
+            at: now.saturating_duration_since(self.start),
+            rtt,
+            srtt: self.srtt,
+            rto: self.current(),
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rto_converges() {
+        let now = Instant::now();
+        let mut rto = RtoEstimator::new();
+        assert_eq!(rto.current(), RTO_INITIAL);
+        for _ in 0..50 {
+            rto.on_sample(Duration::from_millis(30), now);
+        }
+        assert_eq!(rto.srtt(), Some(Duration::from_millis(30)));
+        assert!(rto.current() <= Duration::from_millis(41));
+        assert!(rto.current() >= Duration::from_millis(30));
+    }
+
+    #[test]
+    fn test_rto_backoff() {
+        let now = Instant::now();
+        let mut rto = RtoEstimator::new();
+        rto.on_sample(Duration::from_millis(10), now);
+        let base = rto.current();
+        rto.on_timeout(now);
+        assert_eq!(rto.current(), base * 2);
+        rto.on_timeout(now);
+        assert_eq!(rto.current(), base * 4);
+        for _ in 0..10 {
+            rto.on_timeout(now);
+        }
+        assert_eq!(rto.current(), BACKOFF_MAX);
+        rto.on_sample(Duration::from_millis(10), now);
+        assert!(rto.current() < BACKOFF_MAX);
+        assert_eq!(rto.history().len(), 14);
+    }
+
+    #[test]
+    fn test_rto_long_path() {
+        let now = Instant::now();
+        let mut rto = RtoEstimator::new();
+        for _ in 0..10 {
+            rto.on_sample(Duration::from_millis(300), now);
+        }
+        let base = rto.current();
+        assert!(base > Duration::from_millis(300), "{base:?}");
+        // Already above the old fixed timeout, so there is nothing to back off
+        rto.on_timeout(now);
+        rto.on_timeout(now);
+        assert_eq!(rto.current(), base);
+    }
+}
@@ -0,0 +1,733 @@
+use std::{
+    collections::{BTreeMap, VecDeque, btree_map::Entry},
+    time::Instant,
+};
+
+use crate::{
+    arq::{Protocol, ReaderStats, SenderStats},
+    congestion::{CongestionAlgorithm, CongestionControl, CongestionWindow},
+    connection::SenderConnection,
+    driver::{ReaderDriver, SenderDriver},
+    error::ArqError,
+    machine::{ArqReceiverMachine, ArqSenderMachine, ReaderCore},
+    packet::{Ack, AckKind, AckNumber, MAX_SACK_BLOCKS, Packet, SackBlocks},
+    rto::RtoEstimator,
+    seq::SequenceSpace,
+    stats::TransferStats,
+};
+
+/// SACK blocks a reader reports by default.
+pub const SACK_BLOCKS: usize = 3;
+
+/// Scoreboard entry of a packet in the window.
+#[derive(Debug, Clone)]
+struct SenderPacket {
+    packet: Packet,
+    /// Reported in a SACK block, the reader holds it past a gap.
+    is_sacked: bool,
+    last_sent: Option<Instant>,
+    /// Order of the last transmission among all packets put on the wire.
+    sent_order: usize,
+    is_retransmitted: bool,
+    /// Waiting for its first transmission or a retransmission.
+    is_due: bool,
+}
+
+/// SACK sender over `mpsc` channels.
+pub type Sender = SenderDriver<SenderMachine>;
+/// SACK reader over `mpsc` channels.
+pub type Reader = ReaderDriver<ReaderMachine>;
+
+/// Sender of the SACK hybrid without I/O: the window slides on cumulative
+/// ACKs like Go-Back-N, but a scoreboard of the SACK blocks lets it resend
+/// only the holes. A hole is taken for lost once a packet sent after it
+/// was SACKed, every packet in flight also keeps its own timer.
+pub struct SenderMachine {
+    window_size: AckNumber,
+    /// Absolute index of the oldest unacknowledged packet, keeps growing
+    /// across the messages of a connection.
+    base: usize,
+    packets_total: usize,
+    packets_send: usize,
+    packets_ack: usize,
+    /// Transmissions so far, numbers `sent_order`.
+    transmissions: usize,
+    /// Transmissions before the last window reduction. Losses of packets
+    /// sent before it belong to a window that was already reduced.
+    recovery_point: usize,
+    /// Transmissions before the last timeout, the same for expired timers.
+    timeout_point: usize,
+    window_packets: VecDeque<SenderPacket>,
+    connection: SenderConnection,
+    rto: RtoEstimator,
+    congestion: CongestionWindow,
+    space: SequenceSpace,
+    is_debug: bool,
+}
+
+impl SenderMachine {
+    #[must_use]
+    pub fn new(window_size: AckNumber, is_debug: bool) -> Self {
+        Self {
+            window_size,
+            base: 0,
+            packets_total: 0,
+            packets_send: 0,
+            packets_ack: 0,
+            transmissions: 0,
+            recovery_point: 0,
+            timeout_point: 0,
+            window_packets: VecDeque::with_capacity(window_size as usize),
+            connection: SenderConnection::default(),
+            rto: RtoEstimator::new(),
+            congestion: CongestionWindow::new(CongestionAlgorithm::Fixed.build(), window_size),
+            space: SequenceSpace::default(),
+            is_debug,
+        }
+    }
+
+    /// Numbers packets modulo `2^bits`. Fails unless the window is at most
+    /// `2^(bits-1)`, the reader buffers past gaps like Selective Repeat.
+    pub fn with_sequence_bits(mut self, bits: u32) -> Result<Self, ArqError> {
+        let space = SequenceSpace::new(bits)?;
+        space.check_window(self.window_size, space.max_window_sr())?;
+        self.space = space;
+        Ok(self)
+    }
+
+    /// Limits the window to `min(cwnd, window_size)` using `control`.
+    #[must_use]
+    pub fn with_congestion_control(mut self, control: Box<dyn CongestionControl>) -> Self {
+        self.congestion = CongestionWindow::new(control, self.window_size);
+        self
+    }
+
+    /// Retransmission deadline of a packet in flight.
+    fn deadline(&self, sender_packet: &SenderPacket) -> Option<Instant> {
+        if sender_packet.is_sacked || sender_packet.is_due {
+            return None;
+        }
+        sender_packet
+            .last_sent
+            .map(|last_sent| last_sent + self.rto.current())
+    }
+
+    /// Window offsets from `start` up to `end` if they are all in flight.
+    fn block_offsets(&self, start: AckNumber, end: AckNumber) -> Option<(usize, usize)> {
+        let base = self.space.wrap(self.base);
+        let from = self.space.distance(base, start) as usize;
+        let to = from + self.space.distance(start, end) as usize;
+        let is_sent = |offset: usize| {
+            self.window_packets
+                .get(offset)
+                .is_some_and(|p| p.last_sent.is_some())
+        };
+        (from < to && is_sent(to - 1)).then_some((from, to))
+    }
+
+    /// Marks the packets of `sack` as held by the reader. Returns how many
+    /// of them are new to the scoreboard.
+    fn mark_sacked(&mut self, sack: &SackBlocks, now: Instant) -> usize {
+        let mut count = 0;
+        for (start, end) in sack.iter() {
+            let Some((from, to)) = self.block_offsets(start, end) else {
+                continue;
+            };
+            for sender_packet in self.window_packets.range_mut(from..to) {
+                if sender_packet.is_sacked {
+                    continue;
+                }
+                sender_packet.is_sacked = true;
+                sender_packet.is_due = false;
+                count += 1;
+                // Karn's algorithm: a retransmitted packet gives an ambiguous sample
+                if let (false, Some(last_sent)) =
+                    (sender_packet.is_retransmitted, sender_packet.last_sent)
+                {
+                    self.rto
+                        .on_sample(now.saturating_duration_since(last_sent), now);
+                }
+            }
+        }
+        count
+    }
+
+    /// Schedules the holes before the latest SACKed transmission for a
+    /// retransmission. Returns whether any new hole was sent after the last
+    /// window reduction.
+    fn retransmit_holes(&mut self) -> bool {
+        let Some(latest) = self
+            .window_packets
+            .iter()
+            .filter(|p| p.is_sacked)
+            .map(|p| p.sent_order)
+            .max()
+        else {
+            return false;
+        };
+        let mut is_loss = false;
+        let recovery_point = self.recovery_point;
+        for sender_packet in &mut self.window_packets {
+            if sender_packet.is_sacked
+                || sender_packet.is_due
+                || sender_packet.last_sent.is_none()
+                || sender_packet.sent_order > latest
+            {
+                continue;
+            }
+            sender_packet.is_due = true;
+            is_loss |= sender_packet.sent_order >= recovery_point;
+            if self.is_debug {
+                eprintln!("Sender | Hole at packet: {}", sender_packet.packet.number);
+            }
+        }
+        is_loss
+    }
+}
+
+impl ArqSenderMachine for SenderMachine {
+    fn space(&self) -> SequenceSpace {
+        self.space
+    }
+
+    fn connect(&mut self, isn: AckNumber, now: Instant) {
+        self.rto.reset(now);
+        self.congestion.reset(now);
+        self.window_packets.clear();
+        let isn = self.space.wrap(isn as usize);
+        self.base = isn as usize + 1;
+        self.connection.connect(isn);
+    }
+
+    fn is_connected(&self) -> bool {
+        self.connection.is_established()
+    }
+
+    fn start_message(&mut self) {
+        self.packets_total = 0;
+        self.packets_send = 0;
+        self.packets_ack = 0;
+    }
+
+    fn has_room(&self) -> bool {
+        self.is_connected() && self.window_packets.len() < self.congestion.effective() as usize
+    }
+
+    fn push(&mut self, mut packet: Packet) {
+        packet.set_number(self.space.wrap(self.base + self.window_packets.len()));
+        self.window_packets.push_back(SenderPacket {
+            packet,
+            is_sacked: false,
+            last_sent: None,
+            sent_order: 0,
+            is_retransmitted: false,
+            is_due: true,
+        });
+        self.packets_total += 1;
+    }
+
+    fn is_idle(&self) -> bool {
+        self.window_packets.is_empty()
+    }
+
+    fn close(&mut self) {
+        self.connection.close(self.space.wrap(self.base));
+    }
+
+    fn is_closed(&self) -> bool {
+        self.connection.is_closed()
+    }
+
+    fn oldest_unacked(&self) -> Option<AckNumber> {
+        self.connection
+            .pending()
+            .or_else(|| self.window_packets.front().map(|p| p.packet.number))
+    }
+
+    fn handle_ack(&mut self, ack: Ack, now: Instant) {
+        if self.connection.on_ack(ack, &mut self.rto, now) {
+            return;
+        }
+        if !matches!(ack.kind, AckKind::Data | AckKind::Sack) {
+            return;
+        }
+        // The cumulative part: everything up to `ack.number` arrived in order
+        let offset = self.space.distance(self.space.wrap(self.base), ack.number) as usize;
+        let is_in_flight = self
+            .window_packets
+            .get(offset)
+            .is_some_and(|p| p.last_sent.is_some());
+        let cumulative = if is_in_flight { offset + 1 } else { 0 };
+        let mut count = 0;
+        for acked in self.window_packets.drain(..cumulative) {
+            self.base += 1;
+            self.packets_ack += 1;
+            if acked.is_sacked {
+                continue;
+            }
+            count += 1;
+            if acked.packet.number == ack.number
+                && let (false, Some(last_sent)) = (acked.is_retransmitted, acked.last_sent)
+            {
+                self.rto
+                    .on_sample(now.saturating_duration_since(last_sent), now);
+            }
+        }
+        count += self.mark_sacked(&ack.sack, now);
+        if count > 0 {
+            self.rto.on_progress(now);
+            self.congestion.on_ack(count as u32, now);
+        }
+        if self.retransmit_holes() {
+            self.recovery_point = self.transmissions;
+            self.congestion.on_loss(now);
+        }
+        if self.is_debug && count > 0 {
+            eprintln!(
+                "Sender | Ack up to packet: {}, {} sack blocks, {} out of {}",
+                ack.number,
+                ack.sack.len(),
+                self.packets_ack,
+                self.packets_total
+            );
+        }
+    }
+
+    fn handle_timeout(&mut self, now: Instant) {
+        self.connection.on_timeout(&mut self.rto, now);
+        let mut is_timeout = false;
+        for index in 0..self.window_packets.len() {
+            if self
+                .deadline(&self.window_packets[index])
+                .is_some_and(|deadline| now >= deadline)
+            {
+                self.window_packets[index].is_due = true;
+                is_timeout |= self.window_packets[index].sent_order >= self.timeout_point;
+            }
+        }
+        // One backoff and one reduction per window, like the single timer
+        // of Go-Back-N
+        if is_timeout {
+            self.recovery_point = self.transmissions;
+            self.timeout_point = self.transmissions;
+            self.rto.on_timeout(now);
+            self.congestion.on_timeout(now);
+            if self.is_debug {
+                eprintln!(
+                    "Sender | Timeout at base {}, rto: {}ms",
+                    self.base,
+                    self.rto.current().as_millis()
+                );
+            }
+        }
+    }
+
+    fn poll_timeout(&self) -> Option<Instant> {
+        let control = self.connection.poll_timeout(&self.rto);
+        self.window_packets
+            .iter()
+            .filter_map(|sender_packet| self.deadline(sender_packet))
+            .chain(control)
+            .min()
+    }
+
+    fn poll_transmit(&mut self, now: Instant) -> Option<Packet> {
+        if let Some(packet) = self.connection.poll_transmit(now) {
+            if self.is_debug {
+                eprintln!("Sender | Send {:?} {}", packet.state, packet.number);
+            }
+            return Some(packet);
+        }
+        let sender_packet = self.window_packets.iter_mut().find(|p| p.is_due)?;
+        sender_packet.is_due = false;
+        sender_packet.is_retransmitted |= sender_packet.last_sent.is_some();
+        sender_packet.last_sent = Some(now);
+        sender_packet.sent_order = self.transmissions;
+        self.transmissions += 1;
+        self.packets_send += 1;
+        let packet = sender_packet.packet.clone();
+        if self.is_debug {
+            eprintln!(
+                "Sender | Send packet: {}, size: {}, state: {:?}",
+                packet.number, packet.size, packet.state
+            );
+        }
+        Some(packet)
+    }
+}
+
+impl SenderStats for SenderMachine {
+    fn efficiency_coefficient(&self) -> f64 {
+        self.packets_total as f64 / self.packets_send as f64
+    }
+
+    fn retransmissions(&self) -> usize {
+        self.packets_send.saturating_sub(self.packets_total)
+    }
+
+    fn rto(&self) -> &RtoEstimator {
+        &self.rto
+    }
+
+    fn congestion(&self) -> Option<&CongestionWindow> {
+        Some(&self.congestion)
+    }
+}
+
+/// Reader of the SACK hybrid without I/O. Packets past a gap are buffered
+/// as in Selective Repeat, every packet is answered with the cumulative
+/// ACK and the blocks of buffered packets, lowest first.
+pub struct ReaderMachine {
+    core: ReaderCore,
+    window_size: AckNumber,
+    /// Out-of-order packets by absolute index.
+    buffer: BTreeMap<usize, Packet>,
+    sack_blocks: usize,
+}
+
+impl ReaderMachine {
+    #[must_use]
+    pub fn new(window_size: AckNumber, is_debug: bool) -> Self {
+        Self {
+            core: ReaderCore::new(SequenceSpace::default(), is_debug),
+            window_size,
+            buffer: BTreeMap::new(),
+            sack_blocks: SACK_BLOCKS,
+        }
+    }
+
+    /// Reports at most `sack_blocks` blocks per ACK, zero sends plain
+    /// cumulative ACKs. Fails past `MAX_SACK_BLOCKS`.
+    pub fn with_sack_blocks(mut self, sack_blocks: usize) -> Result<Self, ArqError> {
+        if sack_blocks > MAX_SACK_BLOCKS {
+            return Err(ArqError::TooManySackBlocks { sack_blocks });
+        }
+        self.sack_blocks = sack_blocks;
+        Ok(self)
+    }
+
+    /// Must match the sender's sequence space. Fails unless the window is
+    /// at most `2^(bits-1)`.
+    pub fn with_sequence_bits(mut self, bits: u32) -> Result<Self, ArqError> {
+        let space = SequenceSpace::new(bits)?;
+        space.check_window(self.window_size, space.max_window_sr())?;
+        self.core.space = space;
+        Ok(self)
+    }
+
+    /// Cumulative ACK of the packets delivered so far with the runs of
+    /// buffered packets after them.
+    fn sack(&self) -> Ack {
+        let mut runs: Vec<(usize, usize)> = Vec::new();
+        for &index in self.buffer.keys() {
+            match runs.last_mut() {
+                Some((_, end)) if *end == index => *end += 1,
+                _ => runs.push((index, index + 1)),
+            }
+        }
+        let space = self.core.space;
+        let mut sack = SackBlocks::default();
+        for (start, end) in runs.into_iter().take(self.sack_blocks) {
+            sack.push(space.wrap(start), space.wrap(end));
+        }
+        Ack::sack(space.wrap(self.core.expected - 1), sack)
+    }
+}
+
+impl ReaderStats for ReaderMachine {
+    fn packets_read(&self) -> usize {
+        self.core.packets_read
+    }
+
+    fn duplicates(&self) -> usize {
+        self.core.duplicates
+    }
+}
+
+impl ArqReceiverMachine for ReaderMachine {
+    fn handle_packet(&mut self, packet: Packet, now: Instant) -> Result<(), ArqError> {
+        let connection = self.core.connection;
+        let Some(packet) = self.core.receive(packet, now)? else {
+            if self.core.connection != connection && self.core.connection.is_established() {
+                self.buffer.clear();
+            }
+            return Ok(());
+        };
+        let space = self.core.space;
+        let wire_number = packet.number;
+        let offset = space.distance(space.wrap(self.core.expected), wire_number);
+        if offset >= self.window_size
+            && u64::from(offset) >= space.modulus() - u64::from(self.window_size)
+        {
+            // Behind the window: the sender missed our ACK
+            self.core.duplicates += 1;
+            self.core.send_ack(self.sack());
+            if self.core.is_debug {
+                eprintln!("Reader | ReAck packet {wire_number}");
+            }
+            return Ok(());
+        }
+        if offset >= self.window_size {
+            if self.core.is_debug {
+                eprintln!("Reader | Packet {wire_number} out of window");
+            }
+            return Ok(());
+        }
+        self.core.ensure_reading(&packet)?;
+        let index = self.core.expected + offset as usize;
+        match self.buffer.entry(index) {
+            Entry::Occupied(_) => self.core.duplicates += 1,
+            Entry::Vacant(entry) => {
+                entry.insert(packet);
+            }
+        }
+        while let Some(packet) = self.buffer.remove(&self.core.expected) {
+            if self.core.is_debug {
+                eprintln!(
+                    "Reader | Deliver packet {}, state: {:?}",
+                    packet.number, packet.state
+                );
+            }
+            self.core.deliver(packet)?;
+        }
+        let ack = self.sack();
+        if self.core.is_debug {
+            eprintln!(
+                "Reader | Ack packet {}, {} sack blocks",
+                ack.number,
+                ack.sack.len()
+            );
+        }
+        self.core.send_ack(ack);
+        Ok(())
+    }
+
+    fn handle_timeout(&mut self, now: Instant) {
+        self.core.handle_timeout(now);
+    }
+
+    fn poll_timeout(&self) -> Option<Instant> {
+        self.core.poll_timeout()
+    }
+
+    fn poll_transmit(&mut self) -> Option<Ack> {
+        self.core.poll_transmit()
+    }
+
+    fn poll_deliver(&mut self) -> Option<Packet> {
+        self.core.poll_deliver()
+    }
+
+    fn close(&mut self) {
+        self.core.close();
+    }
+
+    fn is_established(&self) -> bool {
+        self.core.connection.is_established()
+    }
+
+    fn is_closed(&self) -> bool {
+        self.core.connection.is_closed()
+    }
+
+    fn expected(&self) -> AckNumber {
+        self.core.expected()
+    }
+
+    fn is_time_wait_over(&self, now: Instant) -> bool {
+        self.core.is_time_wait_over(now)
+    }
+
+    fn is_cumulative(&self) -> bool {
+        true
+    }
+}
+
+#[must_use]
+pub fn setup(window_size: AckNumber, message: &str) -> (String, TransferStats) {
+    Protocol::Sack.setup(window_size, message)
+}
+
+#[must_use]
+pub fn silent_setup_loss(
+    window_size: AckNumber,
+    message: &str,
+    loss: f64,
+    seed: Option<u64>,
+) -> (String, TransferStats) {
+    Protocol::Sack.silent_setup_loss(window_size, message, loss, seed)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs::File, io::Read, iter};
+
+    use super::*;
+    use crate::packet::{DATA_SIZE, PacketState};
+
+    fn get_file_string() -> String {
+        let mut s = String::new();
+        File::open("src/lib.rs")
+            .unwrap()
+            .read_to_string(&mut s)
+            .unwrap();
+        s
+    }
+
+    fn packet(number: AckNumber, state: PacketState) -> Packet {
+        Packet::new(number, [0; DATA_SIZE], 1, state)
+    }
+
+    fn blocks(ranges: &[(AckNumber, AckNumber)]) -> SackBlocks {
+        let mut sack = SackBlocks::default();
+        for &(start, end) in ranges {
+            assert!(sack.push(start, end));
+        }
+        sack
+    }
+
+    #[test]
+    fn test_sack_file() {
+        let message_send = get_file_string();
+        let message_received = setup(5, &message_send).0;
+        assert_eq!(message_send, message_received);
+        let message_received = setup(1, &message_send).0;
+        assert_eq!(message_send, message_received);
+    }
+
+    #[test]
+    fn test_sack_file_loss() {
+        let message_send = get_file_string();
+        let message_received = silent_setup_loss(3, &message_send, 0.0, Some(0)).0;
+        assert_eq!(message_send, message_received);
+        let message_received = silent_setup_loss(3, &message_send, 0.25, Some(0)).0;
+        assert_eq!(message_send, message_received);
+        let message_received = silent_setup_loss(3, &message_send, 0.5, Some(0)).0;
+        assert_eq!(message_send, message_received);
+    }
+
+    #[test]
+    fn test_sack_reader_blocks() {
+        let now = Instant::now();
+        let mut reader = ReaderMachine::new(8, false).with_sack_blocks(2).unwrap();
+        reader
+            .handle_packet(Packet::control(PacketState::Syn, 9), now)
+            .unwrap();
+        reader
+            .handle_packet(packet(10, PacketState::Begin), now)
+            .unwrap();
+        for number in [12, 14, 15, 17, 11] {
+            reader
+                .handle_packet(packet(number, PacketState::Ongoing), now)
+                .unwrap();
+        }
+        let acks: Vec<Ack> = iter::from_fn(|| reader.poll_transmit()).skip(1).collect();
+        let expected = [
+            Ack::sack(10, blocks(&[])),
+            Ack::sack(10, blocks(&[(12, 13)])),
+            Ack::sack(10, blocks(&[(12, 13), (14, 15)])),
+            Ack::sack(10, blocks(&[(12, 13), (14, 16)])),
+            // Only the lowest blocks fit
+            Ack::sack(10, blocks(&[(12, 13), (14, 16)])),
+            Ack::sack(12, blocks(&[(14, 16), (17, 18)])),
+        ];
+        assert_eq!(acks, expected);
+        assert!(
+            ReaderMachine::new(8, false)
+                .with_sack_blocks(MAX_SACK_BLOCKS + 1)
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_sack_retransmits_holes() {
+        let now = Instant::now();
+        let mut sender = SenderMachine::new(6, false);
+        sender.connect(9, now);
+        sender.handle_ack(Ack::new(9, AckKind::SynAck), now);
+        for number in 10..16 {
+            sender.push(packet(number, PacketState::Ongoing));
+        }
+        assert_eq!(iter::from_fn(|| sender.poll_transmit(now)).count(), 6);
+        let resend = |sender: &mut SenderMachine, ack| {
+            sender.handle_ack(ack, now);
+            iter::from_fn(|| sender.poll_transmit(now))
+                .map(|p| p.number)
+                .collect::<Vec<_>>()
+        };
+        // Packets 11 and 14 are lost, the others arrive in order
+        assert_eq!(resend(&mut sender, Ack::sack(10, blocks(&[]))), []);
+        assert_eq!(
+            resend(&mut sender, Ack::sack(10, blocks(&[(12, 13)]))),
+            [11]
+        );
+        assert_eq!(resend(&mut sender, Ack::sack(10, blocks(&[(12, 14)]))), []);
+        assert_eq!(
+            resend(&mut sender, Ack::sack(10, blocks(&[(12, 14), (15, 16)]))),
+            [14]
+        );
+        // The retransmission of 14 went out after 15, it is not a hole again
+        assert_eq!(resend(&mut sender, Ack::sack(13, blocks(&[(15, 16)]))), []);
+        assert!(!sender.is_idle());
+        assert_eq!(resend(&mut sender, Ack::sack(15, blocks(&[]))), []);
+        assert!(sender.is_idle());
+        assert_eq!(sender.efficiency_coefficient(), 6.0 / 8.0);
+    }
+
+    #[test]
+    fn test_sack_one_reduction_per_window() {
+        let now = Instant::now();
+        let mut sender = SenderMachine::new(16, false)
+            .with_congestion_control(CongestionAlgorithm::Reno.build());
+        sender.connect(9, now);
+        sender.handle_ack(Ack::new(9, AckKind::SynAck), now);
+        // Slow start takes cwnd from 1 to 8
+        let mut number = 10;
+        for round in 0..4 {
+            while sender.has_room() {
+                sender.push(packet(number, PacketState::Ongoing));
+                number += 1;
+            }
+            iter::from_fn(|| sender.poll_transmit(now)).for_each(drop);
+            if round < 3 {
+                sender.handle_ack(Ack::sack(number - 1, blocks(&[])), now);
+            }
+        }
+        assert_eq!(number, 25);
+        let resend = |sender: &mut SenderMachine, ack| {
+            sender.handle_ack(ack, now);
+            iter::from_fn(|| sender.poll_transmit(now))
+                .map(|p| p.number)
+                .collect::<Vec<_>>()
+        };
+        // Growing to 10 with 17 and 19, the hole at 18 halves the window
+        assert_eq!(
+            resend(&mut sender, Ack::sack(17, blocks(&[(19, 20)]))),
+            [18]
+        );
+        assert_eq!(sender.congestion.effective(), 5);
+        // 20 is a hole of the same window, it is not halved again
+        let ack = Ack::sack(17, blocks(&[(19, 20), (21, 25)]));
+        assert_eq!(resend(&mut sender, ack), [20]);
+        assert_eq!(sender.congestion.effective(), 5);
+        // The retransmission of 18 is lost, that of 20 arrives: a new loss
+        assert_eq!(
+            resend(&mut sender, Ack::sack(17, blocks(&[(19, 25)]))),
+            [18]
+        );
+        assert_eq!(sender.congestion.effective(), 2);
+    }
+
+    #[test]
+    fn test_sack_small() {
+        let message_send = String::from("test");
+        let message_received = setup(5, &message_send).0;
+        assert_eq!(message_send, message_received);
+        let message_send = String::from("");
+        let message_received = setup(5, &message_send).0;
+        assert_eq!(message_send, message_received);
+        let message_send = String::from("test");
+        let message_received = setup(1, &message_send).0;
+        assert_eq!(message_send, message_received);
+    }
+}
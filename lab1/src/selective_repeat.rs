@@ -1,393 +1,481 @@
 use std::{
-    collections::{BTreeMap, VecDeque},
-    sync::mpsc::{self, TryRecvError},
-    thread,
-    time::{Duration, Instant},
+    collections::{BTreeMap, BTreeSet, VecDeque, btree_map::Entry},
+    time::Instant,
 };
 
-use crate::simulate_loss;
-
-const DATA_SIZE: usize = u8::MAX as usize;
-const TIMEOUT: Duration = Duration::from_millis(200);
-const TIMEOUT_TOTAL: Duration = Duration::from_secs(30);
-
-#[derive(Debug, Clone, Copy)]
-enum PacketState {
-    Begin,
-    Ongoing,
-    End,
-}
-
-type AckNumber = u32;
-
-#[derive(Debug, Clone)]
-pub struct Packet {
-    number: AckNumber,
-    data: [u8; DATA_SIZE],
-    size: u8,
-    state: PacketState,
-}
+use crate::{
+    arq::{Protocol, ReaderStats, SenderStats},
+    congestion::{CongestionAlgorithm, CongestionControl, CongestionWindow},
+    connection::SenderConnection,
+    driver::{ReaderDriver, SenderDriver},
+    error::ArqError,
+    machine::{ArqReceiverMachine, ArqSenderMachine, ReaderCore},
+    packet::{Ack, AckKind, AckNumber, Packet},
+    rto::RtoEstimator,
+    seq::SequenceSpace,
+    stats::TransferStats,
+};
 
 #[derive(Debug, Clone)]
 struct SenderPacket {
     packet: Packet,
     is_acked: bool,
     last_sent: Option<Instant>,
+    /// Order of the last transmission among all packets put on the wire.
+    sent_order: usize,
+    is_retransmitted: bool,
+    /// Waiting for its first transmission or a retransmission.
+    is_due: bool,
 }
 
-pub struct Sender {
-    tx: mpsc::Sender<Packet>,
-    rx: mpsc::Receiver<AckNumber>,
+/// Selective Repeat sender over `mpsc` channels.
+pub type Sender = SenderDriver<SenderMachine>;
+/// Selective Repeat reader over `mpsc` channels.
+pub type Reader = ReaderDriver<ReaderMachine>;
+
+/// Selective Repeat sender without I/O. Every packet in flight has its own
+/// retransmission timer.
+pub struct SenderMachine {
     window_size: AckNumber,
-    base: AckNumber,
+    /// Absolute index of the oldest unacknowledged packet, keeps growing
+    /// across the messages of a connection.
+    base: usize,
     packets_total: usize,
     packets_send: usize,
     packets_ack: usize,
+    /// Transmissions so far, numbers `sent_order`.
+    transmissions: usize,
+    /// Transmissions before the last window reduction. Losses of packets
+    /// sent before it belong to a window that was already reduced.
+    recovery_point: usize,
+    /// Transmissions before the last timeout, the same for expired timers.
+    timeout_point: usize,
     window_packets: VecDeque<SenderPacket>,
+    connection: SenderConnection,
+    rto: RtoEstimator,
+    congestion: CongestionWindow,
+    space: SequenceSpace,
     is_debug: bool,
 }
 
-impl Sender {
-    #[must_use] 
-    pub fn new(
-        tx: mpsc::Sender<Packet>,
-        rx: mpsc::Receiver<AckNumber>,
-        window_size: AckNumber,
-        is_debug: bool,
-    ) -> Self {
+impl SenderMachine {
+    #[must_use]
+    pub fn new(window_size: AckNumber, is_debug: bool) -> Self {
         Self {
-            tx,
-            rx,
             window_size,
             base: 0,
             packets_total: 0,
             packets_send: 0,
             packets_ack: 0,
+            transmissions: 0,
+            recovery_point: 0,
+            timeout_point: 0,
             window_packets: VecDeque::with_capacity(window_size as usize),
+            connection: SenderConnection::default(),
+            rto: RtoEstimator::new(),
+            congestion: CongestionWindow::new(CongestionAlgorithm::Fixed.build(), window_size),
+            space: SequenceSpace::default(),
             is_debug,
         }
     }
 
-    fn reset(&mut self, message: &str) {
-        self.base = 0;
-        self.packets_total = message.len().div_ceil(DATA_SIZE).max(2);
+    /// Numbers packets modulo `2^bits`. Fails unless the window is at most `2^(bits-1)`.
+    pub fn with_sequence_bits(mut self, bits: u32) -> Result<Self, ArqError> {
+        let space = SequenceSpace::new(bits)?;
+        space.check_window(self.window_size, space.max_window_sr())?;
+        self.space = space;
+        Ok(self)
+    }
+
+    /// Limits the window to `min(cwnd, window_size)` using `control`.
+    #[must_use]
+    pub fn with_congestion_control(mut self, control: Box<dyn CongestionControl>) -> Self {
+        self.congestion = CongestionWindow::new(control, self.window_size);
+        self
+    }
+
+    /// Retransmission deadline of a packet in flight.
+    fn deadline(&self, sender_packet: &SenderPacket) -> Option<Instant> {
+        if sender_packet.is_acked || sender_packet.is_due {
+            return None;
+        }
+        sender_packet
+            .last_sent
+            .map(|last_sent| last_sent + self.rto.current())
+    }
+}
+
+impl ArqSenderMachine for SenderMachine {
+    fn space(&self) -> SequenceSpace {
+        self.space
+    }
+
+    fn connect(&mut self, isn: AckNumber, now: Instant) {
+        self.rto.reset(now);
+        self.congestion.reset(now);
+        self.window_packets.clear();
+        let isn = self.space.wrap(isn as usize);
+        self.base = isn as usize + 1;
+        self.connection.connect(isn);
+    }
+
+    fn is_connected(&self) -> bool {
+        self.connection.is_established()
+    }
+
+    fn start_message(&mut self) {
+        self.packets_total = 0;
         self.packets_send = 0;
         self.packets_ack = 0;
-        self.window_packets.clear();
     }
 
-    fn window_end(&self) -> AckNumber {
-        (self.base + self.window_size).min(self.packets_total as u32)
+    fn has_room(&self) -> bool {
+        self.is_connected() && self.window_packets.len() < self.congestion.effective() as usize
     }
 
-    fn prepare_packets(&mut self, message: &str) {
-        let bytes = message.as_bytes();
-        let end = self.window_end() as usize;
-        let current_in_window = self.window_packets.len();
-        let next_number = self.base as usize + current_in_window;
-        let packets = (next_number..end).map(|number| {
-            let data_start = DATA_SIZE * number;
-            let mut data = [0; DATA_SIZE];
-            let data_size = match bytes.len().checked_sub(data_start) {
-                Some(data_size) => {
-                    let data_size = DATA_SIZE.min(data_size);
-                    data[..data_size].copy_from_slice(&bytes[data_start..(data_start + data_size)]);
-                    data_size
+    fn push(&mut self, mut packet: Packet) {
+        packet.set_number(self.space.wrap(self.base + self.window_packets.len()));
+        self.window_packets.push_back(SenderPacket {
+            packet,
+            is_acked: false,
+            last_sent: None,
+            sent_order: 0,
+            is_retransmitted: false,
+            is_due: true,
+        });
+        self.packets_total += 1;
+    }
+
+    fn is_idle(&self) -> bool {
+        self.window_packets.is_empty()
+    }
+
+    fn close(&mut self) {
+        self.connection.close(self.space.wrap(self.base));
+    }
+
+    fn is_closed(&self) -> bool {
+        self.connection.is_closed()
+    }
+
+    fn oldest_unacked(&self) -> Option<AckNumber> {
+        self.connection
+            .pending()
+            .or_else(|| self.window_packets.front().map(|p| p.packet.number))
+    }
+
+    fn handle_ack(&mut self, ack: Ack, now: Instant) {
+        if self.connection.on_ack(ack, &mut self.rto, now) {
+            return;
+        }
+        let index = self.space.distance(self.space.wrap(self.base), ack.number) as usize;
+        let Some(acked) = self.window_packets.get_mut(index) else {
+            return;
+        };
+        if acked.is_acked || acked.last_sent.is_none() {
+            return;
+        }
+        if ack.kind == AckKind::Nak {
+            // Resend without waiting for the packet's timer
+            if !acked.is_due {
+                acked.is_due = true;
+                if acked.sent_order >= self.recovery_point {
+                    self.recovery_point = self.transmissions;
+                    self.congestion.on_loss(now);
+                }
+                if self.is_debug {
+                    eprintln!("Sender | Nak packet: {}", ack.number);
                 }
-                None => 0,
-            };
-            let state = if number == 0 {
-                PacketState::Begin
-            } else if number + 1 == self.packets_total {
-                PacketState::End
-            } else {
-                PacketState::Ongoing
-            };
-            SenderPacket {
-                packet: Packet {
-                    number: number as AckNumber,
-                    data,
-                    size: data_size as u8,
-                    state,
-                },
-                is_acked: false,
-                last_sent: None,
             }
-        });
-        self.window_packets.extend(packets);
-    }
-
-    fn do_send_packet(
-        tx: &mpsc::Sender<Packet>,
-        is_debug: bool,
-        sender_packet: &mut SenderPacket,
-    ) -> Result<(), String> {
-        let number = sender_packet.packet.number;
-        let size = sender_packet.packet.size;
-        let state = sender_packet.packet.state;
-        if let Err(e) = tx.send(sender_packet.packet.clone()) {
-            return Err(format!("Failed to send packet {number}: {e}"));
+            return;
+        }
+        acked.is_acked = true;
+        acked.is_due = false;
+        // Karn's algorithm: a retransmitted packet gives an ambiguous sample
+        if let (false, Some(last_sent)) = (acked.is_retransmitted, acked.last_sent) {
+            self.rto
+                .on_sample(now.saturating_duration_since(last_sent), now);
         }
-        sender_packet.last_sent = Some(Instant::now());
-        if is_debug {
+        self.rto.on_progress(now);
+        self.packets_ack += 1;
+        self.congestion.on_ack(1, now);
+        if self.is_debug {
             eprintln!(
-                "Sender | Send packet: {}, size: {}, state: {:?}",
-                number, size, state
+                "Sender | Ack packet: {}, {} out of {}",
+                ack.number, self.packets_ack, self.packets_total
             );
         }
-        Ok(())
+        // Slide window
+        while self.window_packets.front().is_some_and(|p| p.is_acked) {
+            self.window_packets.pop_front();
+            self.base += 1;
+        }
     }
 
-    pub fn send(&mut self, message: &str) -> Result<(), String> {
-        self.reset(message);
-        let time = Instant::now();
-        while self.packets_ack < self.packets_total {
-            if time.elapsed() > TIMEOUT_TOTAL {
-                return Err("Message send timeout".to_string());
+    fn handle_timeout(&mut self, now: Instant) {
+        self.connection.on_timeout(&mut self.rto, now);
+        let mut is_timeout = false;
+        for index in 0..self.window_packets.len() {
+            if self
+                .deadline(&self.window_packets[index])
+                .is_some_and(|deadline| now >= deadline)
+            {
+                self.window_packets[index].is_due = true;
+                is_timeout |= self.window_packets[index].sent_order >= self.timeout_point;
             }
-            self.prepare_packets(message);
-            for sender_packet in &mut self.window_packets {
-                if sender_packet.is_acked {
-                    continue;
-                }
-                let should_send = match sender_packet.last_sent {
-                    None => true,
-                    Some(last_sent) => last_sent.elapsed() > TIMEOUT,
-                };
-                if should_send {
-                    Self::do_send_packet(&self.tx, self.is_debug, sender_packet)?;
-                    self.packets_send += 1;
-                }
+        }
+        // One backoff and one reduction per window, like the single timer
+        // of Go-Back-N
+        if is_timeout {
+            self.recovery_point = self.transmissions;
+            self.timeout_point = self.transmissions;
+            self.rto.on_timeout(now);
+            self.congestion.on_timeout(now);
+            if self.is_debug {
+                eprintln!(
+                    "Sender | Timeout at base {}, rto: {}ms",
+                    self.base,
+                    self.rto.current().as_millis()
+                );
             }
-            self.ack()?;
         }
-        Ok(())
     }
 
-    fn ack(&mut self) -> Result<(), String> {
-        let end = self.window_end();
-        let time = Instant::now();
-        while !self.window_packets.is_empty() && (time.elapsed() < Duration::from_millis(10) || self.window_packets[0].is_acked) {
-            match self.rx.try_recv() {
-                Ok(number) => {
-                    if !(number >= self.base && number < end) {
-                        continue;
-                    }
-                    let index = (number - self.base) as usize;
-                    if index < self.window_packets.len() && !self.window_packets[index].is_acked {
-                        self.window_packets[index].is_acked = true;
-                        self.packets_ack += 1;
-                        if self.is_debug {
-                            eprintln!(
-                                "Sender | Ack packet: {}, {} out of {}",
-                                number, self.packets_ack, self.packets_total
-                            );
-                        }
-                    }
-                }
-                Err(TryRecvError::Empty) => {
-                    thread::sleep(Duration::from_millis(1));
-                    if !self.window_packets.is_empty() && self.window_packets[0].is_acked {
-                        // Slide window
-                        self.window_packets.pop_front();
-                        self.base += 1;
-                    } else {
-                        break;
-                    }
-                }
-                Err(e @ TryRecvError::Disconnected) => {
-                    return Err(format!("Failed to receive ACK: {e}"));
-                }
+    fn poll_timeout(&self) -> Option<Instant> {
+        let control = self.connection.poll_timeout(&self.rto);
+        self.window_packets
+            .iter()
+            .filter_map(|sender_packet| self.deadline(sender_packet))
+            .chain(control)
+            .min()
+    }
+
+    fn poll_transmit(&mut self, now: Instant) -> Option<Packet> {
+        if let Some(packet) = self.connection.poll_transmit(now) {
+            if self.is_debug {
+                eprintln!("Sender | Send {:?} {}", packet.state, packet.number);
             }
+            return Some(packet);
         }
-        Ok(())
+        let sender_packet = self.window_packets.iter_mut().find(|p| p.is_due)?;
+        sender_packet.is_due = false;
+        sender_packet.is_retransmitted |= sender_packet.last_sent.is_some();
+        sender_packet.last_sent = Some(now);
+        sender_packet.sent_order = self.transmissions;
+        self.transmissions += 1;
+        self.packets_send += 1;
+        let packet = sender_packet.packet.clone();
+        if self.is_debug {
+            eprintln!(
+                "Sender | Send packet: {}, size: {}, state: {:?}",
+                packet.number, packet.size, packet.state
+            );
+        }
+        Some(packet)
     }
+}
 
-    #[must_use] 
-    pub fn efficiency_coefficient(&self) -> f64 {
+impl SenderStats for SenderMachine {
+    fn efficiency_coefficient(&self) -> f64 {
         self.packets_total as f64 / self.packets_send as f64
     }
+
+    fn retransmissions(&self) -> usize {
+        self.packets_send.saturating_sub(self.packets_total)
+    }
+
+    fn rto(&self) -> &RtoEstimator {
+        &self.rto
+    }
+
+    fn congestion(&self) -> Option<&CongestionWindow> {
+        Some(&self.congestion)
+    }
 }
 
-pub struct Reader {
-    tx: mpsc::Sender<AckNumber>,
-    rx: mpsc::Receiver<Packet>,
-    expected_number: AckNumber,
+/// Selective Repeat reader without I/O. Packets within the window are
+/// buffered until the gaps before them are filled.
+pub struct ReaderMachine {
+    core: ReaderCore,
     window_size: AckNumber,
-    packets_read: usize,
-    buffer: BTreeMap<AckNumber, Packet>,
-    is_debug: bool,
+    /// Out-of-order packets by absolute index.
+    buffer: BTreeMap<usize, Packet>,
+    is_nak_enabled: bool,
+    /// Absolute indices of the missing packets asked for with a NAK.
+    naks_sent: BTreeSet<usize>,
 }
 
-impl Reader {
-    #[must_use] 
-    pub fn new(
-        tx: mpsc::Sender<AckNumber>,
-        rx: mpsc::Receiver<Packet>,
-        window_size: AckNumber,
-        is_debug: bool,
-    ) -> Self {
+impl ReaderMachine {
+    #[must_use]
+    pub fn new(window_size: AckNumber, is_debug: bool) -> Self {
         Self {
-            tx,
-            rx,
-            expected_number: 0,
+            core: ReaderCore::new(SequenceSpace::default(), is_debug),
             window_size,
-            packets_read: 0,
             buffer: BTreeMap::new(),
-            is_debug,
+            is_nak_enabled: false,
+            naks_sent: BTreeSet::new(),
         }
     }
 
-    fn reset(&mut self) {
-        self.expected_number = 0;
-        self.packets_read = 0;
-        self.buffer.clear();
+    /// NAKs every gap before a buffered out-of-order packet, once per
+    /// missing packet.
+    #[must_use]
+    pub fn with_naks(mut self, is_nak_enabled: bool) -> Self {
+        self.is_nak_enabled = is_nak_enabled;
+        self
     }
 
-    fn window_end(&self) -> AckNumber {
-        self.expected_number + self.window_size
+    fn send_naks(&mut self, up_to: usize) {
+        for index in self.core.expected..up_to {
+            if self.buffer.contains_key(&index) || !self.naks_sent.insert(index) {
+                continue;
+            }
+            let missing = self.core.space.wrap(index);
+            self.core.send_ack(Ack::nak(missing));
+            if self.core.is_debug {
+                eprintln!("Reader | Nak packet {missing}");
+            }
+        }
     }
 
-    pub fn read(&mut self) -> Result<String, String> {
-        self.reset();
-        let mut data = Vec::<u8>::new();
-        let mut is_finished_timeout: Option<Instant> = None;
-        let time = Instant::now();
-        loop {
-            if time.elapsed() > TIMEOUT_TOTAL {
-                if is_finished_timeout.is_none() {
-                    return Err("Message read timeout".to_string());
-                }
-                break;
+    /// Must match the sender's sequence space. Fails unless the window is
+    /// at most `2^(bits-1)`.
+    pub fn with_sequence_bits(mut self, bits: u32) -> Result<Self, ArqError> {
+        let space = SequenceSpace::new(bits)?;
+        space.check_window(self.window_size, space.max_window_sr())?;
+        self.core.space = space;
+        Ok(self)
+    }
+}
+
+impl ReaderStats for ReaderMachine {
+    fn packets_read(&self) -> usize {
+        self.core.packets_read
+    }
+
+    fn duplicates(&self) -> usize {
+        self.core.duplicates
+    }
+}
+
+impl ArqReceiverMachine for ReaderMachine {
+    fn handle_packet(&mut self, packet: Packet, now: Instant) -> Result<(), ArqError> {
+        let connection = self.core.connection;
+        let Some(packet) = self.core.receive(packet, now)? else {
+            if self.core.connection != connection && self.core.connection.is_established() {
+                self.buffer.clear();
+                self.naks_sent.clear();
             }
-            match self.rx.try_recv() {
-                Ok(packet) => {
-                    self.packets_read += 1;
-                    let number = packet.number;
-                    
-                    if number < self.expected_number {
-                        self.send_ack(number)?;
-                        if self.is_debug {
-                            eprintln!(
-                                "Reader | ReAck packet {}, state: {:?}, at: {}ms",
-                                number,
-                                packet.state,
-                                time.elapsed().as_millis(),
-                            );
-                        }
-                        continue;
-                    }
-
-                    if number >= self.window_end() {
-                        if self.is_debug {
-                            eprintln!("Reader | Packet {} out of window", number);
-                        }
-                        continue;
-                    }
-
-                    // Selective Repeat: Send ACK even if it's out of order
-                    self.send_ack(number)?;
-
-                    if let std::collections::btree_map::Entry::Vacant(e) = self.buffer.entry(number) {
-                        if number == 0 && !matches!(packet.state, PacketState::Begin) {
-                            return Err("First packet does not correspond to the start of the message".to_string());
-                        } else if number != 0 && matches!(packet.state, PacketState::Begin) {
-                            return Err("Non first packet corresponds to the start of the message".to_string());
-                        }
-                        e.insert(packet);
-                    }
-
-                    // Process buffer
-                    while let Some(p) = self.buffer.remove(&self.expected_number) {
-                        data.extend(&p.data[..p.size as usize]);
-                        if self.is_debug {
-                            eprintln!(
-                                "Reader | Deliver packet {}, state: {:?}, at {}ms",
-                                p.number,
-                                p.state,
-                                time.elapsed().as_millis()
-                            );
-                        }
-                        if matches!(p.state, PacketState::End) {
-                            is_finished_timeout = Some(Instant::now());
-                        }
-                        self.expected_number += 1;
-                    }
-                }
-                Err(TryRecvError::Empty) => {
-                    if is_finished_timeout.is_some_and(|t| t.elapsed() > 2 * TIMEOUT) {
-                        break;
-                    }
-                    thread::sleep(Duration::from_millis(10));
-                }
-                Err(e @ TryRecvError::Disconnected) => {
-                    return Err(format!("Failed to receive packet: {e}"));
-                }
+            return Ok(());
+        };
+        let space = self.core.space;
+        let wire_number = packet.number;
+        let offset = space.distance(space.wrap(self.core.expected), wire_number);
+        if offset >= self.window_size
+            && u64::from(offset) >= space.modulus() - u64::from(self.window_size)
+        {
+            // Behind the window: the sender missed our ACK
+            self.core.duplicates += 1;
+            self.core.send_ack(Ack::data(wire_number));
+            if self.core.is_debug {
+                eprintln!("Reader | ReAck packet {wire_number}");
             }
+            return Ok(());
         }
-        if self.is_debug {
-            eprintln!(
-                "Reader | Finished receiving the message at {}ms",
-                time.elapsed().as_millis()
-            );
+        if offset >= self.window_size {
+            if self.core.is_debug {
+                eprintln!("Reader | Packet {wire_number} out of window");
+            }
+            return Ok(());
         }
-        String::from_utf8(data).map_err(|e| format!("Failed to encode the message: {e}"))
+        self.core.ensure_reading(&packet)?;
+        // Selective Repeat: Send ACK even if it's out of order
+        self.core.send_ack(Ack::data(wire_number));
+        let index = self.core.expected + offset as usize;
+        match self.buffer.entry(index) {
+            Entry::Occupied(_) => self.core.duplicates += 1,
+            Entry::Vacant(entry) => {
+                entry.insert(packet);
+            }
+        }
+        if self.is_nak_enabled {
+            self.send_naks(index);
+        }
+        while let Some(packet) = self.buffer.remove(&self.core.expected) {
+            if self.core.is_debug {
+                eprintln!(
+                    "Reader | Deliver packet {}, state: {:?}",
+                    packet.number, packet.state
+                );
+            }
+            self.core.deliver(packet)?;
+        }
+        self.naks_sent = self.naks_sent.split_off(&self.core.expected);
+        Ok(())
+    }
+
+    fn handle_timeout(&mut self, now: Instant) {
+        self.core.handle_timeout(now);
+    }
+
+    fn poll_timeout(&self) -> Option<Instant> {
+        self.core.poll_timeout()
+    }
+
+    fn poll_transmit(&mut self) -> Option<Ack> {
+        self.core.poll_transmit()
+    }
+
+    fn poll_deliver(&mut self) -> Option<Packet> {
+        self.core.poll_deliver()
+    }
+
+    fn close(&mut self) {
+        self.core.close();
+    }
+
+    fn is_established(&self) -> bool {
+        self.core.connection.is_established()
     }
 
-    fn send_ack(&mut self, ack: AckNumber) -> Result<(), String> {
-        self.tx
-            .send(ack)
-            .map_err(|e| format!("Failed to send ack {ack}: {e}"))
+    fn is_closed(&self) -> bool {
+        self.core.connection.is_closed()
+    }
+
+    fn expected(&self) -> AckNumber {
+        self.core.expected()
+    }
+
+    fn is_time_wait_over(&self, now: Instant) -> bool {
+        self.core.is_time_wait_over(now)
+    }
+
+    fn is_cumulative(&self) -> bool {
+        false
     }
 }
 
-#[must_use] 
-pub fn setup(window_size: AckNumber, message: &str) -> (String, f64) {
-    let (tx_packet, rx_packet) = mpsc::channel();
-    let (tx_ack, rx_ack) = mpsc::channel();
-    let mut sender = Sender::new(tx_packet, rx_ack, window_size, true);
-    let mut reader = Reader::new(tx_ack, rx_packet, window_size, true);
-    let message_read = thread::scope(|s| {
-        s.spawn(|| {
-            if let Err(e) = sender.send(message) {
-                eprintln!("Sender | {e}");
-            }
-        });
-        reader.read()
-    });
-    (message_read.unwrap(), sender.efficiency_coefficient())
+#[must_use]
+pub fn setup(window_size: AckNumber, message: &str) -> (String, TransferStats) {
+    Protocol::SelectiveRepeat.setup(window_size, message)
 }
 
-#[must_use] 
-pub fn silent_setup_loss(window_size: AckNumber, message: &str, loss: f64) -> (String, f64) {
-    let (tx_packet, rx_packet) = mpsc::channel();
-    let (tx_ack, rx_ack) = mpsc::channel();
-    let (rx_packet, rx_ack, loss_handle) = simulate_loss(rx_packet, rx_ack, loss);
-    let result = {
-        let mut sender = Sender::new(tx_packet, rx_ack, window_size, false);
-        let mut reader = Reader::new(tx_ack, rx_packet, window_size, false);
-        let message_read = thread::scope(|s| {
-            s.spawn(|| {
-                if let Err(e) = sender.send(message) {
-                    eprintln!("Sender | {e}");
-                }
-            });
-            reader.read().unwrap_or_else(|e| {
-                eprintln!("Reader warning: {e}");
-                String::new()
-            })
-        });
-        (message_read, sender.efficiency_coefficient())
-    };
-    loss_handle.join().unwrap();
-    result
+#[must_use]
+pub fn silent_setup_loss(
+    window_size: AckNumber,
+    message: &str,
+    loss: f64,
+    seed: Option<u64>,
+) -> (String, TransferStats) {
+    Protocol::SelectiveRepeat.silent_setup_loss(window_size, message, loss, seed)
 }
 
 #[cfg(test)]
 mod tests {
-    use std::{fs::File, io::Read};
+    use std::{fs::File, io::Read, iter, sync::mpsc, thread, time::Duration};
 
     use super::*;
+    use crate::packet::{AckKind, DATA_SIZE, PacketState};
 
     fn get_file_string() -> String {
         let mut s = String::new();
@@ -412,16 +500,152 @@ mod tests {
     #[test]
     fn test_selective_repeat_file_loss() {
         let message_send = get_file_string();
-        let message_received = setup_loss(3, &message_send, 0.0).0;
+        let message_received = silent_setup_loss(3, &message_send, 0.0, Some(0)).0;
         assert_eq!(message_send, message_received);
-        let message_received = setup_loss(3, &message_send, 0.25).0;
+        let message_received = silent_setup_loss(3, &message_send, 0.25, Some(0)).0;
         assert_eq!(message_send, message_received);
-        let message_received = setup_loss(3, &message_send, 0.5).0;
+        let message_received = silent_setup_loss(3, &message_send, 0.5, Some(0)).0;
         assert_eq!(message_send, message_received);
-        let message_received = setup_loss(3, &message_send, 0.75).0;
+        let message_received = silent_setup_loss(3, &message_send, 0.75, Some(0)).0;
         assert_eq!(message_send, message_received);
     }
 
+    /// Runs a transfer in a 2-bit sequence space where the first ACKs for
+    /// packets 1 and 2 are lost, forcing their retransmission after the
+    /// receiver window has already moved past them.
+    fn setup_lost_acks(window_size: AckNumber, message: &str) -> String {
+        let space = SequenceSpace::new(2).unwrap();
+        let (tx_packet, rx_packet) = mpsc::channel();
+        let (tx_ack, rx_ack_lossy) = mpsc::channel::<Ack>();
+        let (tx_ack_filtered, rx_ack) = mpsc::channel();
+        let filter = thread::spawn(move || {
+            let mut is_dropped = [false; 4];
+            while let Ok(ack) = rx_ack_lossy.recv() {
+                let number = ack.number as usize;
+                if ack.kind == AckKind::Data && (number == 1 || number == 2) && !is_dropped[number]
+                {
+                    is_dropped[number] = true;
+                    continue;
+                }
+                if tx_ack_filtered.send(ack).is_err() {
+                    break;
+                }
+            }
+        });
+        // SYN takes number 3, so data starts at 0
+        let mut sender = Sender::new((tx_packet, rx_ack), SenderMachine::new(window_size, false))
+            .with_initial_sequence(3);
+        let mut reader = Reader::new((tx_ack, rx_packet), ReaderMachine::new(window_size, false));
+        // Bypass `with_sequence_bits` so that a window too large for the
+        // space can be configured
+        sender.machine.space = space;
+        reader.machine.core.space = space;
+        let message_read = crate::arq::transfer(&mut sender, &mut reader, message);
+        drop(reader);
+        filter.join().unwrap();
+        message_read.unwrap_or_default()
+    }
+
+    #[test]
+    fn test_selective_repeat_window_too_large() {
+        let message_send: String = (b'a'..=b'h')
+            .map(|c| (c as char).to_string().repeat(DATA_SIZE))
+            .collect();
+        assert!(SenderMachine::new(3, false).with_sequence_bits(2).is_err());
+        assert_eq!(setup_lost_acks(2, &message_send), message_send);
+        assert_ne!(setup_lost_acks(3, &message_send), message_send);
+    }
+
+    #[test]
+    fn test_selective_repeat_naks() {
+        let now = Instant::now();
+        let packet = |number, state| Packet::new(number, [0; DATA_SIZE], 1, state);
+        let mut reader = ReaderMachine::new(8, false).with_naks(true);
+        reader
+            .handle_packet(Packet::control(PacketState::Syn, 9), now)
+            .unwrap();
+        for number in [12, 10, 14, 12] {
+            let state = if number == 10 {
+                PacketState::Begin
+            } else {
+                PacketState::Ongoing
+            };
+            reader.handle_packet(packet(number, state), now).unwrap();
+        }
+        let acks: Vec<Ack> = iter::from_fn(|| reader.poll_transmit()).skip(1).collect();
+        let expected = [
+            Ack::data(12),
+            Ack::nak(10),
+            Ack::nak(11),
+            Ack::data(10),
+            Ack::data(14),
+            Ack::nak(13),
+            Ack::data(12),
+        ];
+        assert_eq!(acks, expected);
+
+        let mut sender = SenderMachine::new(4, false);
+        sender.connect(9, now);
+        sender.handle_ack(Ack::new(9, AckKind::SynAck), now);
+        for number in 10..14 {
+            sender.push(packet(number, PacketState::Ongoing));
+        }
+        assert_eq!(iter::from_fn(|| sender.poll_transmit(now)).count(), 4);
+        // Only the NAKed packet goes again, before its timer expires
+        sender.handle_ack(Ack::nak(11), now);
+        sender.handle_ack(Ack::nak(11), now);
+        let resent: Vec<AckNumber> = iter::from_fn(|| sender.poll_transmit(now))
+            .map(|p| p.number)
+            .collect();
+        assert_eq!(resent, [11]);
+    }
+
+    #[test]
+    fn test_selective_repeat_one_reduction_per_window() {
+        let now = Instant::now();
+        let packet = |number| Packet::new(number, [0; DATA_SIZE], 1, PacketState::Ongoing);
+        let mut sender = SenderMachine::new(16, false)
+            .with_congestion_control(CongestionAlgorithm::Reno.build());
+        sender.connect(9, now);
+        sender.handle_ack(Ack::new(9, AckKind::SynAck), now);
+        // Slow start takes cwnd from 1 to 8
+        let mut number = 10;
+        for _ in 0..3 {
+            let start = number;
+            while sender.has_room() {
+                sender.push(packet(number));
+                number += 1;
+            }
+            iter::from_fn(|| sender.poll_transmit(now)).for_each(drop);
+            (start..number).for_each(|acked| sender.handle_ack(Ack::data(acked), now));
+        }
+        assert_eq!(sender.congestion.effective(), 8);
+        while sender.has_room() {
+            sender.push(packet(number));
+            number += 1;
+        }
+        iter::from_fn(|| sender.poll_transmit(now)).for_each(drop);
+        // Two losses of the same window halve it once
+        sender.handle_ack(Ack::nak(18), now);
+        sender.handle_ack(Ack::nak(20), now);
+        assert_eq!(sender.congestion.effective(), 4);
+        let resent_at = now + Duration::from_millis(10);
+        let resent: Vec<AckNumber> = iter::from_fn(|| sender.poll_transmit(resent_at))
+            .map(|p| p.number)
+            .collect();
+        assert_eq!(resent, [18, 20]);
+        // A retransmission was sent after the reduction, its loss is news
+        sender.handle_ack(Ack::nak(18), resent_at);
+        assert_eq!(sender.congestion.effective(), 2);
+        // The timers of the window expire one by one, it backs off once
+        let rto = sender.rto.current();
+        sender.handle_timeout(now + rto);
+        assert_eq!(sender.congestion.effective(), 1);
+        assert_eq!(sender.rto.current(), rto * 2);
+        sender.handle_timeout(resent_at + rto * 2);
+        assert_eq!(sender.rto.current(), rto * 2);
+    }
+
     #[test]
     fn test_selective_repeat_small() {
         let message_send = String::from("test");
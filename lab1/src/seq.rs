@@ -0,0 +1,118 @@
+use crate::{error::ArqError, packet::AckNumber};
+
+/// Space of `k`-bit sequence numbers. Numbers on the wire wrap modulo `2^k`
+/// and are compared relative to a window base.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SequenceSpace {
+    bits: u32,
+}
+
+impl Default for SequenceSpace {
+    fn default() -> Self {
+        Self {
+            bits: AckNumber::BITS,
+        }
+    }
+}
+
+impl SequenceSpace {
+    /// One bit is all Stop-and-Wait needs.
+    pub const ALTERNATING_BIT: Self = Self { bits: 1 };
+
+    pub fn new(bits: u32) -> Result<Self, ArqError> {
+        if bits == 0 || bits > AckNumber::BITS {
+            return Err(ArqError::SequenceBits { bits });
+        }
+        Ok(Self { bits })
+    }
+
+    #[must_use]
+    pub fn bits(self) -> u32 {
+        self.bits
+    }
+
+    #[must_use]
+    pub fn modulus(self) -> u64 {
+        1 << self.bits
+    }
+
+    /// Wire representation of the absolute packet index `number`.
+    #[must_use]
+    pub fn wrap(self, number: usize) -> AckNumber {
+        (number as u64 % self.modulus()) as AckNumber
+    }
+
+    /// How far `to` is ahead of `from`, going forward around the space.
+    #[must_use]
+    pub fn distance(self, from: AckNumber, to: AckNumber) -> AckNumber {
+        ((u64::from(to) + self.modulus() - u64::from(from)) % self.modulus()) as AckNumber
+    }
+
+    /// Largest Go-Back-N window, `2^k - 1`.
+    #[must_use]
+    pub fn max_window_gbn(self) -> u64 {
+        self.modulus() - 1
+    }
+
+    /// Largest Selective Repeat window, `2^(k-1)`.
+    #[must_use]
+    pub fn max_window_sr(self) -> u64 {
+        self.modulus() / 2
+    }
+
+    pub(crate) fn check_window(
+        self,
+        window_size: AckNumber,
+        max_window: u64,
+    ) -> Result<(), ArqError> {
+        if window_size == 0 {
+            return Err(ArqError::ZeroWindow);
+        }
+        if u64::from(window_size) > max_window {
+            return Err(ArqError::WindowTooLarge {
+                window_size,
+                bits: self.bits,
+                max_window,
+            });
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wrap_and_distance() {
+        let space = SequenceSpace::new(3).unwrap();
+        assert_eq!(space.wrap(9), 1);
+        assert_eq!(space.distance(6, 1), 3);
+        assert_eq!(space.distance(1, 6), 5);
+        assert_eq!(space.distance(4, 4), 0);
+        let space = SequenceSpace::default();
+        assert_eq!(space.wrap(u32::MAX as usize + 2), 1);
+        assert_eq!(space.distance(u32::MAX, 1), 2);
+    }
+
+    #[test]
+    fn test_max_window() {
+        let space = SequenceSpace::new(3).unwrap();
+        assert_eq!(space.max_window_gbn(), 7);
+        assert_eq!(space.max_window_sr(), 4);
+        assert!(space.check_window(4, space.max_window_sr()).is_ok());
+        assert!(matches!(
+            space.check_window(5, space.max_window_sr()),
+            Err(ArqError::WindowTooLarge {
+                window_size: 5,
+                bits: 3,
+                max_window: 4
+            })
+        ));
+        assert!(matches!(
+            SequenceSpace::new(0),
+            Err(ArqError::SequenceBits { bits: 0 })
+        ));
+        assert!(SequenceSpace::new(33).is_err());
+    }
+}
@@ -0,0 +1,389 @@
+use rand::Rng;
+use std::{
+    cmp::Reverse,
+    collections::BinaryHeap,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    arq::TIMEOUT_TOTAL,
+    error::{ArqError, Operation},
+    impairment::{Impairments, Link, Scheduled, simulation_rng},
+    machine::{ArqReceiverMachine, ArqSenderMachine},
+    packet::{Ack, AckNumber, Packet, PacketState, Packetizer},
+    transport::{Meter, wire_bytes},
+};
+
+/// Something arriving at an endpoint.
+enum Arrival {
+    /// A packet at the reader.
+    Packet(Box<Packet>),
+    /// An ACK at the sender.
+    Ack(Ack),
+}
+
+/// Values in flight between the endpoints, ordered by their arrival time.
+struct Network<'a> {
+    link: Link,
+    in_flight: BinaryHeap<Reverse<Scheduled<Arrival>>>,
+    order: u64,
+    /// What each end put on and took off the link, as `transport::Metered`
+    /// counts it.
+    sender_link: &'a Meter,
+    reader_link: &'a Meter,
+}
+
+impl Network<'_> {
+    fn schedule(&mut self, at: Instant, value: Arrival) {
+        self.in_flight.push(Reverse(Scheduled {
+            at,
+            order: self.order,
+            value,
+        }));
+        self.order += 1;
+    }
+
+    fn send_packet(&mut self, packet: Packet, now: Instant) {
+        self.sender_link.on_sent(wire_bytes(&packet));
+        for (at, packet) in self.link.transmit_packet(packet, now) {
+            self.schedule(at, Arrival::Packet(Box::new(packet)));
+        }
+    }
+
+    fn send_ack(&mut self, ack: Ack, now: Instant) {
+        self.reader_link.on_sent(wire_bytes(&ack));
+        for at in self.link.transmit_ack(ack, now) {
+            self.schedule(at, Arrival::Ack(ack));
+        }
+    }
+
+    fn next_at(&self) -> Option<Instant> {
+        self.in_flight.peek().map(|Reverse(next)| next.at)
+    }
+
+    fn pop(&mut self) -> Option<Arrival> {
+        let arrival = self.in_flight.pop().map(|Reverse(next)| next.value);
+        match &arrival {
+            Some(Arrival::Packet(packet)) => self.reader_link.on_received(wire_bytes(&**packet)),
+            Some(Arrival::Ack(ack)) => self.sender_link.on_received(wire_bytes(ack)),
+            None => {}
+        }
+        arrival
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Phase {
+    Connecting,
+    Sending,
+    Closing,
+}
+
+impl Phase {
+    fn timeout_error(self, sender: &dyn ArqSenderMachine) -> ArqError {
+        let operation = match self {
+            Self::Connecting => Operation::Connect,
+            Self::Sending => Operation::Send,
+            Self::Closing => Operation::Close,
+        };
+        ArqError::Timeout {
+            operation,
+            number: sender.oldest_unacked(),
+        }
+    }
+}
+
+/// Transfers `message` from `sender` to `reader` over a link with the
+/// given `impairments`. Returns what the reader delivered and the completion
+/// time, from the SYN until the last data packet is acknowledged.
+///
+/// Nothing sleeps: a virtual clock jumps straight to the next arrival or
+/// timer, so a transfer finishes as fast as its events can be handled and
+/// the same seed always replays it exactly. Timeouts still follow the
+/// virtual clock: connecting or closing fails after `TIMEOUT_TOTAL`, sending
+/// once no ACK advanced the window for that long.
+pub fn transfer(
+    sender: &mut dyn ArqSenderMachine,
+    reader: &mut dyn ArqReceiverMachine,
+    message: &[u8],
+    impairments: &Impairments,
+) -> Result<(Vec<u8>, Duration), ArqError> {
+    let (sender_link, reader_link) = (Meter::default(), Meter::default());
+    metered_transfer(
+        sender,
+        reader,
+        message,
+        impairments,
+        &sender_link,
+        &reader_link,
+    )
+}
+
+/// Same as `transfer`, but counts what each end sends and receives into
+/// `sender_link` and `reader_link`.
+pub(crate) fn metered_transfer(
+    sender: &mut dyn ArqSenderMachine,
+    reader: &mut dyn ArqReceiverMachine,
+    message: &[u8],
+    impairments: &Impairments,
+    sender_link: &Meter,
+    reader_link: &Meter,
+) -> Result<(Vec<u8>, Duration), ArqError> {
+    let start = Instant::now();
+    let mut now = start;
+    let mut rnd = simulation_rng(impairments.seed);
+    let mut network = Network {
+        link: impairments.start(&mut rnd, now),
+        in_flight: BinaryHeap::new(),
+        order: 0,
+        sender_link,
+        reader_link,
+    };
+    let isn = rnd.random_range(0..sender.space().modulus()) as AckNumber;
+    sender.connect(isn, now);
+    let mut packetizer = Packetizer::new(message);
+    let mut phase = Phase::Connecting;
+    let mut deadline = now + TIMEOUT_TOTAL;
+    let mut data = Vec::new();
+    let mut completion_time = Duration::ZERO;
+    let mut oldest = None;
+    loop {
+        if phase == Phase::Connecting && sender.is_connected() {
+            sender.start_message();
+            phase = Phase::Sending;
+            deadline = now + TIMEOUT_TOTAL;
+        }
+        if phase == Phase::Sending {
+            while !packetizer.is_finished() && sender.has_room() {
+                let packet = packetizer
+                    .next_packet()?
+                    .expect("the packetizer is not finished");
+                sender.push(packet);
+            }
+            if sender.oldest_unacked() != oldest {
+                // Only a transfer that stops advancing times out
+                oldest = sender.oldest_unacked();
+                deadline = now + TIMEOUT_TOTAL;
+            }
+            if packetizer.is_finished() && sender.is_idle() {
+                sender.close();
+                completion_time = now - start;
+                phase = Phase::Closing;
+                deadline = now + TIMEOUT_TOTAL;
+            }
+        }
+        if sender.is_closed() {
+            return Ok((data, completion_time));
+        }
+        while let Some(packet) = sender.poll_transmit(now) {
+            network.send_packet(packet, now);
+        }
+        while let Some(ack) = reader.poll_transmit() {
+            network.send_ack(ack, now);
+        }
+        while let Some(packet) = reader.poll_deliver() {
+            data.extend_from_slice(packet.payload());
+            if matches!(packet.state, PacketState::End) {
+                reader.close();
+            }
+        }
+
+        let timer = [sender.poll_timeout(), reader.poll_timeout()]
+            .into_iter()
+            .flatten()
+            .min();
+        let arrival = network.next_at();
+        // With nothing left to happen the transfer would never complete
+        let Some(next) = [timer, arrival].into_iter().flatten().min() else {
+            return Err(phase.timeout_error(sender));
+        };
+        if next > deadline {
+            return Err(phase.timeout_error(sender));
+        }
+        now = next;
+        if arrival == Some(now) {
+            match network.pop().expect("peeked") {
+                Arrival::Packet(packet) => reader.handle_packet(*packet, now)?,
+                Arrival::Ack(ack) => sender.handle_ack(ack, now),
+            }
+        } else {
+            sender.handle_timeout(now);
+            reader.handle_timeout(now);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::{
+        arq::{ArqConfig, Protocol},
+        congestion::CongestionAlgorithm,
+        impairment::{ChannelModel, GilbertElliott, Jitter, LossModel},
+        packet::DATA_SIZE,
+    };
+
+    #[test]
+    fn test_simulated_protocols() {
+        let message_send = "S".repeat(5_000);
+        let fifo = ChannelModel::default()
+            .with_delay(Duration::from_millis(5))
+            .with_duplication(0.1)
+            .with_bandwidth(1_000_000);
+        let reordering = fifo
+            .with_jitter(Jitter::Normal(Duration::from_millis(2)))
+            .with_reorder(0.1);
+        for protocol in Protocol::ALL {
+            // The alternating bit cannot tell a reordered old packet from a new one
+            let channel = if protocol.is_windowed() {
+                reordering
+            } else {
+                fifo
+            };
+            let burst = LossModel::GilbertElliott(GilbertElliott::bursts(0.2, 3.0));
+            for impairments in [
+                Impairments::loss(0.0),
+                Impairments::loss(0.4),
+                Impairments::asymmetric_loss(0.1, 0.4).with_bit_error_rate(1e-4),
+                Impairments::default()
+                    .with_loss(burst)
+                    .with_channel(channel),
+            ] {
+                let config = ArqConfig::new(8).with_congestion(CongestionAlgorithm::Reno);
+                let impairments = impairments.with_seed(0);
+                let message_received = protocol
+                    .simulate_with(&config, &message_send, &impairments, |_| ())
+                    .0;
+                assert!(message_send == message_received, "{}", protocol.name());
+            }
+        }
+    }
+
+    #[test]
+    fn test_simulation_is_reproducible() {
+        let message_send = "R".repeat(10_000);
+        let impairments = Impairments::loss(0.3)
+            .with_channel(
+                ChannelModel::default().with_jitter(Jitter::Exponential(Duration::from_millis(3))),
+            )
+            .with_seed(11);
+        let config = ArqConfig::new(6).with_congestion(CongestionAlgorithm::Cubic);
+        for protocol in Protocol::ALL {
+            let run = || {
+                protocol.simulate_with(&config, &message_send, &impairments, |sender| {
+                    let rto: Vec<_> = sender
+                        .rto()
+                        .history()
+                        .iter()
+                        .map(|s| (s.at, s.rto))
+                        .collect();
+                    (sender.efficiency_coefficient().to_bits(), rto)
+                })
+            };
+            let (message_received, first) = run();
+            assert!(message_send == message_received, "{}", protocol.name());
+            assert!(!first.1.is_empty());
+            for _ in 0..3 {
+                assert_eq!(run().1, first, "{}", protocol.name());
+            }
+        }
+    }
+
+    #[test]
+    fn test_naks_shorten_transfers() {
+        let message_send = "N".repeat(20_000);
+        let channel = ChannelModel::default().with_delay(Duration::from_millis(10));
+        for protocol in [Protocol::GoBackN, Protocol::SelectiveRepeat] {
+            let completion_time = |is_nak_enabled| {
+                let config = ArqConfig::new(8).with_naks(is_nak_enabled);
+                (0..20)
+                    .map(|seed| {
+                        // NAKs only help with lost data, a lost ACK still needs the timer
+                        let impairments = Impairments::asymmetric_loss(0.1, 0.0)
+                            .with_channel(channel)
+                            .with_seed(seed);
+                        let (mut sender, mut reader) = protocol.machines(&config).unwrap();
+                        let (message_received, time) = transfer(
+                            sender.as_mut(),
+                            reader.as_mut(),
+                            message_send.as_bytes(),
+                            &impairments,
+                        )
+                        .unwrap();
+                        assert!(message_received == message_send.as_bytes());
+                        time
+                    })
+                    .sum::<Duration>()
+            };
+            let (without, with) = (completion_time(false), completion_time(true));
+            assert!(
+                with < without,
+                "{}: {with:?} >= {without:?}",
+                protocol.name()
+            );
+        }
+    }
+
+    #[test]
+    fn test_fast_retransmit_shortens_transfers() {
+        let message_send = "F".repeat(20_000);
+        let channel = ChannelModel::default().with_delay(Duration::from_millis(10));
+        let completion_time = |fast_retransmit| {
+            let config = ArqConfig::new(8).with_fast_retransmit(fast_retransmit);
+            (0..20)
+                .map(|seed| {
+                    let impairments = Impairments::loss(0.1).with_channel(channel).with_seed(seed);
+                    let (mut sender, mut reader) = Protocol::GoBackN.machines(&config).unwrap();
+                    let (message_received, time) = transfer(
+                        sender.as_mut(),
+                        reader.as_mut(),
+                        message_send.as_bytes(),
+                        &impairments,
+                    )
+                    .unwrap();
+                    assert!(message_received == message_send.as_bytes());
+                    time
+                })
+                .sum::<Duration>()
+        };
+        let (without, with) = (completion_time(None), completion_time(Some(3)));
+        assert!(with < without, "{with:?} >= {without:?}");
+    }
+
+    #[test]
+    fn test_simulated_timeout() {
+        let (mut sender, mut reader) = Protocol::GoBackN.machines(&ArqConfig::new(4)).unwrap();
+        let result = transfer(
+            sender.as_mut(),
+            reader.as_mut(),
+            b"lost",
+            &Impairments::loss(1.0),
+        );
+        let Err(ArqError::Timeout { operation, number }) = result else {
+            panic!("unexpected result {result:?}");
+        };
+        assert_eq!(operation, Operation::Connect);
+        // The SYN is still waiting for its ACK
+        assert!(number.is_some());
+        assert_eq!(number, sender.oldest_unacked());
+    }
+
+    #[test]
+    fn test_slow_transfer_does_not_time_out() {
+        // About a second per packet, far longer than `TIMEOUT_TOTAL` in total
+        let channel = ChannelModel::default().with_delay(Duration::from_millis(500));
+        let message = vec![7; 50 * DATA_SIZE];
+        let (mut sender, mut reader) = Protocol::StopAndWait.machines(&ArqConfig::new(1)).unwrap();
+        let (data, completion_time) = transfer(
+            sender.as_mut(),
+            reader.as_mut(),
+            &message,
+            &Impairments::default().with_channel(channel),
+        )
+        .unwrap();
+        assert_eq!(data, message);
+        assert!(completion_time > TIMEOUT_TOTAL);
+    }
+}
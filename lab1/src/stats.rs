@@ -0,0 +1,173 @@
+use std::time::Duration;
+
+use crate::{
+    arq::{ReaderStats, SenderStats},
+    transport::Meter,
+};
+
+/// What one transfer cost, see `Protocol::setup`.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct TransferStats {
+    /// Size of the message the reader got.
+    pub message_bytes: usize,
+    /// See `SenderStats::efficiency_coefficient`.
+    pub efficiency: f64,
+    /// Data packets put on the wire more than once.
+    pub retransmissions: usize,
+    /// Data packets the reader received although it already had them.
+    pub duplicates: usize,
+    /// ACKs and NAKs the reader put on the wire, the lost ones included.
+    pub acks_sent: usize,
+    /// ACKs and NAKs that reached the sender.
+    pub acks_received: usize,
+    /// Packets and ACKs put on the wire in both directions, headers and
+    /// the handshakes included.
+    pub wire_bytes: usize,
+    /// Time until the reader had the whole message, the close is not part
+    /// of it.
+    pub completion_time: Duration,
+    /// Round trips the sender measured, `None` without a single sample.
+    pub rtt_min: Option<Duration>,
+    pub rtt_avg: Option<Duration>,
+    pub rtt_max: Option<Duration>,
+}
+
+impl TransferStats {
+    /// Sums up a transfer from its endpoints and the meters of their
+    /// transports.
+    pub(crate) fn collect(
+        sender: &dyn SenderStats,
+        reader: &dyn ReaderStats,
+        sender_link: &Meter,
+        reader_link: &Meter,
+        message_bytes: usize,
+        completion_time: Duration,
+    ) -> Self {
+        let samples: Vec<Duration> = sender
+            .rto()
+            .history()
+            .iter()
+            .filter_map(|sample| sample.rtt)
+            .collect();
+        let rtt_avg = u32::try_from(samples.len())
+            .ok()
+            .filter(|&count| count > 0)
+            .map(|count| samples.iter().sum::<Duration>() / count);
+        Self {
+            message_bytes,
+            efficiency: sender.efficiency_coefficient(),
+            retransmissions: sender.retransmissions(),
+            duplicates: reader.duplicates(),
+            acks_sent: reader_link.sent(),
+            acks_received: sender_link.received(),
+            wire_bytes: sender_link.bytes_sent() + reader_link.bytes_sent(),
+            completion_time,
+            rtt_min: samples.iter().min().copied(),
+            rtt_avg,
+            rtt_max: samples.iter().max().copied(),
+        }
+    }
+
+    /// Message bytes delivered per second.
+    #[must_use]
+    pub fn goodput(&self) -> f64 {
+        if self.completion_time.is_zero() {
+            return 0.0;
+        }
+        self.message_bytes as f64 / self.completion_time.as_secs_f64()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use super::*;
+    use crate::{
+        arq::Protocol,
+        gobackn,
+        machine::ArqReceiverMachine,
+        packet::{AckNumber, DATA_SIZE, Packet, PacketState},
+        selective_repeat,
+    };
+
+    fn packet(number: AckNumber, state: PacketState) -> Packet {
+        Packet::new(number, [0; DATA_SIZE], 1, state)
+    }
+
+    /// Connects `reader` at 9, then feeds it `numbers` as data packets.
+    fn feed(reader: &mut dyn ArqReceiverMachine, numbers: &[AckNumber]) {
+        let now = Instant::now();
+        reader
+            .handle_packet(Packet::control(PacketState::Syn, 9), now)
+            .unwrap();
+        for &number in numbers {
+            let state = if number == 10 {
+                PacketState::Begin
+            } else {
+                PacketState::Ongoing
+            };
+            reader.handle_packet(packet(number, state), now).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_duplicates() {
+        let mut reader = gobackn::ReaderMachine::new(false);
+        // 12 past the gap is discarded, not a duplicate
+        feed(&mut reader, &[10, 10, 12, 11, 10]);
+        assert_eq!(reader.duplicates(), 2);
+        assert_eq!(reader.packets_read(), 6);
+
+        let mut reader = selective_repeat::ReaderMachine::new(4, false);
+        // 12 is buffered, then comes again before and after delivery
+        feed(&mut reader, &[10, 12, 12, 11, 12, 10]);
+        assert_eq!(reader.duplicates(), 3);
+        assert_eq!(reader.packets_read(), 7);
+    }
+
+    #[test]
+    fn test_setup_stats() {
+        let message_send = "S".repeat(3_000);
+        for protocol in Protocol::ALL {
+            let (message_received, stats) = protocol.setup(4, &message_send);
+            assert_eq!(message_received, message_send);
+            assert_eq!(stats.message_bytes, message_send.len());
+            // The sender stops listening once its FIN is acknowledged
+            assert!(stats.acks_received > 0 && stats.acks_received <= stats.acks_sent);
+            assert!(stats.wire_bytes > message_send.len());
+            assert!(stats.goodput() > 0.0);
+            let (min, avg, max) = (
+                stats.rtt_min.unwrap(),
+                stats.rtt_avg.unwrap(),
+                stats.rtt_max.unwrap(),
+            );
+            assert!(min <= avg && avg <= max, "{}", protocol.name());
+        }
+    }
+
+    #[test]
+    fn test_loss_stats() {
+        let message_send = "L".repeat(5_000);
+        let (message_received, stats) =
+            Protocol::SelectiveRepeat.silent_setup_loss(4, &message_send, 0.3, Some(7));
+        assert_eq!(message_received, message_send);
+        assert!(stats.retransmissions > 0);
+        assert!(stats.duplicates > 0);
+        assert!(stats.acks_received < stats.acks_sent);
+        assert!(stats.efficiency < 1.0);
+    }
+
+    #[test]
+    fn test_simulated_stats() {
+        let message_send = "V".repeat(5_000);
+        let (message_received, stats) =
+            Protocol::SelectiveRepeat.simulate_loss(4, &message_send, 0.3, Some(7));
+        assert_eq!(message_received, message_send);
+        assert_eq!(stats.message_bytes, message_send.len());
+        assert!(stats.retransmissions > 0);
+        assert!(stats.acks_received < stats.acks_sent);
+        assert!(stats.wire_bytes > message_send.len());
+        assert!(stats.goodput() > 0.0);
+    }
+}
@@ -0,0 +1,67 @@
+use crate::{
+    arq::Protocol,
+    driver::{ReaderDriver, SenderDriver},
+    gobackn::{ReaderMachine, SenderMachine},
+    stats::TransferStats,
+};
+
+/// Stop-and-Wait is Go-Back-N with a single packet in flight. One bit is
+/// enough to tell a new packet from a retransmission, as long as the link
+/// does not reorder packets. See `SenderMachine::stop_and_wait`.
+pub type Sender = SenderDriver<SenderMachine>;
+/// Reader of the alternating bit, see `ReaderMachine::stop_and_wait`.
+pub type Reader = ReaderDriver<ReaderMachine>;
+
+#[must_use]
+pub fn setup(message: &str) -> (String, TransferStats) {
+    Protocol::StopAndWait.setup(1, message)
+}
+
+#[must_use]
+pub fn silent_setup_loss(message: &str, loss: f64, seed: Option<u64>) -> (String, TransferStats) {
+    Protocol::StopAndWait.silent_setup_loss(1, message, loss, seed)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs::File, io::Read};
+
+    use super::*;
+
+    fn get_file_string() -> String {
+        let mut s = String::new();
+        File::open("src/lib.rs")
+            .unwrap()
+            .read_to_string(&mut s)
+            .unwrap();
+        s
+    }
+
+    #[test]
+    fn test_stop_and_wait_file() {
+        let message_send = get_file_string();
+        let message_received = setup(&message_send).0;
+        assert_eq!(message_send, message_received);
+    }
+
+    #[test]
+    fn test_stop_and_wait_file_loss() {
+        let message_send = get_file_string();
+        let message_received = silent_setup_loss(&message_send, 0.0, Some(0)).0;
+        assert_eq!(message_send, message_received);
+        let message_received = silent_setup_loss(&message_send, 0.25, Some(0)).0;
+        assert_eq!(message_send, message_received);
+        let message_received = silent_setup_loss(&message_send, 0.5, Some(0)).0;
+        assert_eq!(message_send, message_received);
+    }
+
+    #[test]
+    fn test_stop_and_wait_small() {
+        let message_send = String::from("test");
+        let message_received = setup(&message_send).0;
+        assert_eq!(message_send, message_received);
+        let message_send = String::from("");
+        let message_received = setup(&message_send).0;
+        assert_eq!(message_send, message_received);
+    }
+}
@@ -0,0 +1,239 @@
+use std::{
+    io::{self, Read, Write},
+    sync::mpsc::{self, Receiver, SyncSender},
+    thread::{self, JoinHandle},
+};
+
+use crate::{
+    arq::{BoxedReceiver, BoxedSender},
+    error::ArqError,
+};
+
+/// Chunks buffered between the caller and the protocol thread before
+/// `write` blocks.
+const PIPE_CAPACITY: usize = 16;
+
+/// Writing end of an in-memory pipe, hands every written buffer over to
+/// the other thread.
+struct PipeWriter {
+    tx: SyncSender<Vec<u8>>,
+}
+
+impl Write for PipeWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        self.tx
+            .send(buf.to_vec())
+            .map_err(|_| io::Error::from(io::ErrorKind::BrokenPipe))?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Reading end of an in-memory pipe, hits EOF once the writer is dropped.
+struct PipeReader {
+    rx: Receiver<Vec<u8>>,
+    chunk: Vec<u8>,
+    position: usize,
+}
+
+impl PipeReader {
+    fn new(rx: Receiver<Vec<u8>>) -> Self {
+        Self {
+            rx,
+            chunk: Vec::new(),
+            position: 0,
+        }
+    }
+}
+
+impl Read for PipeReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.position == self.chunk.len() {
+            match self.rx.recv() {
+                Ok(chunk) => {
+                    self.chunk = chunk;
+                    self.position = 0;
+                }
+                Err(_) => return Ok(0),
+            }
+        }
+        let size = buf.len().min(self.chunk.len() - self.position);
+        buf[..size].copy_from_slice(&self.chunk[self.position..self.position + size]);
+        self.position += size;
+        Ok(size)
+    }
+}
+
+/// Sending side of an ARQ stream. Everything written until `finish` is
+/// transferred as a single message, so the peer sees one continuous
+/// stream of bytes. Data goes out in full packets, and the whole stream is
+/// bound by the same total timeout as a single `send`.
+pub struct StreamWriter {
+    pipe: Option<PipeWriter>,
+    handle: Option<JoinHandle<(BoxedSender, Result<(), ArqError>)>>,
+}
+
+impl StreamWriter {
+    #[must_use]
+    pub fn new(mut sender: BoxedSender) -> Self {
+        let (tx, rx) = mpsc::sync_channel(PIPE_CAPACITY);
+        let handle = thread::spawn(move || {
+            let result = sender
+                .send_stream(&mut PipeReader::new(rx))
+                .and_then(|()| sender.close());
+            (sender, result)
+        });
+        Self {
+            pipe: Some(PipeWriter { tx }),
+            handle: Some(handle),
+        }
+    }
+
+    /// Ends the stream and blocks until the peer acknowledged all of it and
+    /// the connection is closed.
+    /// Returns the sender so that its statistics can be inspected.
+    pub fn finish(mut self) -> Result<BoxedSender, ArqError> {
+        let (sender, result) = self.close();
+        result.map(|()| sender)
+    }
+
+    fn close(&mut self) -> (BoxedSender, Result<(), ArqError>) {
+        self.pipe = None;
+        self.handle
+            .take()
+            .expect("stream writer is closed only once")
+            .join()
+            .expect("sender thread panicked")
+    }
+}
+
+impl Write for StreamWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let pipe = self.pipe.as_mut().expect("stream writer is open");
+        match pipe.write(buf) {
+            Err(e) if e.kind() == io::ErrorKind::BrokenPipe => {
+                // The sender gave up, report why
+                let (_, result) = self.close();
+                Err(result.err().map_or(e, io::Error::other))
+            }
+            result => result,
+        }
+    }
+
+    /// Packets go out as soon as the window allows, there is nothing to flush
+    /// until `finish`.
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for StreamWriter {
+    fn drop(&mut self) {
+        if self.handle.is_some()
+            && let (_, Err(e)) = self.close()
+        {
+            eprintln!("Sender | {e}");
+        }
+    }
+}
+
+/// Receiving side of an ARQ stream. Reads return bytes as soon as the
+/// protocol delivers them in order and EOF once the whole message is in and
+/// the sender closed the connection.
+pub struct StreamReader {
+    pipe: PipeReader,
+    handle: Option<JoinHandle<Result<u64, ArqError>>>,
+}
+
+impl StreamReader {
+    #[must_use]
+    pub fn new(mut reader: BoxedReceiver) -> Self {
+        let (tx, rx) = mpsc::sync_channel(PIPE_CAPACITY);
+        let handle = thread::spawn(move || {
+            let bytes_read = reader.read_stream(&mut PipeWriter { tx })?;
+            reader.close()?;
+            Ok(bytes_read)
+        });
+        Self {
+            pipe: PipeReader::new(rx),
+            handle: Some(handle),
+        }
+    }
+}
+
+impl Read for StreamReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let size = self.pipe.read(buf)?;
+        if size == 0
+            && !buf.is_empty()
+            && let Some(handle) = self.handle.take()
+        {
+            // The pipe is closed, make sure the message was complete
+            handle
+                .join()
+                .expect("reader thread panicked")
+                .map_err(io::Error::other)?;
+        }
+        Ok(size)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufRead, BufReader};
+
+    use super::*;
+    use crate::{arq::ArqConfig, arq::Protocol, packet::DATA_SIZE, simulate_loss, transport};
+
+    fn streams(protocol: Protocol, loss: f64) -> (StreamWriter, StreamReader) {
+        let (sender_transport, reader_transport) = transport::channel();
+        let (sender_transport, reader_transport) =
+            simulate_loss(sender_transport, reader_transport, loss, Some(0));
+        protocol
+            .stream_endpoints(sender_transport, reader_transport, &ArqConfig::new(4))
+            .unwrap()
+    }
+
+    #[test]
+    fn test_stream_copy() {
+        let message_send: Vec<u8> = (0..10 * DATA_SIZE).map(|i| (i % 251) as u8).collect();
+        for protocol in [Protocol::GoBackN, Protocol::SelectiveRepeat] {
+            let (mut writer, mut reader) = streams(protocol, 0.1);
+            let mut message_received = Vec::new();
+            let message_send = &message_send;
+            thread::scope(|s| {
+                s.spawn(move || {
+                    // Odd-sized writes do not line up with packet boundaries
+                    for chunk in message_send.chunks(100) {
+                        writer.write_all(chunk).unwrap();
+                    }
+                    writer.finish().unwrap();
+                });
+                io::copy(&mut reader, &mut message_received).unwrap();
+            });
+            assert_eq!(*message_send, message_received, "{}", protocol.name());
+        }
+    }
+
+    #[test]
+    fn test_stream_lines() {
+        let (mut writer, reader) = streams(Protocol::GoBackN, 0.0);
+        let lines: Vec<String> = thread::scope(|s| {
+            s.spawn(move || {
+                for i in 0..100 {
+                    writeln!(writer, "line {i}").unwrap();
+                }
+                writer.finish().unwrap();
+            });
+            BufReader::new(reader).lines().map(Result::unwrap).collect()
+        });
+        assert_eq!(lines.len(), 100);
+        assert_eq!(lines[42], "line 42");
+    }
+}
@@ -0,0 +1,454 @@
+use std::{
+    cmp::Reverse,
+    collections::BinaryHeap,
+    sync::{
+        Arc, Condvar, Mutex,
+        atomic::{AtomicUsize, Ordering},
+        mpsc::{self, RecvTimeoutError, SendError},
+    },
+    time::{Duration, Instant},
+};
+
+use rand::{Rng, rngs::StdRng};
+
+use crate::{
+    impairment::{Impairments, Link, Scheduled, simulation_rng},
+    packet::{Ack, Frame, Packet},
+};
+
+/// One end of a datagram link: sends values of type `O` and receives values
+/// of type `I`. A sender runs over a `Transport<Packet, Ack>`, a reader over
+/// a `Transport<Ack, Packet>`.
+///
+/// Values may be lost, duplicated or reordered on the way, the protocols
+/// recover from that. Errors only report a peer that is gone for good.
+pub trait Transport<O, I> {
+    /// Puts `value` on the link. Fails with the value once the peer can no
+    /// longer receive anything.
+    fn send(&mut self, value: O) -> Result<(), SendError<O>>;
+
+    /// Waits up to `timeout` for the next value. Fails with `Disconnected`
+    /// once nothing can arrive anymore.
+    fn recv_timeout(&mut self, timeout: Duration) -> Result<I, RecvTimeoutError>;
+}
+
+/// Transport over a pair of `mpsc` channels.
+pub type Channel<O, I> = (mpsc::Sender<O>, mpsc::Receiver<I>);
+
+/// Two connected `mpsc` transports, one for each end.
+#[must_use]
+pub fn channel<A, B>() -> (Channel<A, B>, Channel<B, A>) {
+    let (tx_a, rx_a) = mpsc::channel();
+    let (tx_b, rx_b) = mpsc::channel();
+    ((tx_a, rx_b), (tx_b, rx_a))
+}
+
+impl<O, I> Transport<O, I> for Channel<O, I> {
+    fn send(&mut self, value: O) -> Result<(), SendError<O>> {
+        self.0.send(value)
+    }
+
+    fn recv_timeout(&mut self, timeout: Duration) -> Result<I, RecvTimeoutError> {
+        self.1.recv_timeout(timeout)
+    }
+}
+
+/// Transport over a pair of crossbeam channels.
+pub type CrossbeamChannel<O, I> = (crossbeam_channel::Sender<O>, crossbeam_channel::Receiver<I>);
+
+/// Two connected crossbeam transports, one for each end.
+#[must_use]
+pub fn crossbeam_channel<A, B>() -> (CrossbeamChannel<A, B>, CrossbeamChannel<B, A>) {
+    let (tx_a, rx_a) = crossbeam_channel::unbounded();
+    let (tx_b, rx_b) = crossbeam_channel::unbounded();
+    ((tx_a, rx_b), (tx_b, rx_a))
+}
+
+impl<O, I> Transport<O, I> for CrossbeamChannel<O, I> {
+    fn send(&mut self, value: O) -> Result<(), SendError<O>> {
+        self.0.send(value).map_err(|e| SendError(e.into_inner()))
+    }
+
+    fn recv_timeout(&mut self, timeout: Duration) -> Result<I, RecvTimeoutError> {
+        self.1.recv_timeout(timeout).map_err(|e| match e {
+            crossbeam_channel::RecvTimeoutError::Timeout => RecvTimeoutError::Timeout,
+            crossbeam_channel::RecvTimeoutError::Disconnected => RecvTimeoutError::Disconnected,
+        })
+    }
+}
+
+/// Transport that drops every received value with probability `loss`,
+/// whatever `inner` carries them.
+pub struct Lossy<T> {
+    pub(crate) inner: T,
+    loss: f64,
+    rnd: StdRng,
+}
+
+impl<T> Lossy<T> {
+    /// Drops with a fresh generator, see `with_seed` for reproducible drops.
+    #[must_use]
+    pub fn new(inner: T, loss: f64) -> Self {
+        assert!((0.0..=1.0).contains(&loss));
+        Self {
+            inner,
+            loss,
+            rnd: simulation_rng(None),
+        }
+    }
+
+    /// The same `seed` always drops the same values.
+    #[must_use]
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.rnd = simulation_rng(Some(seed));
+        self
+    }
+
+    /// Draws whether the value just received is dropped.
+    pub(crate) fn is_lost(&mut self) -> bool {
+        self.rnd.random::<f64>() < self.loss
+    }
+}
+
+impl<O, I, T: Transport<O, I>> Transport<O, I> for Lossy<T> {
+    fn send(&mut self, value: O) -> Result<(), SendError<O>> {
+        self.inner.send(value)
+    }
+
+    fn recv_timeout(&mut self, timeout: Duration) -> Result<I, RecvTimeoutError> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let value = self
+                .inner
+                .recv_timeout(deadline.saturating_duration_since(Instant::now()))?;
+            if !self.is_lost() {
+                return Ok(value);
+            }
+        }
+    }
+}
+
+/// Values and bytes that went through a `Metered` transport.
+#[derive(Debug, Default)]
+pub struct Meter {
+    sent: AtomicUsize,
+    received: AtomicUsize,
+    bytes_sent: AtomicUsize,
+    bytes_received: AtomicUsize,
+}
+
+impl Meter {
+    /// Values put on the link, including the ones it loses later.
+    pub fn sent(&self) -> usize {
+        self.sent.load(Ordering::Relaxed)
+    }
+
+    pub fn received(&self) -> usize {
+        self.received.load(Ordering::Relaxed)
+    }
+
+    /// Wire size of the values sent, headers included.
+    pub fn bytes_sent(&self) -> usize {
+        self.bytes_sent.load(Ordering::Relaxed)
+    }
+
+    pub fn bytes_received(&self) -> usize {
+        self.bytes_received.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn on_sent(&self, bytes: usize) {
+        self.sent.fetch_add(1, Ordering::Relaxed);
+        self.bytes_sent.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    pub(crate) fn on_received(&self, bytes: usize) {
+        self.received.fetch_add(1, Ordering::Relaxed);
+        self.bytes_received.fetch_add(bytes, Ordering::Relaxed);
+    }
+}
+
+/// Wire size of `value` in whole bytes, as a datagram carries it.
+pub(crate) fn wire_bytes(value: &impl Frame) -> usize {
+    value.bit_len().div_ceil(8)
+}
+
+/// Transport that counts what `inner` sends and receives. The `meter`
+/// stays readable once the transport is handed to an endpoint.
+pub struct Metered<T> {
+    inner: T,
+    meter: Arc<Meter>,
+}
+
+impl<T> Metered<T> {
+    #[must_use]
+    pub fn new(inner: T) -> Self {
+        Self {
+            inner,
+            meter: Arc::default(),
+        }
+    }
+
+    #[must_use]
+    pub fn meter(&self) -> Arc<Meter> {
+        Arc::clone(&self.meter)
+    }
+}
+
+impl<O: Frame, I: Frame, T: Transport<O, I>> Transport<O, I> for Metered<T> {
+    fn send(&mut self, value: O) -> Result<(), SendError<O>> {
+        let bytes = wire_bytes(&value);
+        self.inner.send(value)?;
+        self.meter.on_sent(bytes);
+        Ok(())
+    }
+
+    fn recv_timeout(&mut self, timeout: Duration) -> Result<I, RecvTimeoutError> {
+        let value = self.inner.recv_timeout(timeout)?;
+        self.meter.on_received(wire_bytes(&value));
+        Ok(value)
+    }
+}
+
+/// Values in flight towards one end of a simulated link.
+struct Queue<T> {
+    in_flight: BinaryHeap<Reverse<Scheduled<T>>>,
+    /// Whether the end sending into the queue still exists.
+    is_open: bool,
+}
+
+impl<T> Queue<T> {
+    fn new() -> Self {
+        Self {
+            in_flight: BinaryHeap::new(),
+            is_open: true,
+        }
+    }
+}
+
+struct LinkState {
+    link: Link,
+    /// Sending order, keeps values due at the same instant in order.
+    order: u64,
+    packets: Queue<Packet>,
+    acks: Queue<Ack>,
+}
+
+impl LinkState {
+    fn schedule<T>(queue: &mut Queue<T>, order: &mut u64, arrivals: Vec<(Instant, T)>) {
+        for (at, value) in arrivals {
+            queue.in_flight.push(Reverse(Scheduled {
+                at,
+                order: *order,
+                value,
+            }));
+            *order += 1;
+        }
+    }
+}
+
+/// State shared by both ends of a simulated link.
+struct Shared {
+    state: Mutex<LinkState>,
+    /// Signalled whenever a value is scheduled or an end goes away.
+    changed: Condvar,
+}
+
+impl Shared {
+    /// Waits up to `timeout` for the first value of the `select`ed queue to arrive.
+    fn recv<T>(
+        &self,
+        timeout: Duration,
+        select: fn(&mut LinkState) -> &mut Queue<T>,
+    ) -> Result<T, RecvTimeoutError> {
+        let deadline = Instant::now() + timeout;
+        let mut state = self.state.lock().unwrap();
+        loop {
+            let now = Instant::now();
+            let queue = select(&mut state);
+            let wake = match queue.in_flight.peek() {
+                Some(Reverse(next)) if next.at <= now => {
+                    let Reverse(next) = queue.in_flight.pop().expect("peeked");
+                    return Ok(next.value);
+                }
+                Some(Reverse(next)) => next.at.min(deadline),
+                None if !queue.is_open => return Err(RecvTimeoutError::Disconnected),
+                None => deadline,
+            };
+            if now >= deadline {
+                return Err(RecvTimeoutError::Timeout);
+            }
+            state = self.changed.wait_timeout(state, wake - now).unwrap().0;
+        }
+    }
+}
+
+/// Sender end of a simulated link.
+pub struct SimulatedSender(Arc<Shared>);
+
+/// Reader end of a simulated link.
+pub struct SimulatedReader(Arc<Shared>);
+
+/// In-memory link with the given `impairments` on the wall clock. It needs
+/// no threads, the impairments are drawn as values are sent and a value is
+/// due once its arrival time has passed.
+#[must_use]
+pub fn simulated_link(impairments: &Impairments) -> (SimulatedSender, SimulatedReader) {
+    let mut rnd = simulation_rng(impairments.seed);
+    let state = LinkState {
+        link: impairments.start(&mut rnd, Instant::now()),
+        order: 0,
+        packets: Queue::new(),
+        acks: Queue::new(),
+    };
+    let shared = Arc::new(Shared {
+        state: Mutex::new(state),
+        changed: Condvar::new(),
+    });
+    (
+        SimulatedSender(Arc::clone(&shared)),
+        SimulatedReader(shared),
+    )
+}
+
+impl Transport<Packet, Ack> for SimulatedSender {
+    fn send(&mut self, packet: Packet) -> Result<(), SendError<Packet>> {
+        let mut state = self.0.state.lock().unwrap();
+        if !state.acks.is_open {
+            return Err(SendError(packet));
+        }
+        let arrivals = state.link.transmit_packet(packet, Instant::now());
+        let LinkState { order, packets, .. } = &mut *state;
+        LinkState::schedule(packets, order, arrivals);
+        self.0.changed.notify_all();
+        Ok(())
+    }
+
+    fn recv_timeout(&mut self, timeout: Duration) -> Result<Ack, RecvTimeoutError> {
+        self.0.recv(timeout, |state| &mut state.acks)
+    }
+}
+
+impl Drop for SimulatedSender {
+    fn drop(&mut self) {
+        self.0.state.lock().unwrap().packets.is_open = false;
+        self.0.changed.notify_all();
+    }
+}
+
+impl Transport<Ack, Packet> for SimulatedReader {
+    fn send(&mut self, ack: Ack) -> Result<(), SendError<Ack>> {
+        let mut state = self.0.state.lock().unwrap();
+        if !state.packets.is_open {
+            return Err(SendError(ack));
+        }
+        let arrivals = state.link.transmit_ack(ack, Instant::now());
+        let LinkState { order, acks, .. } = &mut *state;
+        LinkState::schedule(
+            acks,
+            order,
+            arrivals.into_iter().map(|at| (at, ack)).collect(),
+        );
+        self.0.changed.notify_all();
+        Ok(())
+    }
+
+    fn recv_timeout(&mut self, timeout: Duration) -> Result<Packet, RecvTimeoutError> {
+        self.0.recv(timeout, |state| &mut state.packets)
+    }
+}
+
+impl Drop for SimulatedReader {
+    fn drop(&mut self) {
+        self.0.state.lock().unwrap().acks.is_open = false;
+        self.0.changed.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        arq::{ArqConfig, Protocol, transfer},
+        impairment::ChannelModel,
+    };
+
+    /// Runs a transfer of every protocol over the transports `make` returns.
+    fn check_transfer<S, R>(make: impl Fn() -> (S, R))
+    where
+        S: Transport<Packet, Ack> + Send + 'static,
+        R: Transport<Ack, Packet> + Send + 'static,
+    {
+        let message_send = "T".repeat(2_000);
+        for protocol in Protocol::ALL {
+            let (sender_transport, reader_transport) = make();
+            let (mut sender, mut reader) = protocol
+                .endpoints(sender_transport, reader_transport, &ArqConfig::new(4))
+                .unwrap();
+            let message_received = transfer(sender.as_mut(), reader.as_mut(), &message_send);
+            assert_eq!(
+                message_received.unwrap(),
+                message_send,
+                "{}",
+                protocol.name()
+            );
+        }
+    }
+
+    #[test]
+    fn test_transports() {
+        check_transfer(channel);
+        check_transfer(crossbeam_channel);
+        check_transfer(|| {
+            let (sender, reader) = crossbeam_channel();
+            (
+                Lossy::new(sender, 0.2).with_seed(0),
+                Lossy::new(reader, 0.2).with_seed(1),
+            )
+        });
+        let impairments = Impairments::loss(0.2)
+            .with_channel(ChannelModel::default().with_delay(Duration::from_millis(1)))
+            .with_seed(0);
+        check_transfer(|| simulated_link(&impairments));
+    }
+
+    #[test]
+    fn test_metered() {
+        let (sender, reader) = channel::<Packet, Ack>();
+        let (mut sender, mut reader) = (Metered::new(sender), Metered::new(reader));
+        let (sender_link, reader_link) = (sender.meter(), reader.meter());
+        let packet = Packet::control(crate::packet::PacketState::Syn, 1);
+        let packet_bytes = packet.bit_len() / 8;
+        sender.send(packet).unwrap();
+        reader.recv_timeout(Duration::ZERO).unwrap();
+        reader.send(Ack::data(1)).unwrap();
+        reader.send(Ack::data(1)).unwrap();
+        assert_eq!(sender_link.sent(), 1);
+        assert_eq!(sender_link.bytes_sent(), packet_bytes);
+        assert_eq!(reader_link.received(), 1);
+        assert_eq!(reader_link.bytes_received(), packet_bytes);
+        assert_eq!(reader_link.sent(), 2);
+        assert_eq!(reader_link.bytes_sent(), 2 * crate::wire::ACK_SIZE);
+        assert_eq!(sender_link.received(), 0);
+    }
+
+    #[test]
+    fn test_simulated_link_disconnect() {
+        let delay = Duration::from_millis(20);
+        let impairments =
+            Impairments::default().with_channel(ChannelModel::default().with_delay(delay));
+        let (mut sender, mut reader) = simulated_link(&impairments);
+        let time = Instant::now();
+        sender
+            .send(Packet::control(crate::packet::PacketState::Syn, 1))
+            .unwrap();
+        drop(sender);
+        // Values in flight still arrive after their sender is gone
+        assert_eq!(reader.recv_timeout(delay * 10).unwrap().number, 1);
+        assert!(time.elapsed() >= delay);
+        assert_eq!(
+            reader.recv_timeout(delay).unwrap_err(),
+            RecvTimeoutError::Disconnected
+        );
+        assert!(reader.send(Ack::data(1)).is_err());
+    }
+}
@@ -0,0 +1,336 @@
+use std::{
+    cmp::Reverse,
+    collections::BinaryHeap,
+    io,
+    marker::PhantomData,
+    net::{SocketAddr, UdpSocket},
+    sync::{
+        Arc, Condvar, Mutex,
+        atomic::{AtomicBool, Ordering},
+        mpsc::{RecvTimeoutError, SendError},
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    impairment::{Impairments, Link, Scheduled, simulation_rng},
+    packet::{Ack, Packet},
+    transport::Transport,
+    wire::{Datagram, MAX_SEGMENT_SIZE},
+};
+
+/// How often the threads of a proxy check whether to stop.
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Shortest read timeout, a zero timeout would block forever.
+const MIN_READ_TIMEOUT: Duration = Duration::from_micros(1);
+
+/// Transport over a UDP socket. Values go out as datagrams to `peer`,
+/// datagrams that decode as `I` come in. Without a `peer` the values go to
+/// whoever sent the last datagram, the way a server answers its client.
+///
+/// Datagrams that do not decode are dropped like frames with a broken
+/// header. A socket has no notion of a peer that is gone, so the transport
+/// never reports a disconnect.
+pub struct UdpTransport<O, I> {
+    socket: UdpSocket,
+    peer: Option<SocketAddr>,
+    is_peer_fixed: bool,
+    /// One byte more than the largest datagram, so that longer ones fail to decode
+    buffer: Box<[u8; MAX_SEGMENT_SIZE + 1]>,
+    values: PhantomData<fn(O) -> I>,
+}
+
+impl<O, I> UdpTransport<O, I> {
+    #[must_use]
+    pub fn new(socket: UdpSocket, peer: Option<SocketAddr>) -> Self {
+        Self {
+            socket,
+            peer,
+            is_peer_fixed: peer.is_some(),
+            buffer: Box::new([0; MAX_SEGMENT_SIZE + 1]),
+            values: PhantomData,
+        }
+    }
+}
+
+impl<O: Datagram, I: Datagram> Transport<O, I> for UdpTransport<O, I> {
+    fn send(&mut self, value: O) -> Result<(), SendError<O>> {
+        // Nobody to answer before the first datagram
+        if let Some(addr) = self.peer {
+            // A datagram the socket fails to send is just lost, the
+            // protocol retransmits it
+            let _ = self.socket.send_to(&value.to_bytes(), addr);
+        }
+        Ok(())
+    }
+
+    fn recv_timeout(&mut self, timeout: Duration) -> Result<I, RecvTimeoutError> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let timeout = deadline.saturating_duration_since(Instant::now());
+            if timeout.is_zero() {
+                return Err(RecvTimeoutError::Timeout);
+            }
+            let _ = self
+                .socket
+                .set_read_timeout(Some(timeout.max(MIN_READ_TIMEOUT)));
+            // Read timeouts and ICMP errors of earlier sends end up here
+            let Ok((size, from)) = self.socket.recv_from(&mut self.buffer[..]) else {
+                continue;
+            };
+            if !self.is_peer_fixed {
+                self.peer = Some(from);
+            }
+            if let Ok(value) = I::from_bytes(&self.buffer[..size]) {
+                return Ok(value);
+            }
+        }
+    }
+}
+
+/// Transport for a sender whose reader, or a proxy in front of it, is at `peer`.
+#[must_use]
+pub fn sender_transport(socket: UdpSocket, peer: SocketAddr) -> UdpTransport<Packet, Ack> {
+    UdpTransport::new(socket, Some(peer))
+}
+
+/// Transport for a reader that answers whichever sender reaches `socket`.
+#[must_use]
+pub fn reader_transport(socket: UdpSocket) -> UdpTransport<Ack, Packet> {
+    UdpTransport::new(socket, None)
+}
+
+/// Datagram in flight through a `Proxy`.
+enum Forward {
+    /// A packet on its way to the reader.
+    Packet(Box<Packet>),
+    /// An ACK on its way back to the sender.
+    Ack(Ack),
+}
+
+/// Impairments of a proxy and the datagrams in flight through it.
+struct ProxyState {
+    link: Link,
+    in_flight: BinaryHeap<Reverse<Scheduled<Forward>>>,
+    /// Sending order, keeps datagrams due at the same instant in order.
+    order: u64,
+    /// Where the ACKs go, whoever sent the last packet.
+    sender: Option<SocketAddr>,
+}
+
+impl ProxyState {
+    fn schedule(&mut self, at: Instant, value: Forward) {
+        self.in_flight.push(Reverse(Scheduled {
+            at,
+            order: self.order,
+            value,
+        }));
+        self.order += 1;
+    }
+}
+
+/// State shared by the threads of a proxy.
+struct ProxyShared {
+    state: Mutex<ProxyState>,
+    /// Signalled whenever a datagram is scheduled or the proxy stops.
+    changed: Condvar,
+    is_stopped: AtomicBool,
+}
+
+/// Puts every datagram from `socket` that decodes as `T` on the link with
+/// `transmit`, until the proxy stops.
+fn receive<T: Datagram + 'static>(
+    socket: UdpSocket,
+    shared: Arc<ProxyShared>,
+    transmit: fn(&mut ProxyState, T, SocketAddr, Instant),
+) -> JoinHandle<()> {
+    thread::spawn(move || {
+        // One byte more than the largest datagram, so that longer ones fail to decode
+        let mut buffer = [0; MAX_SEGMENT_SIZE + 1];
+        while !shared.is_stopped.load(Ordering::Relaxed) {
+            // Read timeouts and ICMP errors of earlier sends end up here
+            let Ok((size, from)) = socket.recv_from(&mut buffer) else {
+                continue;
+            };
+            let Ok(value) = T::from_bytes(&buffer[..size]) else {
+                continue;
+            };
+            transmit(
+                &mut shared.state.lock().unwrap(),
+                value,
+                from,
+                Instant::now(),
+            );
+            shared.changed.notify_all();
+        }
+    })
+}
+
+/// Sends every datagram in flight once it is due, packets from `upstream`
+/// to the reader at `peer` and ACKs from `downstream` to the sender.
+fn deliver(
+    shared: Arc<ProxyShared>,
+    downstream: UdpSocket,
+    upstream: UdpSocket,
+    peer: SocketAddr,
+) -> JoinHandle<()> {
+    thread::spawn(move || {
+        let mut state = shared.state.lock().unwrap();
+        while !shared.is_stopped.load(Ordering::Relaxed) {
+            let now = Instant::now();
+            let wake = match state.in_flight.peek() {
+                Some(Reverse(next)) if next.at <= now => {
+                    let Reverse(next) = state.in_flight.pop().expect("peeked");
+                    // A datagram the socket fails to send is just lost, the
+                    // protocol retransmits it
+                    match next.value {
+                        Forward::Packet(packet) => {
+                            let _ = upstream.send_to(&packet.to_bytes(), peer);
+                        }
+                        Forward::Ack(ack) => {
+                            if let Some(sender) = state.sender {
+                                let _ = downstream.send_to(&ack.to_bytes(), sender);
+                            }
+                        }
+                    }
+                    continue;
+                }
+                Some(Reverse(next)) => (next.at - now).min(POLL_INTERVAL),
+                None => POLL_INTERVAL,
+            };
+            state = shared.changed.wait_timeout(state, wake).unwrap().0;
+        }
+    })
+}
+
+/// UDP proxy that puts the impairments between a sender and a reader
+/// running as separate processes. Packets from the sender at its socket go
+/// to the reader at `peer` and the ACKs back, each through `impairments`
+/// the way `transport::simulated_link` impairs them.
+pub struct Proxy {
+    local_addr: SocketAddr,
+    shared: Arc<ProxyShared>,
+    handles: Vec<JoinHandle<()>>,
+}
+
+impl Proxy {
+    pub fn start(
+        socket: UdpSocket,
+        peer: SocketAddr,
+        impairments: &Impairments,
+    ) -> io::Result<Self> {
+        let local_addr = socket.local_addr()?;
+        let upstream = UdpSocket::bind((local_addr.ip(), 0))?;
+        socket.set_read_timeout(Some(POLL_INTERVAL))?;
+        upstream.set_read_timeout(Some(POLL_INTERVAL))?;
+        let mut rnd = simulation_rng(impairments.seed);
+        let state = ProxyState {
+            link: impairments.start(&mut rnd, Instant::now()),
+            in_flight: BinaryHeap::new(),
+            order: 0,
+            sender: None,
+        };
+        let shared = Arc::new(ProxyShared {
+            state: Mutex::new(state),
+            changed: Condvar::new(),
+            is_stopped: AtomicBool::new(false),
+        });
+        let send_packet = |state: &mut ProxyState, packet: Packet, from, now| {
+            state.sender = Some(from);
+            for (at, packet) in state.link.transmit_packet(packet, now) {
+                state.schedule(at, Forward::Packet(Box::new(packet)));
+            }
+        };
+        let send_ack = |state: &mut ProxyState, ack: Ack, _, now| {
+            for at in state.link.transmit_ack(ack, now) {
+                state.schedule(at, Forward::Ack(ack));
+            }
+        };
+        let handles = vec![
+            receive(socket.try_clone()?, Arc::clone(&shared), send_packet),
+            receive(upstream.try_clone()?, Arc::clone(&shared), send_ack),
+            deliver(Arc::clone(&shared), socket, upstream, peer),
+        ];
+        Ok(Self {
+            local_addr,
+            shared,
+            handles,
+        })
+    }
+
+    /// Address senders should send to.
+    #[must_use]
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// Blocks for as long as the proxy runs, that is until `stop`.
+    pub fn wait(self) {
+        for handle in self.handles {
+            handle.join().unwrap();
+        }
+    }
+
+    /// Stops forwarding and waits for the threads to finish.
+    pub fn stop(self) {
+        self.shared.is_stopped.store(true, Ordering::Relaxed);
+        self.shared.changed.notify_all();
+        self.wait();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arq::{ArqConfig, Protocol, transfer};
+
+    /// Runs a transfer between two loopback sockets, through a proxy with
+    /// `impairments` if any.
+    fn setup_udp(protocol: Protocol, message: &str, impairments: Option<&Impairments>) -> String {
+        let config = ArqConfig::new(4);
+        let reader_socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let reader_addr = reader_socket.local_addr().unwrap();
+        let proxy = impairments.map(|impairments| {
+            let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+            Proxy::start(socket, reader_addr, impairments).unwrap()
+        });
+        let peer = proxy.as_ref().map_or(reader_addr, Proxy::local_addr);
+        let sender_socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+
+        let (mut sender, mut reader) = protocol
+            .endpoints(
+                sender_transport(sender_socket, peer),
+                reader_transport(reader_socket),
+                &config,
+            )
+            .unwrap();
+        let message_read = transfer(sender.as_mut(), reader.as_mut(), message);
+        if let Some(proxy) = proxy {
+            proxy.stop();
+        }
+        message_read.unwrap()
+    }
+
+    #[test]
+    fn test_udp_transfer() {
+        let message_send = "U".repeat(3_000);
+        for protocol in Protocol::ALL {
+            let message_received = setup_udp(protocol, &message_send, None);
+            assert_eq!(message_send, message_received, "{}", protocol.name());
+        }
+    }
+
+    #[test]
+    fn test_udp_proxy() {
+        let message_send = "P".repeat(3_000);
+        let impairments = Impairments::loss(0.2)
+            .with_bit_error_rate(1e-4)
+            .with_seed(0);
+        for protocol in Protocol::ALL {
+            let message_received = setup_udp(protocol, &message_send, Some(&impairments));
+            assert_eq!(message_send, message_received, "{}", protocol.name());
+        }
+    }
+}
@@ -0,0 +1,460 @@
+use std::fmt;
+
+use crate::{
+    duplex::Segment,
+    packet::{Ack, AckKind, AckNumber, DATA_SIZE, MAX_SACK_BLOCKS, Packet, PacketState},
+};
+
+/// Bytes of a packet header on the wire.
+///
+/// ```text
+/// 0       4      5       6          10
+/// +-------+------+-------+----------+-------------------+
+/// | number| size | state | checksum | payload (size)    |
+/// +-------+------+-------+----------+-------------------+
+/// ```
+///
+/// Multi-byte fields are big-endian. `state` is 0 for SYN, 1 for `Begin`,
+/// 2 for `Ongoing`, 3 for `End` and 4 for FIN. `checksum` is the CRC-32
+/// of the other header fields and the payload.
+pub const PACKET_HEADER_SIZE: usize = size_of::<AckNumber>() + 2 + size_of::<u32>();
+
+/// Largest encoded packet.
+pub const MAX_PACKET_SIZE: usize = PACKET_HEADER_SIZE + DATA_SIZE;
+
+/// Bytes of an ACK on the wire: a big-endian number followed by the kind,
+/// 0 for a data ACK, 1 for a SYN-ACK, 2 for a FIN-ACK, 3 for a NAK and 4
+/// for a SACK.
+///
+/// A SACK goes on with a count byte and that many blocks:
+///
+/// ```text
+/// 0       4      5       6         10        14
+/// +-------+------+-------+---------+---------+-----
+/// | number| kind | count | start 1 | end 1   | ...
+/// +-------+------+-------+---------+---------+-----
+/// ```
+pub const ACK_SIZE: usize = size_of::<AckNumber>() + 1;
+
+/// Bytes of a SACK block, its start and its exclusive end.
+pub const SACK_BLOCK_SIZE: usize = 2 * size_of::<AckNumber>();
+
+/// Largest encoded ACK, a SACK with every block taken.
+pub const MAX_ACK_SIZE: usize = ACK_SIZE + 1 + MAX_SACK_BLOCKS * SACK_BLOCK_SIZE;
+
+/// Bytes of a segment header: a flag byte with bit 0 set when an ACK
+/// follows and bit 1 when a packet does. The ACK comes first, the packet
+/// takes the rest of the datagram.
+pub const SEGMENT_HEADER_SIZE: usize = 1;
+
+const SEGMENT_ACK: u8 = 1;
+const SEGMENT_PACKET: u8 = 2;
+
+/// Largest encoded segment, an ACK riding on the largest packet. No other
+/// datagram is longer.
+pub const MAX_SEGMENT_SIZE: usize = SEGMENT_HEADER_SIZE + MAX_ACK_SIZE + MAX_PACKET_SIZE;
+
+/// Reason a datagram is not a packet or an ACK.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecodeError {
+    /// The datagram is shorter or longer than its header says.
+    Length {
+        expected: usize,
+        actual: usize,
+    },
+    UnknownState(u8),
+    UnknownAckKind(u8),
+    /// A SACK that lists more than `MAX_SACK_BLOCKS` blocks.
+    TooManySackBlocks(u8),
+    /// Flags of a segment that carries nothing or something unknown.
+    UnknownSegmentFlags(u8),
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Length { expected, actual } => {
+                write!(f, "expected {expected} bytes, got {actual}")
+            }
+            Self::UnknownState(code) => write!(f, "unknown packet state {code}"),
+            Self::UnknownAckKind(code) => write!(f, "unknown ACK kind {code}"),
+            Self::TooManySackBlocks(count) => write!(f, "{count} SACK blocks is too many"),
+            Self::UnknownSegmentFlags(flags) => write!(f, "unknown segment flags {flags:#04b}"),
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+fn check_length(bytes: &[u8], expected: usize) -> Result<(), DecodeError> {
+    if bytes.len() != expected {
+        return Err(DecodeError::Length {
+            expected,
+            actual: bytes.len(),
+        });
+    }
+    Ok(())
+}
+
+fn read_u32(bytes: &[u8]) -> u32 {
+    u32::from_be_bytes(bytes[..4].try_into().expect("four bytes"))
+}
+
+impl Packet {
+    /// Encodes the packet as `PACKET_HEADER_SIZE` header bytes followed by
+    /// the payload. Padding past the payload is not sent.
+    #[must_use]
+    pub fn encode(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(PACKET_HEADER_SIZE + self.size as usize);
+        bytes.extend_from_slice(&self.number.to_be_bytes());
+        bytes.extend_from_slice(&[self.size, self.state.code()]);
+        bytes.extend_from_slice(&self.checksum.to_be_bytes());
+        bytes.extend_from_slice(self.payload());
+        bytes
+    }
+
+    /// Parses a packet encoded by `encode`. The checksum is carried over
+    /// as is, a corrupted packet still decodes but is not `is_valid`.
+    pub fn decode(bytes: &[u8]) -> Result<Self, DecodeError> {
+        if bytes.len() < PACKET_HEADER_SIZE {
+            return Err(DecodeError::Length {
+                expected: PACKET_HEADER_SIZE,
+                actual: bytes.len(),
+            });
+        }
+        let size = bytes[4];
+        check_length(bytes, PACKET_HEADER_SIZE + size as usize)?;
+        let state = PacketState::from_code(bytes[5]).ok_or(DecodeError::UnknownState(bytes[5]))?;
+        let mut data = [0; DATA_SIZE];
+        data[..size as usize].copy_from_slice(&bytes[PACKET_HEADER_SIZE..]);
+        Ok(Self {
+            number: read_u32(bytes),
+            data,
+            size,
+            state,
+            checksum: read_u32(&bytes[6..]),
+        })
+    }
+}
+
+impl Ack {
+    #[must_use]
+    pub fn encode(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(MAX_ACK_SIZE);
+        bytes.extend_from_slice(&self.number.to_be_bytes());
+        bytes.push(self.kind.code());
+        if self.kind == AckKind::Sack {
+            bytes.push(self.sack.len() as u8);
+            for (start, end) in self.sack.iter() {
+                bytes.extend_from_slice(&start.to_be_bytes());
+                bytes.extend_from_slice(&end.to_be_bytes());
+            }
+        }
+        bytes
+    }
+
+    /// Parses an ACK encoded by `encode`.
+    pub fn decode(bytes: &[u8]) -> Result<Self, DecodeError> {
+        let (ack, len) = Self::decode_prefix(bytes)?;
+        check_length(bytes, len)?;
+        Ok(ack)
+    }
+
+    /// Parses the ACK at the start of `bytes` and tells how long it is.
+    fn decode_prefix(bytes: &[u8]) -> Result<(Self, usize), DecodeError> {
+        let mut len = ACK_SIZE;
+        if bytes.len() < len {
+            return Err(DecodeError::Length {
+                expected: len,
+                actual: bytes.len(),
+            });
+        }
+        let kind = AckKind::from_code(bytes[4]).ok_or(DecodeError::UnknownAckKind(bytes[4]))?;
+        let mut ack = Self::new(read_u32(bytes), kind);
+        if kind == AckKind::Sack {
+            let Some(&count) = bytes.get(len) else {
+                return Err(DecodeError::Length {
+                    expected: len + 1,
+                    actual: bytes.len(),
+                });
+            };
+            if count as usize > MAX_SACK_BLOCKS {
+                return Err(DecodeError::TooManySackBlocks(count));
+            }
+            len += 1 + count as usize * SACK_BLOCK_SIZE;
+            if bytes.len() < len {
+                return Err(DecodeError::Length {
+                    expected: len,
+                    actual: bytes.len(),
+                });
+            }
+            for block in bytes[ACK_SIZE + 1..len].chunks_exact(SACK_BLOCK_SIZE) {
+                ack.sack.push(read_u32(block), read_u32(&block[4..]));
+            }
+        }
+        Ok((ack, len))
+    }
+}
+
+impl Segment {
+    #[must_use]
+    pub fn encode(&self) -> Vec<u8> {
+        let mut flags = 0;
+        if self.ack.is_some() {
+            flags |= SEGMENT_ACK;
+        }
+        if self.packet.is_some() {
+            flags |= SEGMENT_PACKET;
+        }
+        let mut bytes = vec![flags];
+        if let Some(ack) = &self.ack {
+            bytes.extend(ack.encode());
+        }
+        if let Some(packet) = &self.packet {
+            bytes.extend_from_slice(&packet.encode());
+        }
+        bytes
+    }
+
+    /// Parses a segment encoded by `encode`.
+    pub fn decode(bytes: &[u8]) -> Result<Self, DecodeError> {
+        let Some((&flags, mut rest)) = bytes.split_first() else {
+            return Err(DecodeError::Length {
+                expected: SEGMENT_HEADER_SIZE,
+                actual: 0,
+            });
+        };
+        if flags == 0 || flags & !(SEGMENT_ACK | SEGMENT_PACKET) != 0 {
+            return Err(DecodeError::UnknownSegmentFlags(flags));
+        }
+        let mut ack = None;
+        if flags & SEGMENT_ACK != 0 {
+            let (decoded, len) = Ack::decode_prefix(rest).map_err(|e| match e {
+                DecodeError::Length { expected, actual } => DecodeError::Length {
+                    expected: SEGMENT_HEADER_SIZE + expected,
+                    actual: SEGMENT_HEADER_SIZE + actual,
+                },
+                e => e,
+            })?;
+            ack = Some(decoded);
+            rest = &rest[len..];
+        }
+        let packet = if flags & SEGMENT_PACKET != 0 {
+            Some(Packet::decode(rest)?)
+        } else {
+            check_length(bytes, bytes.len() - rest.len())?;
+            None
+        };
+        Ok(Self { packet, ack })
+    }
+}
+
+/// Value that travels as a single datagram.
+pub trait Datagram: Sized {
+    fn to_bytes(&self) -> Vec<u8>;
+
+    fn from_bytes(bytes: &[u8]) -> Result<Self, DecodeError>;
+}
+
+impl Datagram for Packet {
+    fn to_bytes(&self) -> Vec<u8> {
+        self.encode()
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Result<Self, DecodeError> {
+        Self::decode(bytes)
+    }
+}
+
+impl Datagram for Ack {
+    fn to_bytes(&self) -> Vec<u8> {
+        self.encode()
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Result<Self, DecodeError> {
+        Self::decode(bytes)
+    }
+}
+
+impl Datagram for Segment {
+    fn to_bytes(&self) -> Vec<u8> {
+        self.encode()
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Result<Self, DecodeError> {
+        Self::decode(bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{Rng, SeedableRng, rngs::StdRng};
+
+    use super::*;
+    use crate::packet::{Corruptible, Frame, SackBlocks};
+
+    #[test]
+    fn test_packet_round_trip() {
+        let mut packet = Packet::control(PacketState::Fin, 0);
+        packet.set_number(0xDEAD_BEEF);
+        let bytes = packet.encode();
+        assert_eq!(
+            bytes,
+            [0xDE, 0xAD, 0xBE, 0xEF, 0, 4, 0x49, 0x3F, 0xED, 0xF6]
+        );
+        assert_eq!(Packet::decode(&bytes), Ok(packet));
+
+        let data = Packet::new(7, [b'x'; DATA_SIZE], 3, PacketState::End);
+        let bytes = data.encode();
+        assert_eq!(bytes.len(), PACKET_HEADER_SIZE + 3);
+        assert_eq!(8 * bytes.len(), data.bit_len());
+        let decoded = Packet::decode(&bytes).unwrap();
+        assert!(decoded.is_valid());
+        assert_eq!(decoded.payload(), b"xxx");
+        assert_eq!(decoded.encode(), bytes);
+    }
+
+    #[test]
+    fn test_packet_decode_errors() {
+        let bytes = Packet::new(1, [0; DATA_SIZE], 2, PacketState::Begin).encode();
+        assert_eq!(
+            Packet::decode(&bytes[..3]),
+            Err(DecodeError::Length {
+                expected: PACKET_HEADER_SIZE,
+                actual: 3
+            })
+        );
+        assert_eq!(
+            Packet::decode(&bytes[..bytes.len() - 1]),
+            Err(DecodeError::Length {
+                expected: bytes.len(),
+                actual: bytes.len() - 1
+            })
+        );
+        let mut unknown = bytes.clone();
+        unknown[5] = 9;
+        assert_eq!(Packet::decode(&unknown), Err(DecodeError::UnknownState(9)));
+        assert_eq!(
+            DecodeError::UnknownState(9).to_string(),
+            "unknown packet state 9"
+        );
+    }
+
+    #[test]
+    fn test_bit_errors_match_the_encoding() {
+        let packet = Packet::new(12_345, [0xA5; DATA_SIZE], 4, PacketState::Ongoing);
+        let bytes = packet.encode();
+        for index in 0..packet.bit_len() {
+            let mut corrupted = packet.clone();
+            let mut flipped = bytes.clone();
+            flipped[index / 8] ^= 1 << (7 - index % 8);
+            match Packet::decode(&flipped) {
+                Ok(decoded) => {
+                    assert!(corrupted.flip_bit(index), "bit {index}");
+                    assert_eq!(decoded, corrupted, "bit {index}");
+                }
+                Err(_) => assert!(!corrupted.flip_bit(index), "bit {index}"),
+            }
+        }
+    }
+
+    #[test]
+    fn test_ack_round_trip() {
+        for ack in [
+            Ack::data(3),
+            Ack::nak(9),
+            Ack::new(AckNumber::MAX, AckKind::FinAck),
+            Ack::sack(7, SackBlocks::default()),
+            full_sack(),
+        ] {
+            assert_eq!(8 * ack.encode().len(), ack.bit_len());
+            assert_eq!(Ack::decode(&ack.encode()), Ok(ack));
+        }
+        assert_eq!(full_sack().encode().len(), MAX_ACK_SIZE);
+        assert_eq!(
+            Ack::decode(&[0, 0, 0, 1, 5]),
+            Err(DecodeError::UnknownAckKind(5))
+        );
+        assert!(Ack::decode(&[0; ACK_SIZE + 1]).is_err());
+        assert_eq!(
+            Ack::decode(&[0, 0, 0, 1, 4, 5]),
+            Err(DecodeError::TooManySackBlocks(5))
+        );
+        let bytes = full_sack().encode();
+        assert_eq!(
+            Ack::decode(&bytes[..bytes.len() - 1]),
+            Err(DecodeError::Length {
+                expected: bytes.len(),
+                actual: bytes.len() - 1
+            })
+        );
+    }
+
+    fn full_sack() -> Ack {
+        let mut sack = SackBlocks::default();
+        for start in 0..MAX_SACK_BLOCKS as AckNumber {
+            assert!(sack.push(10 * start + 2, 10 * start + 5));
+        }
+        assert!(!sack.push(50, 51));
+        Ack::sack(1, sack)
+    }
+
+    #[test]
+    fn test_segment_round_trip() {
+        let packet = Packet::new(5, [b'y'; DATA_SIZE], DATA_SIZE as u8, PacketState::Begin);
+        for segment in [
+            Segment {
+                packet: Some(packet.clone()),
+                ack: Some(Ack::data(4)),
+            },
+            Segment {
+                packet: Some(Packet::control(PacketState::Syn, 1)),
+                ack: None,
+            },
+            Segment {
+                packet: None,
+                ack: Some(Ack::nak(2)),
+            },
+            Segment {
+                packet: Some(packet.clone()),
+                ack: Some(full_sack()),
+            },
+        ] {
+            assert_eq!(Segment::decode(&segment.encode()), Ok(segment));
+        }
+        let bytes = Segment {
+            packet: Some(packet),
+            ack: Some(full_sack()),
+        }
+        .encode();
+        assert_eq!(bytes.len(), MAX_SEGMENT_SIZE);
+        assert_eq!(
+            Segment::decode(&[0]),
+            Err(DecodeError::UnknownSegmentFlags(0))
+        );
+        assert_eq!(
+            Segment::decode(&bytes[..3]),
+            Err(DecodeError::Length {
+                expected: SEGMENT_HEADER_SIZE + ACK_SIZE,
+                actual: 3
+            })
+        );
+        // An ACK alone must end the datagram
+        assert!(Segment::decode(&[SEGMENT_ACK, 0, 0, 0, 1, 0, 0]).is_err());
+    }
+
+    #[test]
+    fn test_decode_random_bytes() {
+        let mut rnd = StdRng::seed_from_u64(16);
+        for _ in 0..10_000 {
+            let mut bytes = vec![0; rnd.random_range(0..MAX_PACKET_SIZE + 2)];
+            rnd.fill(&mut bytes[..]);
+            if let Ok(packet) = Packet::decode(&bytes) {
+                assert_eq!(packet.encode(), bytes);
+            }
+            if let Ok(ack) = Ack::decode(&bytes) {
+                assert_eq!(ack.encode(), bytes);
+            }
+        }
+    }
+}